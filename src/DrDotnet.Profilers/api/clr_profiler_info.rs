@@ -4,17 +4,19 @@ use crate::{
         int, mdFieldDef, mdMethodDef, mdTypeDef, AppDomainID, AssemblyID, ClassID, ContextID, CorElementType, CorOpenFlags, CorProfilerFunctionEnum,
         CorProfilerInfo as FFICorProfilerInfo, CorProfilerModuleEnum, CorProfilerThreadEnum, FunctionEnter, FunctionEnter2, FunctionEnter3,
         FunctionEnter3WithInfo, FunctionID, FunctionIDMapper, FunctionIDMapper2, FunctionLeave, FunctionLeave2, FunctionLeave3, FunctionLeave3WithInfo,
-        FunctionTailcall, FunctionTailcall2, FunctionTailcall3, FunctionTailcall3WithInfo, IMetaDataImport2, MethodMalloc, ModuleID, ObjectID,
-        ObjectReferenceCallback, ReJITID, StackSnapshotCallback, ThreadID, BOOL, BYTE, COR_DEBUG_IL_TO_NATIVE_MAP, COR_FIELD_OFFSET, COR_IL_MAP,
-        COR_PRF_CODE_INFO, COR_PRF_ELT_INFO, COR_PRF_EX_CLAUSE_INFO, COR_PRF_FRAME_INFO, COR_PRF_GC_GENERATION_RANGE, COR_PRF_HIGH_MONITOR,
-        COR_PRF_MODULE_FLAGS, COR_PRF_MONITOR, COR_PRF_REJIT_FLAGS, COR_PRF_SNAPSHOT_INFO, COR_PRF_STATIC_TYPE, DWORD, GUID, HANDLE, HRESULT, LPCBYTE,
+        FunctionTailcall, FunctionTailcall2, FunctionTailcall3, FunctionTailcall3WithInfo, ICorProfilerInfo12, IMetaDataAssemblyImport, IMetaDataImport2,
+        MetaDataAssemblyImport, MethodMalloc, ModuleID, ObjectID, ObjectReferenceCallback, ReJITID, StackSnapshotCallback, ThreadID, ASSEMBLYMETADATA, BOOL,
+        BYTE, COR_DEBUG_IL_TO_NATIVE_MAP, COR_FIELD_OFFSET, COR_IL_MAP, COR_PRF_CODE_INFO, COR_PRF_ELT_INFO, COR_PRF_EVENTPIPE_PROVIDER_CONFIG,
+        COR_PRF_EX_CLAUSE_INFO, COR_PRF_FRAME_INFO, COR_PRF_GC_GENERATION_RANGE, COR_PRF_HIGH_MONITOR, COR_PRF_MODULE_FLAGS, COR_PRF_MONITOR,
+        COR_PRF_REJIT_FLAGS, COR_PRF_SNAPSHOT_INFO, COR_PRF_STATIC_TYPE, DWORD, EVENTPIPE_PROVIDER, EVENTPIPE_SESSION, GUID, HANDLE, HRESULT, LPCBYTE,
         UINT_PTR, ULONG, ULONG32, WCHAR,
     },
     utils::NameResolver,
-    AppDomainInfo, ArrayClassInfo, ArrayObjectInfo, AssemblyInfo, ClassInfo, ClassInfo2, ClassLayout, CorProfilerInfo, CorProfilerInfo10, CorProfilerInfo2,
-    CorProfilerInfo3, CorProfilerInfo4, CorProfilerInfo5, CorProfilerInfo6, CorProfilerInfo7, CorProfilerInfo8, CorProfilerInfo9, DynamicFunctionInfo,
-    EnumNgenModuleMethodsInliningThisMethod, EventMask2, FunctionAndRejit, FunctionEnter3Info, FunctionInfo, FunctionInfo2, FunctionLeave3Info,
-    FunctionTokenAndMetadata, IlFunctionBody, MetadataImport, ModuleInfo, ModuleInfo2, RuntimeInfo, StringLayout,
+    AppDomainInfo, ArrayClassInfo, ArrayObjectInfo, AssemblyInfo, ClassInfo, ClassInfo2, ClassLayout, CorProfilerInfo, CorProfilerInfo10, CorProfilerInfo11,
+    CorProfilerInfo12, CorProfilerInfo2, CorProfilerInfo3, CorProfilerInfo4, CorProfilerInfo5, CorProfilerInfo6, CorProfilerInfo7, CorProfilerInfo8,
+    CorProfilerInfo9, DynamicFunctionInfo, EnumNgenModuleMethodsInliningThisMethod, EventMask2, EventPipeProviderConfig, FunctionAndRejit, FunctionEnter3Info,
+    FunctionInfo, FunctionInfo2, FunctionLeave3Info, FunctionTokenAndMetadata, IlFunctionBody, MetadataImport, ModuleInfo, ModuleInfo2, RuntimeInfo,
+    StringLayout,
 };
 use itertools::Itertools;
 use std::slice;
@@ -61,6 +63,22 @@ impl ClrProfilerInfo {
         unsafe { self.info.as_ref().unwrap() }
     }
 
+    // Newer interfaces are missing from the vtable of older runtimes, and must not be called there.
+    // For instance, EventPipe methods (ICorProfilerInfo12) are only available from .NET 5.
+    pub fn implements(&self, iid: GUID) -> bool {
+        let info = self.info as *mut FFICorProfilerInfo;
+        let mut interface = ptr::null_mut();
+        unsafe {
+            match (*info).QueryInterface(&iid, &mut interface) {
+                HRESULT::S_OK => {
+                    (*info).Release();
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
     pub fn get_attached_status(&self) -> AttachedStatus {
         match self.attached_status.load(Ordering::SeqCst) {
             x if x == AttachedStatus::Attaching as usize => AttachedStatus::Attaching,
@@ -102,6 +120,59 @@ impl ClrProfilerInfo {
         // };
     }
 
+    // Return the version of the assembly a module belongs to, as found in its manifest (eg. 1.2.3.4)
+    pub fn get_assembly_version(&self, module_id: ModuleID) -> Result<String, HRESULT> {
+        let mut assembly_import = MaybeUninit::<*mut MetaDataAssemblyImport>::uninit();
+        let riid = IMetaDataAssemblyImport::IID;
+        let hr = unsafe {
+            self.info()
+                .GetModuleMetaData(module_id, CorOpenFlags::ofRead.bits(), &riid, assembly_import.as_mut_ptr() as *mut *mut _)
+        };
+        if hr != HRESULT::S_OK {
+            return Err(hr);
+        }
+        let assembly_import = unsafe { assembly_import.assume_init().as_mut().ok_or(HRESULT::E_FAIL)? };
+
+        let version = Self::read_assembly_version(assembly_import);
+
+        // GetModuleMetaData hands out a new reference to the metadata interface, that we are responsible for releasing
+        unsafe { assembly_import.Release() };
+
+        version
+    }
+
+    fn read_assembly_version(assembly_import: &MetaDataAssemblyImport) -> Result<String, HRESULT> {
+        let mut assembly = MaybeUninit::uninit();
+        let hr = unsafe { assembly_import.GetAssemblyFromScope(assembly.as_mut_ptr()) };
+        if hr != HRESULT::S_OK {
+            return Err(hr);
+        }
+
+        // Zeroed buffer lengths tell the runtime we are only interested in the version numbers
+        let mut metadata: ASSEMBLYMETADATA = unsafe { std::mem::zeroed() };
+        let hr = unsafe {
+            assembly_import.GetAssemblyProps(
+                assembly.assume_init(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                &mut metadata,
+                ptr::null_mut(),
+            )
+        };
+
+        match hr {
+            HRESULT::S_OK => Ok(format!(
+                "{}.{}.{}.{}",
+                metadata.usMajorVersion, metadata.usMinorVersion, metadata.usBuildNumber, metadata.usRevisionNumber
+            )),
+            _ => Err(hr),
+        }
+    }
+
     // Return the name of type (with its namespace)
     fn get_type_name(&self, module_id: ModuleID, td: mdTypeDef) -> String {
         match self.get_module_metadata(module_id, CorOpenFlags::ofRead) {
@@ -1892,3 +1963,142 @@ impl CorProfilerInfo10 for ClrProfilerInfo {
         }
     }
 }
+
+impl CorProfilerInfo11 for ClrProfilerInfo {
+    fn get_environment_variable(&self, name: &str) -> Result<String, HRESULT> {
+        let name = U16CString::from_str(name).unwrap();
+        let mut value_buffer_length = MaybeUninit::uninit();
+        let hr = unsafe {
+            self.info()
+                .GetEnvironmentVariable(name.as_ptr(), 0, value_buffer_length.as_mut_ptr(), ptr::null_mut())
+        };
+        if hr != HRESULT::S_OK {
+            return Err(hr);
+        }
+
+        let value_buffer_length = unsafe { value_buffer_length.assume_init() };
+        let mut value_buffer = vec![0 as WCHAR; value_buffer_length as usize];
+        let mut value_length = MaybeUninit::uninit();
+        let hr = unsafe {
+            self.info()
+                .GetEnvironmentVariable(name.as_ptr(), value_buffer_length, value_length.as_mut_ptr(), value_buffer.as_mut_ptr())
+        };
+        match hr {
+            HRESULT::S_OK => {
+                let value = U16CString::from_vec_with_nul(value_buffer).unwrap().to_string_lossy();
+                Ok(value)
+            }
+            _ => Err(hr),
+        }
+    }
+    fn set_environment_variable(&self, name: &str, value: Option<&str>) -> Result<(), HRESULT> {
+        let name = U16CString::from_str(name).unwrap();
+        let value = value.map(|value| U16CString::from_str(value).unwrap());
+        let value_ptr = value.as_ref().map_or(ptr::null(), |value| value.as_ptr());
+        let hr = unsafe { self.info().SetEnvironmentVariable(name.as_ptr(), value_ptr) };
+        match hr {
+            HRESULT::S_OK => Ok(()),
+            _ => Err(hr),
+        }
+    }
+}
+
+impl CorProfilerInfo12 for ClrProfilerInfo {
+    fn event_pipe_start_session(&self, provider_configs: &[EventPipeProviderConfig], request_rundown: bool) -> Result<EVENTPIPE_SESSION, HRESULT> {
+        if !self.implements(ICorProfilerInfo12::IID) {
+            return Err(HRESULT::E_NOINTERFACE);
+        }
+
+        // The wide strings must outlive the call, as the configs only point to them
+        let names: Vec<(U16CString, Option<U16CString>)> = provider_configs
+            .iter()
+            .map(|config| {
+                let provider_name = U16CString::from_str(&config.provider_name).unwrap();
+                let filter_data = config.filter_data.as_ref().map(|filter_data| U16CString::from_str(filter_data).unwrap());
+                (provider_name, filter_data)
+            })
+            .collect();
+        let configs: Vec<COR_PRF_EVENTPIPE_PROVIDER_CONFIG> = provider_configs
+            .iter()
+            .zip(names.iter())
+            .map(|(config, (provider_name, filter_data))| COR_PRF_EVENTPIPE_PROVIDER_CONFIG {
+                providerName: provider_name.as_ptr(),
+                keywords: config.keywords,
+                loggingLevel: config.logging_level,
+                filterData: filter_data.as_ref().map_or(ptr::null(), |filter_data| filter_data.as_ptr()),
+            })
+            .collect();
+
+        let mut session = MaybeUninit::uninit();
+        let hr = unsafe {
+            self.info()
+                .EventPipeStartSession(configs.len() as u32, configs.as_ptr(), request_rundown as BOOL, session.as_mut_ptr())
+        };
+        match hr {
+            HRESULT::S_OK => {
+                let session = unsafe { session.assume_init() };
+                Ok(session)
+            }
+            _ => Err(hr),
+        }
+    }
+    fn event_pipe_add_provider_to_session(&self, session: EVENTPIPE_SESSION, provider_config: &EventPipeProviderConfig) -> Result<(), HRESULT> {
+        let provider_name = U16CString::from_str(&provider_config.provider_name).unwrap();
+        let filter_data = provider_config
+            .filter_data
+            .as_ref()
+            .map(|filter_data| U16CString::from_str(filter_data).unwrap());
+        let config = COR_PRF_EVENTPIPE_PROVIDER_CONFIG {
+            providerName: provider_name.as_ptr(),
+            keywords: provider_config.keywords,
+            loggingLevel: provider_config.logging_level,
+            filterData: filter_data.as_ref().map_or(ptr::null(), |filter_data| filter_data.as_ptr()),
+        };
+        let hr = unsafe { self.info().EventPipeAddProviderToSession(session, config) };
+        match hr {
+            HRESULT::S_OK => Ok(()),
+            _ => Err(hr),
+        }
+    }
+    fn event_pipe_stop_session(&self, session: EVENTPIPE_SESSION) -> Result<(), HRESULT> {
+        let hr = unsafe { self.info().EventPipeStopSession(session) };
+        match hr {
+            HRESULT::S_OK => Ok(()),
+            _ => Err(hr),
+        }
+    }
+    fn event_pipe_create_provider(&self, provider_name: &str) -> Result<EVENTPIPE_PROVIDER, HRESULT> {
+        let provider_name = U16CString::from_str(provider_name).unwrap();
+        let mut provider = MaybeUninit::uninit();
+        let hr = unsafe { self.info().EventPipeCreateProvider(provider_name.as_ptr(), provider.as_mut_ptr()) };
+        match hr {
+            HRESULT::S_OK => {
+                let provider = unsafe { provider.assume_init() };
+                Ok(provider)
+            }
+            _ => Err(hr),
+        }
+    }
+    fn event_pipe_get_provider_info(&self, provider: EVENTPIPE_PROVIDER) -> Result<String, HRESULT> {
+        let mut name_buffer_length = MaybeUninit::uninit();
+        unsafe {
+            self.info()
+                .EventPipeGetProviderInfo(provider, 0, name_buffer_length.as_mut_ptr(), ptr::null_mut())
+        };
+
+        let name_buffer_length = unsafe { name_buffer_length.assume_init() };
+        let mut name_buffer = vec![0 as WCHAR; name_buffer_length as usize];
+        let mut name_length = MaybeUninit::uninit();
+        let hr = unsafe {
+            self.info()
+                .EventPipeGetProviderInfo(provider, name_buffer_length, name_length.as_mut_ptr(), name_buffer.as_mut_ptr())
+        };
+        match hr {
+            HRESULT::S_OK => {
+                let name = U16CString::from_vec_with_nul(name_buffer).unwrap().to_string_lossy();
+                Ok(name)
+            }
+            _ => Err(hr),
+        }
+    }
+}
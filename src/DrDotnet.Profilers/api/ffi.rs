@@ -25,6 +25,8 @@ pub type UINT = c_uint;
 pub type ULONG32 = c_uint;
 pub type ULONG = c_ulong;
 pub type DWORD = c_ulong;
+pub type UINT32 = u32;
+pub type UINT64 = u64;
 pub type BYTE = c_uchar;
 pub type COR_SIGNATURE = BYTE;
 
@@ -85,6 +87,7 @@ pub type IID = GUID;
 pub type REFGUID = *const GUID;
 pub type REFCLSID = *const IID;
 pub type REFIID = *const IID;
+pub type LPCGUID = *const GUID;
 
 // profiler-specific pointers
 pub type AppDomainID = UINT_PTR;
@@ -104,6 +107,9 @@ pub type ReJITID = UINT_PTR;
 pub type ThreadID = UINT_PTR;
 pub type ClrInstanceID = USHORT;
 pub type HCORENUM = *const c_void;
+pub type EVENTPIPE_SESSION = UINT64;
+pub type EVENTPIPE_PROVIDER = UINT_PTR;
+pub type EVENTPIPE_EVENT = UINT_PTR;
 
 #[repr(C)]
 pub union FunctionIDOrClientID {
@@ -210,14 +216,6 @@ pub enum COR_PRF_GC_ROOT_KIND {
     COR_PRF_GC_ROOT_OTHER = 0,
 }
 #[repr(C)]
-#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
-pub enum COR_PRF_GC_ROOT_FLAGS {
-    COR_PRF_GC_ROOT_PINNING = 1,
-    COR_PRF_GC_ROOT_WEAKREF = 2,
-    COR_PRF_GC_ROOT_INTERIOR = 4,
-    COR_PRF_GC_ROOT_REFCOUNTED = 8,
-}
-#[repr(C)]
 #[derive(Debug, PartialEq)]
 pub struct COR_IL_MAP {
     pub oldOffset: ULONG32,
@@ -259,6 +257,7 @@ pub enum COR_PRF_GC_GENERATION {
     COR_PRF_GC_GEN_1 = 1,
     COR_PRF_GC_GEN_2 = 2,
     COR_PRF_GC_LARGE_OBJECT_HEAP = 3,
+    COR_PRF_GC_PINNED_OBJECT_HEAP = 4,
 }
 #[repr(C)]
 #[derive(Debug, PartialEq)]
@@ -371,10 +370,10 @@ pub struct OSINFO {
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub struct ASSEMBLYMETADATA {
-    usMajorVersion: USHORT,   // Major Version.
-    usMinorVersion: USHORT,   // Minor Version.
-    usBuildNumber: USHORT,    // Build Number.
-    usRevisionNumber: USHORT, // Revision Number.
+    pub usMajorVersion: USHORT,   // Major Version.
+    pub usMinorVersion: USHORT,   // Minor Version.
+    pub usBuildNumber: USHORT,    // Build Number.
+    pub usRevisionNumber: USHORT, // Revision Number.
     szLocale: *mut WCHAR,     // Locale.
     cbLocale: ULONG,          // [IN/OUT] Size of the buffer in wide chars/Actual size.
     rProcessor: *const DWORD, // Processor ID array.
@@ -396,8 +395,30 @@ pub struct COR_PRF_ASSEMBLY_REFERENCE_INFO {
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub struct COR_PRF_FUNCTION {
-    functionId: FunctionID,
-    reJitId: ReJITID,
+    pub functionId: FunctionID,
+    pub reJitId: ReJITID,
+}
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct COR_PRF_EVENTPIPE_PROVIDER_CONFIG {
+    pub providerName: LPCWSTR,
+    pub keywords: UINT64,
+    pub loggingLevel: UINT32,
+    pub filterData: LPCWSTR,
+}
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct COR_PRF_EVENTPIPE_PARAM_DESC {
+    pub type_: UINT32,
+    pub elementType: UINT32,
+    pub name: LPCWSTR,
+}
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct COR_PRF_EVENT_DATA {
+    pub ptr: UINT64,
+    pub size: UINT32,
+    pub reserved: UINT32,
 }
 bitflags! {
     pub struct COR_PRF_MONITOR: DWORD {
@@ -479,11 +500,15 @@ bitflags! {
         const COR_PRF_HIGH_MONITOR_GC_MOVED_OBJECTS = 0x20;
         const COR_PRF_HIGH_REQUIRE_PROFILE_IMAGE = 0;
         const COR_PRF_HIGH_MONITOR_LARGEOBJECT_ALLOCATED = 0x40;
+        const COR_PRF_HIGH_MONITOR_EVENT_PIPE = 0x80;
+        const COR_PRF_HIGH_MONITOR_PINNEDOBJECT_ALLOCATED = 0x100;
         const COR_PRF_HIGH_ALLOWABLE_AFTER_ATTACH = Self::COR_PRF_HIGH_IN_MEMORY_SYMBOLS_UPDATED.bits
             | Self::COR_PRF_HIGH_MONITOR_DYNAMIC_FUNCTION_UNLOADS.bits
             | Self::COR_PRF_HIGH_BASIC_GC.bits
             | Self::COR_PRF_HIGH_MONITOR_GC_MOVED_OBJECTS.bits
-            | Self::COR_PRF_HIGH_MONITOR_LARGEOBJECT_ALLOCATED.bits;
+            | Self::COR_PRF_HIGH_MONITOR_LARGEOBJECT_ALLOCATED.bits
+            | Self::COR_PRF_HIGH_MONITOR_EVENT_PIPE.bits
+            | Self::COR_PRF_HIGH_MONITOR_PINNEDOBJECT_ALLOCATED.bits;
         const COR_PRF_HIGH_MONITOR_IMMUTABLE = COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_DISABLE_TIERED_COMPILATION.bits;
     }
 }
@@ -536,6 +561,16 @@ bitflags! {
         const COR_PRF_REJIT_INLINING_CALLBACKS = 0x2;
     }
 }
+bitflags! {
+    // The runtime passes a combination of these flags, or none at all (eg. for strong handles)
+    #[repr(transparent)]
+    pub struct COR_PRF_GC_ROOT_FLAGS: DWORD {
+        const COR_PRF_GC_ROOT_PINNING = 0x1;
+        const COR_PRF_GC_ROOT_WEAKREF = 0x2;
+        const COR_PRF_GC_ROOT_INTERIOR = 0x4;
+        const COR_PRF_GC_ROOT_REFCOUNTED = 0x8;
+    }
+}
 bitflags! {
     pub struct COR_PRF_FINALIZER_FLAGS: DWORD {
         const COR_PRF_FINALIZER_CRITICAL = 0x1;
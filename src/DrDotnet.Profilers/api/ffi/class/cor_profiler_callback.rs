@@ -22,6 +22,7 @@ pub struct CorProfilerCallbackVtbl<T: Profiler> {
     pub ICorProfilerCallback7: ICorProfilerCallback7<CorProfilerCallback<T>>,
     pub ICorProfilerCallback8: ICorProfilerCallback8<CorProfilerCallback<T>>,
     pub ICorProfilerCallback9: ICorProfilerCallback9<CorProfilerCallback<T>>,
+    pub ICorProfilerCallback10: ICorProfilerCallback10<CorProfilerCallback<T>>,
 }
 
 #[repr(C)]
@@ -151,6 +152,10 @@ impl<T: Profiler> CorProfilerCallback<T> {
                 ICorProfilerCallback9: ICorProfilerCallback9 {
                     DynamicMethodUnloaded: Self::DynamicMethodUnloaded,
                 },
+                ICorProfilerCallback10: ICorProfilerCallback10 {
+                    EventPipeEventDelivered: Self::EventPipeEventDelivered,
+                    EventPipeProviderCreated: Self::EventPipeProviderCreated,
+                },
             },
             ref_count: AtomicU32::new(1), // TODO: Why does ref_count have to start at 1? Isn't 0 more appropriate? Why is release called by profiling api without calling add_ref?
             profiler,
@@ -175,6 +180,7 @@ impl<T: Profiler> CorProfilerCallback<T> {
             || *riid == ICorProfilerCallback7::IID
             || *riid == ICorProfilerCallback8::IID
             || *riid == ICorProfilerCallback9::IID
+            || *riid == ICorProfilerCallback10::IID
         {
             *ppvObject = self as *mut CorProfilerCallback<T> as LPVOID;
             self.add_ref();
@@ -1015,3 +1021,60 @@ impl<T: Profiler> CorProfilerCallback<T> {
         }
     }
 }
+
+// ICorProfilerCallback10
+impl<T: Profiler> CorProfilerCallback<T> {
+    pub unsafe extern "system" fn EventPipeEventDelivered(
+        &mut self,
+        provider: EVENTPIPE_PROVIDER,
+        eventId: DWORD,
+        eventVersion: DWORD,
+        cbMetadataBlob: ULONG,
+        metadataBlob: LPCBYTE,
+        cbEventData: ULONG,
+        eventData: LPCBYTE,
+        pActivityId: LPCGUID,
+        pRelatedActivityId: LPCGUID,
+        eventThread: ThreadID,
+        numStackFrames: ULONG,
+        stackFrames: *const UINT_PTR,
+    ) -> HRESULT {
+        let metadata_blob = if metadataBlob.is_null() {
+            &[]
+        } else {
+            slice::from_raw_parts(metadataBlob, cbMetadataBlob as usize)
+        };
+        let event_data = if eventData.is_null() {
+            &[]
+        } else {
+            slice::from_raw_parts(eventData, cbEventData as usize)
+        };
+        let stack_frames = if stackFrames.is_null() {
+            &[]
+        } else {
+            slice::from_raw_parts(stackFrames, numStackFrames as usize)
+        };
+        let result = self.profiler.event_pipe_event_delivered(
+            provider,
+            eventId,
+            eventVersion,
+            metadata_blob,
+            event_data,
+            pActivityId,
+            pRelatedActivityId,
+            eventThread,
+            stack_frames,
+        );
+        match result {
+            Ok(_) => HRESULT::S_OK,
+            Err(error) => error,
+        }
+    }
+    pub unsafe extern "system" fn EventPipeProviderCreated(&mut self, provider: EVENTPIPE_PROVIDER) -> HRESULT {
+        let result = self.profiler.event_pipe_provider_created(provider);
+        match result {
+            Ok(_) => HRESULT::S_OK,
+            Err(error) => error,
+        }
+    }
+}
@@ -1,4 +1,6 @@
 #![allow(non_snake_case)]
+use std::mem::MaybeUninit;
+
 use crate::ffi::{ICorProfilerFunctionEnum, IUnknown, COR_PRF_FUNCTION, HRESULT, ULONG};
 
 #[repr(C)]
@@ -32,3 +34,20 @@ impl CorProfilerFunctionEnum {
         (self.i_cor_profiler_function_enum().Next)(self, celt, ids, pceltFetched)
     }
 }
+
+impl Iterator for CorProfilerFunctionEnum {
+    type Item = COR_PRF_FUNCTION;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            let mut ids = MaybeUninit::uninit();
+            let mut fetched = MaybeUninit::uninit();
+
+            if self.Next(1, ids.as_mut_ptr(), fetched.as_mut_ptr()) == HRESULT::S_OK {
+                Some(ids.assume_init())
+            } else {
+                None
+            }
+        }
+    }
+}
@@ -4,12 +4,13 @@ use crate::ffi::{
     int, mdFieldDef, mdMethodDef, mdToken, mdTypeDef, AppDomainID, AssemblyID, ClassID, ContextID, CorElementType, CorProfilerFunctionEnum,
     CorProfilerMethodEnum, CorProfilerModuleEnum, CorProfilerObjectEnum, CorProfilerThreadEnum, FunctionEnter, FunctionEnter2, FunctionEnter3,
     FunctionEnter3WithInfo, FunctionID, FunctionIDMapper, FunctionIDMapper2, FunctionLeave, FunctionLeave2, FunctionLeave3, FunctionLeave3WithInfo,
-    FunctionTailcall, FunctionTailcall2, FunctionTailcall3, FunctionTailcall3WithInfo, ICorProfilerInfo, ICorProfilerInfo10, ICorProfilerInfo2,
-    ICorProfilerInfo3, ICorProfilerInfo4, ICorProfilerInfo5, ICorProfilerInfo6, ICorProfilerInfo7, ICorProfilerInfo8, ICorProfilerInfo9, IUnknown,
-    MethodMalloc, ModuleID, ObjectID, ObjectReferenceCallback, ProcessID, ReJITID, StackSnapshotCallback, ThreadID, Unknown, BOOL, BYTE,
-    COR_DEBUG_IL_TO_NATIVE_MAP, COR_FIELD_OFFSET, COR_IL_MAP, COR_PRF_CODE_INFO, COR_PRF_ELT_INFO, COR_PRF_EX_CLAUSE_INFO, COR_PRF_FRAME_INFO,
-    COR_PRF_FUNCTION_ARGUMENT_INFO, COR_PRF_FUNCTION_ARGUMENT_RANGE, COR_PRF_GC_GENERATION_RANGE, COR_PRF_RUNTIME_TYPE, COR_PRF_STATIC_TYPE, DWORD, HANDLE,
-    HRESULT, LPCBYTE, PCCOR_SIGNATURE, REFIID, SIZE_T, UINT_PTR, ULONG, ULONG32, USHORT, WCHAR,
+    FunctionTailcall, FunctionTailcall2, FunctionTailcall3, FunctionTailcall3WithInfo, ICorProfilerInfo, ICorProfilerInfo10, ICorProfilerInfo11,
+    ICorProfilerInfo12, ICorProfilerInfo2, ICorProfilerInfo3, ICorProfilerInfo4, ICorProfilerInfo5, ICorProfilerInfo6, ICorProfilerInfo7, ICorProfilerInfo8,
+    ICorProfilerInfo9, IUnknown, MethodMalloc, ModuleID, ObjectID, ObjectReferenceCallback, ProcessID, ReJITID, StackSnapshotCallback, ThreadID, Unknown, BOOL,
+    BYTE, COR_DEBUG_IL_TO_NATIVE_MAP, COR_FIELD_OFFSET, COR_IL_MAP, COR_PRF_CODE_INFO, COR_PRF_ELT_INFO, COR_PRF_EVENTPIPE_PROVIDER_CONFIG,
+    COR_PRF_EX_CLAUSE_INFO, COR_PRF_FRAME_INFO, COR_PRF_FUNCTION_ARGUMENT_INFO, COR_PRF_FUNCTION_ARGUMENT_RANGE, COR_PRF_GC_GENERATION_RANGE,
+    COR_PRF_RUNTIME_TYPE, COR_PRF_STATIC_TYPE, DWORD, EVENTPIPE_PROVIDER, EVENTPIPE_SESSION, HANDLE, HRESULT, LPCBYTE, PCCOR_SIGNATURE, REFIID, SIZE_T, UINT32,
+    UINT_PTR, ULONG, ULONG32, USHORT, WCHAR,
 };
 use std::ffi::c_void;
 #[repr(C)]
@@ -25,6 +26,8 @@ pub struct CorProfilerInfoVtbl {
     pub ICorProfilerInfo8: ICorProfilerInfo8<CorProfilerInfo>,
     pub ICorProfilerInfo9: ICorProfilerInfo9<CorProfilerInfo>,
     pub ICorProfilerInfo10: ICorProfilerInfo10<CorProfilerInfo>,
+    pub ICorProfilerInfo11: ICorProfilerInfo11<CorProfilerInfo>,
+    pub ICorProfilerInfo12: ICorProfilerInfo12<CorProfilerInfo>,
 }
 
 #[derive(Clone)]
@@ -34,6 +37,9 @@ pub struct CorProfilerInfo {
 }
 
 impl CorProfilerInfo {
+    unsafe fn i_unknown(&self) -> &IUnknown<Self> {
+        &(*self.lpVtbl).IUnknown
+    }
     unsafe fn i_cor_profiler_info(&self) -> &ICorProfilerInfo<Self> {
         &(*self.lpVtbl).ICorProfilerInfo
     }
@@ -64,6 +70,18 @@ impl CorProfilerInfo {
     unsafe fn i_cor_profiler_info_10(&self) -> &ICorProfilerInfo10<Self> {
         &(*self.lpVtbl).ICorProfilerInfo10
     }
+    unsafe fn i_cor_profiler_info_11(&self) -> &ICorProfilerInfo11<Self> {
+        &(*self.lpVtbl).ICorProfilerInfo11
+    }
+    unsafe fn i_cor_profiler_info_12(&self) -> &ICorProfilerInfo12<Self> {
+        &(*self.lpVtbl).ICorProfilerInfo12
+    }
+    pub unsafe fn QueryInterface(&mut self, riid: REFIID, ppvObject: *mut *mut c_void) -> HRESULT {
+        (self.i_unknown().QueryInterface)(self, riid, ppvObject)
+    }
+    pub unsafe fn Release(&mut self) -> ULONG {
+        (self.i_unknown().Release)(self)
+    }
     pub unsafe fn GetClassFromObject(&self, objectId: ObjectID, pClassId: *mut ClassID) -> HRESULT {
         (self.i_cor_profiler_info().GetClassFromObject)(self, objectId, pClassId)
     }
@@ -594,4 +612,31 @@ impl CorProfilerInfo {
     pub unsafe fn ResumeRuntime(&self) -> HRESULT {
         (self.i_cor_profiler_info_10().ResumeRuntime)(self)
     }
+    pub unsafe fn GetEnvironmentVariable(&self, szName: *const WCHAR, cchValue: ULONG, pcchValue: *mut ULONG, szValue: *mut WCHAR) -> HRESULT {
+        (self.i_cor_profiler_info_11().GetEnvironmentVariable)(self, szName, cchValue, pcchValue, szValue)
+    }
+    pub unsafe fn SetEnvironmentVariable(&self, szName: *const WCHAR, szValue: *const WCHAR) -> HRESULT {
+        (self.i_cor_profiler_info_11().SetEnvironmentVariable)(self, szName, szValue)
+    }
+    pub unsafe fn EventPipeStartSession(
+        &self,
+        cProviderConfigs: UINT32,
+        pProviderConfigs: *const COR_PRF_EVENTPIPE_PROVIDER_CONFIG,
+        requestRundown: BOOL,
+        pSession: *mut EVENTPIPE_SESSION,
+    ) -> HRESULT {
+        (self.i_cor_profiler_info_12().EventPipeStartSession)(self, cProviderConfigs, pProviderConfigs, requestRundown, pSession)
+    }
+    pub unsafe fn EventPipeAddProviderToSession(&self, session: EVENTPIPE_SESSION, providerConfig: COR_PRF_EVENTPIPE_PROVIDER_CONFIG) -> HRESULT {
+        (self.i_cor_profiler_info_12().EventPipeAddProviderToSession)(self, session, providerConfig)
+    }
+    pub unsafe fn EventPipeStopSession(&self, session: EVENTPIPE_SESSION) -> HRESULT {
+        (self.i_cor_profiler_info_12().EventPipeStopSession)(self, session)
+    }
+    pub unsafe fn EventPipeCreateProvider(&self, providerName: *const WCHAR, pProvider: *mut EVENTPIPE_PROVIDER) -> HRESULT {
+        (self.i_cor_profiler_info_12().EventPipeCreateProvider)(self, providerName, pProvider)
+    }
+    pub unsafe fn EventPipeGetProviderInfo(&self, provider: EVENTPIPE_PROVIDER, cchName: ULONG, pcchName: *mut ULONG, providerName: *mut WCHAR) -> HRESULT {
+        (self.i_cor_profiler_info_12().EventPipeGetProviderInfo)(self, provider, cchName, pcchName, providerName)
+    }
 }
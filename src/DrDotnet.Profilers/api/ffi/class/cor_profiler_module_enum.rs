@@ -1,4 +1,6 @@
 #![allow(non_snake_case)]
+use std::mem::MaybeUninit;
+
 use crate::ffi::{ICorProfilerModuleEnum, IUnknown, ModuleID, HRESULT, ULONG};
 
 #[repr(C)]
@@ -32,3 +34,20 @@ impl CorProfilerModuleEnum {
         (self.i_cor_profiler_module_enum().Next)(self, celt, objects, pceltFetched)
     }
 }
+
+impl Iterator for CorProfilerModuleEnum {
+    type Item = ModuleID;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            let mut ids = MaybeUninit::uninit();
+            let mut fetched = MaybeUninit::uninit();
+
+            if self.Next(1, ids.as_mut_ptr(), fetched.as_mut_ptr()) == HRESULT::S_OK {
+                Some(*ids.as_ptr())
+            } else {
+                None
+            }
+        }
+    }
+}
@@ -17,9 +17,15 @@ pub struct MetaDataAssemblyImport {
 }
 
 impl MetaDataAssemblyImport {
+    pub unsafe fn i_unknown(&self) -> &IUnknown<Self> {
+        &(*self.lpVtbl).IUnknown
+    }
     pub unsafe fn i_metadata_assembly_import(&self) -> &IMetaDataAssemblyImport<Self> {
         &(*self.lpVtbl).IMetaDataAssemblyImport
     }
+    pub unsafe fn Release(&mut self) -> ULONG {
+        (self.i_unknown().Release)(self)
+    }
     pub unsafe fn GetAssemblyProps(
         &self,
         mda: mdAssembly,
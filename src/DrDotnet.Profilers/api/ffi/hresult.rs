@@ -4,6 +4,7 @@
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub enum HRESULT {
     S_OK = 0,
+    S_FALSE = 1,
     E_NOINTERFACE = 0x8000_4002,
     E_OUTOFMEMORY = 0x8007_000E,
     CLASS_E_NOAGGREGATION = 0x8004_0110,
@@ -11,6 +12,8 @@ pub enum HRESULT {
     /// Unspecified error
     E_FAIL = 0x8000_4005,
     E_POINTER = 0x8000_4003,
+    /// Not implemented
+    E_NOTIMPL = 0x8000_4001,
     COR_E_INVALIDPROGRAM = 0x8013_153A,
     COR_E_INVALIDOPERATION = 0x8013_1509,
     COR_E_INDEXOUTOFRANGE = 0x8,
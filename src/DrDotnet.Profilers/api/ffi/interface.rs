@@ -1,6 +1,7 @@
 mod i_class_factory;
 mod i_cor_profiler_assembly_reference_provider;
 mod i_cor_profiler_callback_1;
+mod i_cor_profiler_callback_10;
 mod i_cor_profiler_callback_2;
 mod i_cor_profiler_callback_3;
 mod i_cor_profiler_callback_4;
@@ -13,6 +14,8 @@ mod i_cor_profiler_function_control;
 mod i_cor_profiler_function_enum;
 mod i_cor_profiler_info;
 mod i_cor_profiler_info_10;
+mod i_cor_profiler_info_11;
+mod i_cor_profiler_info_12;
 mod i_cor_profiler_info_2;
 mod i_cor_profiler_info_3;
 mod i_cor_profiler_info_4;
@@ -37,6 +40,7 @@ mod i_unknown;
 pub use self::i_class_factory::IClassFactory;
 pub use self::i_cor_profiler_assembly_reference_provider::ICorProfilerAssemblyReferenceProvider;
 pub use self::i_cor_profiler_callback_1::ICorProfilerCallback;
+pub use self::i_cor_profiler_callback_10::ICorProfilerCallback10;
 pub use self::i_cor_profiler_callback_2::ICorProfilerCallback2;
 pub use self::i_cor_profiler_callback_3::ICorProfilerCallback3;
 pub use self::i_cor_profiler_callback_4::ICorProfilerCallback4;
@@ -49,6 +53,8 @@ pub use self::i_cor_profiler_function_control::ICorProfilerFunctionControl;
 pub use self::i_cor_profiler_function_enum::ICorProfilerFunctionEnum;
 pub use self::i_cor_profiler_info::ICorProfilerInfo;
 pub use self::i_cor_profiler_info_10::ICorProfilerInfo10;
+pub use self::i_cor_profiler_info_11::ICorProfilerInfo11;
+pub use self::i_cor_profiler_info_12::ICorProfilerInfo12;
 pub use self::i_cor_profiler_info_2::ICorProfilerInfo2;
 pub use self::i_cor_profiler_info_3::ICorProfilerInfo3;
 pub use self::i_cor_profiler_info_4::ICorProfilerInfo4;
@@ -1,12 +1,12 @@
 use crate::{
     api::ffi::HCORENUM,
     ffi::{mdMethodDef, CorMethodAttr, CorMethodImpl, CorTypeAttr, MetaDataImport as FFIMetaDataImport, HRESULT, WCHAR},
-    MetadataImportTrait, MethodProps, TypeProps,
+    FieldProps, MetadataImportTrait, MethodProps, TypeProps,
 };
 use std::{mem::MaybeUninit, ptr};
 use widestring::U16CString;
 
-use super::ffi::mdGenericParam;
+use super::ffi::{mdFieldDef, mdGenericParam, mdInterfaceImpl, mdToken, mdTypeDef, mdTypeRef};
 
 #[derive(Clone)]
 pub struct MetadataImport {
@@ -233,4 +233,177 @@ impl MetadataImportTrait for MetadataImport {
             _ => Err(hr),
         }
     }
+
+    fn enum_interface_impls(&self, td: mdTypeDef) -> Result<Vec<mdToken>, HRESULT> {
+        let mut enumerator_handle = 0 as HCORENUM; // Must be initialized to NULL
+        let mut impls = Vec::<mdInterfaceImpl>::new();
+        let mut buffer = [0 as mdInterfaceImpl; 16];
+
+        // Fetch implementations by batches until the enumeration is exhausted
+        let hr = loop {
+            let mut fetched = 0;
+            let hr = unsafe {
+                self.import()
+                    .EnumInterfaceImpls(&mut enumerator_handle, td, buffer.as_mut_ptr(), buffer.len() as u32, &mut fetched)
+            };
+            impls.extend_from_slice(&buffer[..fetched as usize]);
+            if hr != HRESULT::S_OK || fetched == 0 {
+                break hr;
+            }
+        };
+
+        // Enumeration must be closed
+        unsafe { self.import().CloseEnum(enumerator_handle) };
+
+        if hr != HRESULT::S_OK && hr != HRESULT::S_FALSE {
+            return Err(hr);
+        }
+
+        impls
+            .into_iter()
+            .map(|interface_impl| {
+                let mut class = MaybeUninit::uninit();
+                let mut interface = MaybeUninit::uninit();
+                let hr = unsafe { self.import().GetInterfaceImplProps(interface_impl, class.as_mut_ptr(), interface.as_mut_ptr()) };
+                match hr {
+                    HRESULT::S_OK => Ok(unsafe { interface.assume_init() }),
+                    _ => Err(hr),
+                }
+            })
+            .collect()
+    }
+
+    fn get_type_ref_props(&self, tr: mdTypeRef) -> Result<String, HRESULT> {
+        let mut name_buffer_length = MaybeUninit::uninit();
+        unsafe {
+            self.import()
+                .GetTypeRefProps(tr, ptr::null_mut(), ptr::null_mut(), 0, name_buffer_length.as_mut_ptr())
+        };
+
+        let name_buffer_length = unsafe { name_buffer_length.assume_init() };
+        let mut name_buffer = vec![0 as WCHAR; name_buffer_length as usize];
+        let mut resolution_scope = MaybeUninit::uninit();
+        let mut name_length = MaybeUninit::uninit();
+        let hr = unsafe {
+            self.import().GetTypeRefProps(
+                tr,
+                resolution_scope.as_mut_ptr(),
+                name_buffer.as_mut_ptr(),
+                name_buffer_length,
+                name_length.as_mut_ptr(),
+            )
+        };
+
+        match hr {
+            HRESULT::S_OK => Ok(U16CString::from_vec_with_nul(name_buffer).unwrap().to_string_lossy()),
+            _ => Err(hr),
+        }
+    }
+
+    fn enum_type_defs(&self) -> Result<Vec<mdTypeDef>, HRESULT> {
+        let mut enumerator_handle = 0 as HCORENUM; // Must be initialized to NULL
+        let mut type_defs = Vec::<mdTypeDef>::new();
+        let mut buffer = [0 as mdTypeDef; 64];
+
+        // Fetch types by batches until the enumeration is exhausted
+        let hr = loop {
+            let mut fetched = 0;
+            let hr = unsafe {
+                self.import()
+                    .EnumTypeDefs(&mut enumerator_handle, buffer.as_mut_ptr(), buffer.len() as u32, &mut fetched)
+            };
+            type_defs.extend_from_slice(&buffer[..fetched as usize]);
+            if hr != HRESULT::S_OK || fetched == 0 {
+                break hr;
+            }
+        };
+
+        // Enumeration must be closed
+        unsafe { self.import().CloseEnum(enumerator_handle) };
+
+        match hr {
+            HRESULT::S_OK | HRESULT::S_FALSE => Ok(type_defs),
+            _ => Err(hr),
+        }
+    }
+
+    fn enum_fields(&self, td: mdTypeDef) -> Result<Vec<mdFieldDef>, HRESULT> {
+        let mut enumerator_handle = 0 as HCORENUM; // Must be initialized to NULL
+        let mut fields = Vec::<mdFieldDef>::new();
+        let mut buffer = [0 as mdFieldDef; 64];
+
+        // Fetch fields by batches until the enumeration is exhausted
+        let hr = loop {
+            let mut fetched = 0;
+            let hr = unsafe {
+                self.import()
+                    .EnumFields(&mut enumerator_handle, td, buffer.as_mut_ptr(), buffer.len() as u32, &mut fetched)
+            };
+            fields.extend_from_slice(&buffer[..fetched as usize]);
+            if hr != HRESULT::S_OK || fetched == 0 {
+                break hr;
+            }
+        };
+
+        // Enumeration must be closed
+        unsafe { self.import().CloseEnum(enumerator_handle) };
+
+        match hr {
+            HRESULT::S_OK | HRESULT::S_FALSE => Ok(fields),
+            _ => Err(hr),
+        }
+    }
+
+    fn get_field_props(&self, fd: mdFieldDef) -> Result<FieldProps, HRESULT> {
+        let mut name_buffer_length = MaybeUninit::uninit();
+        unsafe {
+            self.import().GetFieldProps(
+                fd,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                name_buffer_length.as_mut_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+
+        let name_buffer_length = unsafe { name_buffer_length.assume_init() };
+        let mut name_buffer = vec![0 as WCHAR; name_buffer_length as usize];
+        let mut class_token = MaybeUninit::uninit();
+        let mut name_length = MaybeUninit::uninit();
+        let mut attr_flags = MaybeUninit::uninit();
+        let mut sig = MaybeUninit::uninit();
+        let mut sig_length = MaybeUninit::uninit();
+        let hr = unsafe {
+            self.import().GetFieldProps(
+                fd,
+                class_token.as_mut_ptr(),
+                name_buffer.as_mut_ptr(),
+                name_buffer_length,
+                name_length.as_mut_ptr(),
+                attr_flags.as_mut_ptr(),
+                sig.as_mut_ptr(),
+                sig_length.as_mut_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+
+        match hr {
+            HRESULT::S_OK => Ok(FieldProps {
+                class_token: unsafe { class_token.assume_init() },
+                name: U16CString::from_vec_with_nul(name_buffer).unwrap().to_string_lossy(),
+                attr_flags: unsafe { attr_flags.assume_init() },
+                sig: unsafe { sig.assume_init() },
+                sig_length: unsafe { sig_length.assume_init() },
+            }),
+            _ => Err(hr),
+        }
+    }
 }
@@ -1,4 +1,5 @@
 mod cor_profiler_callback_1;
+mod cor_profiler_callback_10;
 mod cor_profiler_callback_2;
 mod cor_profiler_callback_3;
 mod cor_profiler_callback_4;
@@ -9,6 +10,8 @@ mod cor_profiler_callback_8;
 mod cor_profiler_callback_9;
 mod cor_profiler_info;
 mod cor_profiler_info_10;
+mod cor_profiler_info_11;
+mod cor_profiler_info_12;
 mod cor_profiler_info_2;
 mod cor_profiler_info_3;
 mod cor_profiler_info_4;
@@ -20,6 +23,7 @@ mod cor_profiler_info_9;
 mod metadata_import_trait;
 
 pub use self::cor_profiler_callback_1::CorProfilerCallback;
+pub use self::cor_profiler_callback_10::CorProfilerCallback10;
 pub use self::cor_profiler_callback_2::CorProfilerCallback2;
 pub use self::cor_profiler_callback_3::CorProfilerCallback3;
 pub use self::cor_profiler_callback_4::CorProfilerCallback4;
@@ -30,6 +34,8 @@ pub use self::cor_profiler_callback_8::CorProfilerCallback8;
 pub use self::cor_profiler_callback_9::CorProfilerCallback9;
 pub use self::cor_profiler_info::CorProfilerInfo;
 pub use self::cor_profiler_info_10::CorProfilerInfo10;
+pub use self::cor_profiler_info_11::CorProfilerInfo11;
+pub use self::cor_profiler_info_12::CorProfilerInfo12;
 pub use self::cor_profiler_info_2::CorProfilerInfo2;
 pub use self::cor_profiler_info_3::CorProfilerInfo3;
 pub use self::cor_profiler_info_4::CorProfilerInfo4;
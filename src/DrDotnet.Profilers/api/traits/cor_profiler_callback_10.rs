@@ -0,0 +1,26 @@
+#![allow(unused_variables)]
+use crate::{
+    ffi::{ThreadID, DWORD, EVENTPIPE_PROVIDER, HRESULT, LPCGUID, UINT_PTR},
+    CorProfilerCallback9,
+};
+
+pub trait CorProfilerCallback10: CorProfilerCallback9 {
+    fn event_pipe_event_delivered(
+        &mut self,
+        provider: EVENTPIPE_PROVIDER,
+        event_id: DWORD,
+        event_version: DWORD,
+        metadata_blob: &[u8],
+        event_data: &[u8],
+        activity_id: LPCGUID,
+        related_activity_id: LPCGUID,
+        event_thread: ThreadID,
+        stack_frames: &[UINT_PTR],
+    ) -> Result<(), HRESULT> {
+        Ok(())
+    }
+
+    fn event_pipe_provider_created(&mut self, provider: EVENTPIPE_PROVIDER) -> Result<(), HRESULT> {
+        Ok(())
+    }
+}
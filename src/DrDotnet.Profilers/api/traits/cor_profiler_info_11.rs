@@ -0,0 +1,6 @@
+use crate::{ffi::HRESULT, CorProfilerInfo10};
+
+pub trait CorProfilerInfo11: CorProfilerInfo10 {
+    fn get_environment_variable(&self, name: &str) -> Result<String, HRESULT>;
+    fn set_environment_variable(&self, name: &str, value: Option<&str>) -> Result<(), HRESULT>;
+}
@@ -0,0 +1,12 @@
+use crate::{
+    ffi::{EVENTPIPE_PROVIDER, EVENTPIPE_SESSION, HRESULT},
+    CorProfilerInfo11, EventPipeProviderConfig,
+};
+
+pub trait CorProfilerInfo12: CorProfilerInfo11 {
+    fn event_pipe_start_session(&self, provider_configs: &[EventPipeProviderConfig], request_rundown: bool) -> Result<EVENTPIPE_SESSION, HRESULT>;
+    fn event_pipe_add_provider_to_session(&self, session: EVENTPIPE_SESSION, provider_config: &EventPipeProviderConfig) -> Result<(), HRESULT>;
+    fn event_pipe_stop_session(&self, session: EVENTPIPE_SESSION) -> Result<(), HRESULT>;
+    fn event_pipe_create_provider(&self, provider_name: &str) -> Result<EVENTPIPE_PROVIDER, HRESULT>;
+    fn event_pipe_get_provider_info(&self, provider: EVENTPIPE_PROVIDER) -> Result<String, HRESULT>;
+}
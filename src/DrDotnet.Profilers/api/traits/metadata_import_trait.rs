@@ -1,6 +1,6 @@
 use crate::{
     ffi::{mdMethodDef, mdTypeDef, HRESULT},
-    MethodProps, TypeProps,
+    FieldProps, MethodProps, TypeProps,
 };
 
 pub trait MetadataImportTrait {
@@ -11,4 +11,14 @@ pub trait MetadataImportTrait {
     // We could return more than just the mdTypeDef, it just needs to be implemented
     fn get_generic_params_props(&self, td: crate::ffi::mdGenericParam) -> Result<crate::ffi::mdTypeDef, HRESULT>;
     fn get_version_string(&self) -> Result<String, HRESULT>;
+    // Returns the tokens (TypeDef, TypeRef or TypeSpec) of the interfaces implemented by a type
+    fn enum_interface_impls(&self, td: mdTypeDef) -> Result<Vec<crate::ffi::mdToken>, HRESULT>;
+    // Returns the name of a type referenced from another module (with its namespace)
+    fn get_type_ref_props(&self, tr: crate::ffi::mdTypeRef) -> Result<String, HRESULT>;
+    // Returns the name, attributes and signature of a field
+    fn get_field_props(&self, fd: crate::ffi::mdFieldDef) -> Result<FieldProps, HRESULT>;
+    // Returns the tokens of the types defined in the module
+    fn enum_type_defs(&self) -> Result<Vec<mdTypeDef>, HRESULT>;
+    // Returns the tokens of the fields (static or not) defined by a type
+    fn enum_fields(&self, td: mdTypeDef) -> Result<Vec<crate::ffi::mdFieldDef>, HRESULT>;
 }
@@ -127,8 +127,24 @@ pub struct MethodProps {
     pub impl_flags: CorMethodImpl,
 }
 
+pub struct FieldProps {
+    pub class_token: mdTypeDef,
+    pub name: String,
+    // CorFieldAttr flags (fdStatic = 0x10, ...)
+    pub attr_flags: DWORD,
+    pub sig: PCCOR_SIGNATURE,
+    pub sig_length: u32,
+}
+
 pub struct TypeProps {
     pub name: String,
     pub type_def_flags: CorTypeAttr,
     pub base_type: mdTypeDef,
 }
+
+pub struct EventPipeProviderConfig {
+    pub provider_name: String,
+    pub keywords: u64,
+    pub logging_level: u32,
+    pub filter_data: Option<String>,
+}
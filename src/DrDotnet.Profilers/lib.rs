@@ -26,7 +26,26 @@ register!(
     RuntimePauseProfiler,
     CpuHotpathProfiler,
     DuplicatedStringsProfiler,
-    MergedCallStacksProfiler
+    MergedCallStacksProfiler,
+    AllocationCallSitesProfiler,
+    LockContentionProfiler,
+    ThreadLifecycleProfiler,
+    JitCostProfiler,
+    ModuleLoadsProfiler,
+    FinalizationProfiler,
+    GCHandlesProfiler,
+    WallClockProfiler,
+    LargeObjectAllocationsProfiler,
+    HeapCensusProfiler,
+    StaticFieldsProfiler,
+    CollectionSizingProfiler,
+    BoxedValuesProfiler,
+    DuplicatedArraysProfiler,
+    PinnedObjectsProfiler,
+    ConditionalWeakTablesProfiler,
+    GCCompactionProfiler,
+    AsyncTasksProfiler,
+    DynamicMethodsProfiler
 );
 
 // Actual COM entry point
@@ -105,3 +105,4 @@ impl CorProfilerCallback6 for AllocationByClassProfiler {}
 impl CorProfilerCallback7 for AllocationByClassProfiler {}
 impl CorProfilerCallback8 for AllocationByClassProfiler {}
 impl CorProfilerCallback9 for AllocationByClassProfiler {}
+impl CorProfilerCallback10 for AllocationByClassProfiler {}
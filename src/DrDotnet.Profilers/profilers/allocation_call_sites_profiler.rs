@@ -0,0 +1,328 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::api::ffi::{ClassID, FunctionID, ThreadID, DWORD, EVENTPIPE_PROVIDER, EVENTPIPE_SESSION, HRESULT, LPCGUID, UINT_PTR};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{
+    gc_events_provider, AllocationStats, AllocationTick, CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver, TreeNode,
+    ALLOCATION_TICK_EVENT_ID,
+};
+
+// Decides which allocations get recorded so that the profiling overhead stays bounded.
+// Allocations are sampled every `interval` AllocationTick events, or every `interval` bytes when sampling by bytes.
+// A sampled allocation stands for every allocation that happened since the previous sample.
+#[derive(Default)]
+pub struct AllocationSampler {
+    sample_by_bytes: bool,
+    interval: u64,
+    counter: AtomicU64,
+}
+
+impl AllocationSampler {
+    pub fn new(sample_by_bytes: bool, interval: u64) -> Self {
+        AllocationSampler {
+            sample_by_bytes,
+            interval: interval.max(1),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    // Returns the estimated allocations the given allocations stand for if they are sampled, or None otherwise.
+    pub fn sample(&self, allocation: AllocationStats) -> Option<AllocationStats> {
+        let amount = if self.sample_by_bytes { allocation.bytes } else { 1 };
+        let previous = self.counter.fetch_add(amount, Ordering::Relaxed);
+
+        // Sample only when crossing an interval boundary
+        if previous / self.interval == (previous + amount) / self.interval {
+            return None;
+        }
+
+        let factor = if self.sample_by_bytes {
+            (self.interval / allocation.bytes.max(1)).max(1)
+        } else {
+            self.interval
+        };
+
+        Some(AllocationStats {
+            count: allocation.count * factor,
+            bytes: allocation.bytes * factor,
+        })
+    }
+}
+
+type AllocationTrees = HashMap<ClassID, TreeNode<FunctionID, AllocationStats>>;
+
+#[derive(Default)]
+pub struct AllocationCallSitesProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    sampler: AllocationSampler,
+    caller_to_callee: bool,
+    trees_by_class: Arc<Mutex<AllocationTrees>>,
+}
+
+impl Profiler for AllocationCallSitesProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "323977FC-434E-4333-B2A7-BDE6950C7847".to_owned(),
+            name: "List allocation call sites".to_owned(),
+            description: "Samples allocations through the runtime's AllocationTick events, emitted roughly every 100 KB allocated, along with the managed callstack of the allocating thread, and lists for each type the callstacks that allocate it the most in a tree view. Requires .NET 5 or later.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define(
+                    "Sample by bytes",
+                    "sample_by_bytes",
+                    false,
+                    "If set, an allocation is sampled every time the allocated bytes cross the sampling interval. Otherwise, an allocation is sampled every sampling interval AllocationTick events.",
+                ),
+                ProfilerParameter::define(
+                    "Sampling interval",
+                    "sampling_interval",
+                    1,
+                    "Number of AllocationTick events (or bytes if sampling by bytes) between two sampled allocations. The runtime already emits an AllocationTick only every ~100 KB allocated, so 1 records every event it sends. Increase it to lower the overhead.",
+                ),
+                ProfilerParameter::define(
+                    "Caller To Callee",
+                    "caller_to_callee",
+                    false,
+                    "If set, the output will display callers first and callees as children in the tree representation",
+                ),
+                ProfilerParameter::define("Maximum types", "max_types", 50, "The maximum number of allocated types to display"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl AllocationCallSitesProfiler {
+    fn profile(
+        session_info: SessionInfo,
+        clr: ClrProfilerInfo,
+        trees_by_class: Arc<Mutex<AllocationTrees>>,
+        event_pipe_session: Result<EVENTPIPE_SESSION, HRESULT>,
+    ) {
+        if event_pipe_session.is_ok() {
+            let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+            std::thread::sleep(std::time::Duration::from_secs(duration_seconds));
+        }
+
+        // Stop receiving allocations while the report is being written
+        if let Ok(session) = event_pipe_session {
+            if let Err(hresult) = clr.event_pipe_stop_session(session) {
+                error!("Error stopping EventPipe session: {:?}", hresult);
+            }
+        }
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let mut trees_by_class = trees_by_class.lock().unwrap();
+        let compare = &|a: &TreeNode<FunctionID, AllocationStats>, b: &TreeNode<FunctionID, AllocationStats>| {
+            b.get_inclusive_value().bytes.cmp(&a.get_inclusive_value().bytes)
+        };
+        for tree in trees_by_class.values_mut() {
+            tree.sort_by_iterative(compare);
+        }
+
+        let max_types = session_info.get_parameter::<usize>("max_types").unwrap();
+        let total: AllocationStats = trees_by_class.values().fold(AllocationStats::default(), |mut total, tree| {
+            total += &tree.get_inclusive_value();
+            total
+        });
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("allocation_call_sites.html".to_owned());
+        report.write_line("<h2>Allocation Call Sites</h2>".to_owned());
+        report.write_line(
+            "<p>Allocations are sampled from the runtime's AllocationTick events, emitted roughly every 100 KB allocated: \
+            each sample stands for all the bytes allocated since the previous one, so counts and bytes are estimates.</p>"
+                .to_owned(),
+        );
+        if let Err(hresult) = event_pipe_session {
+            report.write_line(format!(
+                "<p>⚠️ No allocation was sampled: the EventPipe session could not be started ({hresult:?}). This requires .NET 5 or later.</p>"
+            ));
+        }
+        report.write_line(format!("<h4>{} estimated allocations of {} types</h4>", total, trees_by_class.len()));
+
+        for (class_id, tree) in trees_by_class.iter().sorted_by(|a, b| compare(a.1, b.1)).take(max_types) {
+            let class_name = name_resolver.get_class_name(*class_id);
+            let escaped_class_name = html_escape::encode_text(&class_name);
+            let inclusive = tree.get_inclusive_value();
+
+            report.write_line(format!(
+                "<details><summary><code>{escaped_class_name}</code> \
+                <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">radio_button_checked</i></div></summary>"
+            ));
+            report.write_line("<ul>".to_owned());
+            for node in &tree.children {
+                node.print_html(&mut report, &|node| Self::format_html_line(&name_resolver, node));
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, AllocationStats>) -> String {
+        let inclusive = node.get_inclusive_value();
+        let exclusive = node.value.unwrap_or_default();
+
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">radio_button_checked</i></div> \
+            <div class=\"chip\"><span>{exclusive}</span><i class=\"material-icons\">radio_button_unchecked</i></div>"
+        )
+    }
+}
+
+impl CorProfilerCallback for AllocationCallSitesProfiler {}
+
+impl CorProfilerCallback2 for AllocationCallSitesProfiler {}
+
+impl CorProfilerCallback3 for AllocationCallSitesProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        // Object allocation notifications are immutable flags that can't be set when attaching,
+        // but the runtime still delivers its AllocationTick events through EventPipe.
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+            Some(ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_EVENT_PIPE),
+            profiler_info,
+            client_data,
+            client_data_length,
+        )?;
+
+        let sample_by_bytes = self.session_info().get_parameter::<bool>("sample_by_bytes").unwrap();
+        let sampling_interval = self.session_info().get_parameter::<u64>("sampling_interval").unwrap();
+        self.sampler = AllocationSampler::new(sample_by_bytes, sampling_interval);
+        self.caller_to_callee = self.session_info().get_parameter::<bool>("caller_to_callee").unwrap();
+
+        Ok(())
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let trees_by_class = self.trees_by_class.clone();
+
+        let event_pipe_session = clr.event_pipe_start_session(&[gc_events_provider()], false);
+        if let Err(hresult) = &event_pipe_session {
+            error!("Error starting EventPipe session: {:?}", hresult);
+        }
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || AllocationCallSitesProfiler::profile(session_info, clr, trees_by_class, event_pipe_session));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for AllocationCallSitesProfiler {}
+impl CorProfilerCallback5 for AllocationCallSitesProfiler {}
+impl CorProfilerCallback6 for AllocationCallSitesProfiler {}
+impl CorProfilerCallback7 for AllocationCallSitesProfiler {}
+impl CorProfilerCallback8 for AllocationCallSitesProfiler {}
+impl CorProfilerCallback9 for AllocationCallSitesProfiler {}
+
+impl CorProfilerCallback10 for AllocationCallSitesProfiler {
+    fn event_pipe_event_delivered(
+        &mut self,
+        _provider: EVENTPIPE_PROVIDER,
+        event_id: DWORD,
+        event_version: DWORD,
+        _metadata_blob: &[u8],
+        event_data: &[u8],
+        _activity_id: LPCGUID,
+        _related_activity_id: LPCGUID,
+        _event_thread: ThreadID,
+        _stack_frames: &[UINT_PTR],
+    ) -> Result<(), HRESULT> {
+        if event_id != ALLOCATION_TICK_EVENT_ID {
+            return Ok(());
+        }
+
+        let allocation_tick = match AllocationTick::parse(event_version, event_data) {
+            Some(allocation_tick) => allocation_tick,
+            None => return Ok(()),
+        };
+
+        let allocation = AllocationStats {
+            count: allocation_tick.estimated_count(),
+            bytes: allocation_tick.amount,
+        };
+        let stats = match self.sampler.sample(allocation) {
+            Some(stats) => stats,
+            None => return Ok(()),
+        };
+
+        // Events of the session are delivered synchronously on the allocating thread,
+        // so the current callstack is the allocation callstack
+        let method_ids = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(self.clr().clone());
+
+        let mut trees_by_class = self.trees_by_class.lock().unwrap();
+        let tree = trees_by_class.entry(allocation_tick.class_id).or_insert_with(|| TreeNode::new(0));
+        let node = if self.caller_to_callee {
+            tree.add_sequence(method_ids.into_iter().rev())
+        } else {
+            tree.add_sequence(method_ids)
+        };
+
+        let value = node.value.get_or_insert_with(AllocationStats::default);
+        *value += &stats;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AllocationSampler;
+    use crate::utils::AllocationStats;
+
+    #[test]
+    fn sampler_by_count_samples_every_interval() {
+        let sampler = AllocationSampler::new(false, 3);
+        let samples: Vec<Option<AllocationStats>> = (0..6).map(|_| sampler.sample(AllocationStats { count: 1, bytes: 10 })).collect();
+
+        assert_eq!(samples.iter().filter(|s| s.is_some()).count(), 2);
+        // Each sample stands for the 3 allocations of its interval
+        assert_eq!(samples[2], Some(AllocationStats { count: 3, bytes: 30 }));
+    }
+
+    #[test]
+    fn sampler_by_bytes_weights_samples_by_object_size() {
+        let sampler = AllocationSampler::new(true, 100);
+
+        // Small objects are sampled once every 100 bytes, each sample standing for several objects
+        let small_samples: Vec<AllocationStats> = (0..20).filter_map(|_| sampler.sample(AllocationStats { count: 1, bytes: 10 })).collect();
+        assert_eq!(small_samples, vec![AllocationStats { count: 10, bytes: 100 }; 2]);
+
+        // Objects larger than the interval are always sampled and stand for themselves
+        assert_eq!(
+            sampler.sample(AllocationStats { count: 1, bytes: 1000 }),
+            Some(AllocationStats { count: 1, bytes: 1000 })
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::thread;
+
+use crate::api::ffi::{ClassID, CorOpenFlags, ObjectID, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::static_fields_profiler::is_reference_field_signature;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, NameResolver, TreeNode};
+
+// TASK_STATE_RAN_TO_COMPLETION | TASK_STATE_FAULTED | TASK_STATE_CANCELED
+const TASK_STATE_COMPLETED_MASK: i32 = 0x1000000 | 0x200000 | 0x400000;
+
+// Fields through which continuations (delegates, TaskContinuation, ContinuationWrapper, ...) lead to the awaiting task
+const CONTINUATION_FIELDS: [&str; 4] = ["_target", "m_task", "m_action", "_continuation"];
+
+// Continuations can be wrapped into one another, but never that deep
+const MAX_CONTINUATION_DEPTH: usize = 8;
+
+pub fn is_task_completed(state_flags: i32) -> bool {
+    state_flags & TASK_STATE_COMPLETED_MASK != 0
+}
+
+// Returns the async method of a compiler generated state machine, for instance MyApp.Worker.RunAsync for MyApp.Worker.<RunAsync>d__4
+pub fn state_machine_method_name(state_machine_name: &str) -> Option<String> {
+    let start = state_machine_name.rfind(".<")?;
+    let (declaring_type, generated_name) = state_machine_name.split_at(start);
+
+    // Async lambdas are named after the method declaring them, as in <<Main>b__0_0>d
+    let mut depth = 0;
+    for (position, character) in generated_name.char_indices().skip(1) {
+        match character {
+            '<' => depth += 1,
+            '>' if depth == 1 => return Some(format!("{declaring_type}.{}", &generated_name[2..position])),
+            '>' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy)]
+struct Field {
+    offset: u32,
+    is_reference: bool,
+}
+
+#[derive(Default)]
+pub struct AsyncTasksProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    task_classes: HashMap<ClassID, bool>,
+    field_offsets: HashMap<ClassID, Rc<HashMap<String, Field>>>,
+    task_object_ids: Vec<(ObjectID, ClassID)>,
+    record_object_references: bool,
+}
+
+impl Profiler for AsyncTasksProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "2956ECC2-7D9C-4D29-ACBB-865DCB06DA9C".to_owned(),
+            name: "List pending async tasks".to_owned(),
+            description: "Perform a full blocking garbage collection and list the incomplete tasks and async state machines on the heap. Continuations are followed to rebuild the logical await chains, which are merged and displayed as a tree, outermost async method first.".to_owned(),
+            parameters: vec![ProfilerParameter::define("Maximum types", "max_types", 50, "The maximum number of pending task types to display")],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl AsyncTasksProfiler {
+    fn is_task_class(&mut self, class_id: ClassID) -> bool {
+        let clr = self.clr().clone();
+        *self.task_classes.entry(class_id).or_insert_with(|| {
+            let mut class_id = class_id;
+            while class_id != 0 {
+                if clr.get_class_name(class_id) == "System.Threading.Tasks.Task" {
+                    return true;
+                }
+                class_id = clr.get_class_id_info_2(class_id).map_or(0, |class_info| class_info.parent_class_id);
+            }
+            false
+        })
+    }
+
+    // Offsets of the fields of a class and of its parents, by name, along with whether they hold a reference
+    fn fields(&mut self, class_id: ClassID) -> Rc<HashMap<String, Field>> {
+        let clr = self.clr().clone();
+        self.field_offsets
+            .entry(class_id)
+            .or_insert_with(|| {
+                let mut offsets = HashMap::new();
+                let mut class_id = class_id;
+                while class_id != 0 {
+                    let Ok(class_info) = clr.get_class_id_info_2(class_id) else {
+                        break;
+                    };
+                    if let (Ok(metadata), Ok(class_layout)) = (
+                        clr.get_module_metadata(class_info.module_id, CorOpenFlags::ofRead),
+                        clr.get_class_layout(class_id),
+                    ) {
+                        for field in &class_layout.field_offset {
+                            if let Ok(field_props) = metadata.get_field_props(field.ridOfField) {
+                                let signature = unsafe { std::slice::from_raw_parts(field_props.sig, field_props.sig_length as usize) };
+                                offsets.entry(field_props.name).or_insert(Field {
+                                    offset: field.ulOffset,
+                                    is_reference: is_reference_field_signature(signature),
+                                });
+                            }
+                        }
+                    }
+                    class_id = class_info.parent_class_id;
+                }
+                Rc::new(offsets)
+            })
+            .clone()
+    }
+
+    fn read_field<T: Copy>(&mut self, object_id: ObjectID, class_id: ClassID, name: &str) -> Option<T> {
+        let field = *self.fields(class_id).get(name)?;
+        Some(unsafe { *((object_id + field.offset as usize) as *const T) })
+    }
+
+    // Reads a field holding an object reference. Fields sharing the name of a reference field but holding a value are ignored.
+    fn read_reference_field(&mut self, object_id: ObjectID, class_id: ClassID, name: &str) -> Option<ObjectID> {
+        let field = *self.fields(class_id).get(name)?;
+        if !field.is_reference {
+            return None;
+        }
+        Some(unsafe { *((object_id + field.offset as usize) as *const ObjectID) })
+    }
+
+    // Resolves a continuation object to the tasks it will resume once the awaited task completes
+    fn resolve_continuation(&mut self, object_id: ObjectID, depth: usize, tasks: &mut Vec<ObjectID>) {
+        if object_id == 0 || depth > MAX_CONTINUATION_DEPTH {
+            return;
+        }
+        let Ok(class_id) = self.clr().get_class_from_object(object_id) else {
+            return;
+        };
+
+        // Async state machine boxes are tasks themselves
+        if self.is_task_class(class_id) {
+            tasks.push(object_id);
+            return;
+        }
+
+        // Tasks with several continuations store them in a List<object>
+        if self.clr().get_class_name(class_id) == "System.Collections.Generic.List<System.Object>" {
+            let items_id = self.read_reference_field(object_id, class_id, "_items").unwrap_or(0);
+            let size = self.read_field::<i32>(object_id, class_id, "_size").unwrap_or(0).max(0) as usize;
+            let Ok(items) = self.clr().get_array_object_info(items_id, 1) else {
+                return;
+            };
+            let length = size.min(items.dimension_sizes[0] as usize);
+            for index in 0..length {
+                let item_id = unsafe { *(items.data as *const ObjectID).add(index) };
+                self.resolve_continuation(item_id, depth + 1, tasks);
+            }
+            return;
+        }
+
+        for field in CONTINUATION_FIELDS {
+            if let Some(target_id) = self.read_reference_field(object_id, class_id, field) {
+                self.resolve_continuation(target_id, depth + 1, tasks);
+            }
+        }
+    }
+
+    // Returns, for each pending task, its class and the pending tasks awaiting it
+    fn pending_tasks(&mut self) -> HashMap<ObjectID, (ClassID, Vec<ObjectID>)> {
+        let mut pending_tasks = HashMap::new();
+        for (object_id, class_id) in std::mem::take(&mut self.task_object_ids) {
+            let state_flags = self.read_field::<i32>(object_id, class_id, "m_stateFlags").unwrap_or(0);
+            if is_task_completed(state_flags) {
+                continue;
+            }
+
+            let mut continuations = Vec::new();
+            let continuation_id = self.read_reference_field(object_id, class_id, "m_continuationObject").unwrap_or(0);
+            self.resolve_continuation(continuation_id, 0, &mut continuations);
+            pending_tasks.insert(object_id, (class_id, continuations));
+        }
+        pending_tasks
+    }
+
+    // Merges the await chains into a tree. Chains start from the innermost tasks, which are not awaiting any other task.
+    fn build_chains(pending_tasks: &HashMap<ObjectID, (ClassID, Vec<ObjectID>)>) -> TreeNode<ClassID, u64> {
+        let awaiting: HashSet<ObjectID> = pending_tasks.values().flat_map(|(_, continuations)| continuations.iter().copied()).collect();
+
+        let mut tree = TreeNode::new(0);
+        for (&task_id, _) in pending_tasks.iter().filter(|(task_id, _)| !awaiting.contains(task_id)) {
+            let mut chain = vec![task_id];
+            let mut visited = HashSet::from([task_id]);
+            // Follows the first pending continuation of each task, the await chain of a request being linear
+            while let Some(&next_id) = pending_tasks[chain.last().unwrap()]
+                .1
+                .iter()
+                .find(|continuation_id| pending_tasks.contains_key(continuation_id))
+            {
+                if !visited.insert(next_id) {
+                    break;
+                }
+                chain.push(next_id);
+            }
+
+            let class_ids = chain.iter().rev().map(|task_id| pending_tasks[task_id].0);
+            *tree.add_sequence(class_ids).value.get_or_insert(0) += 1;
+        }
+
+        tree.sort_by_iterative(&|a: &TreeNode<ClassID, u64>, b: &TreeNode<ClassID, u64>| b.get_inclusive_value().cmp(&a.get_inclusive_value()));
+        tree
+    }
+
+    // Names async state machine boxes after their async method, and other tasks after their type
+    fn describe(clr: &ClrProfilerInfo, name_resolver: &CachedNameResolver, class_id: ClassID) -> String {
+        let class_name = name_resolver.get_class_name(class_id);
+        if class_name.contains("AsyncStateMachineBox") {
+            let state_machine_name = clr
+                .get_class_id_info_2(class_id)
+                .ok()
+                .and_then(|class_info| class_info.type_args.last().copied())
+                .map(|state_machine_id| name_resolver.get_class_name(state_machine_id));
+            if let Some(method_name) = state_machine_name.as_deref().and_then(state_machine_method_name) {
+                return format!("async {method_name}");
+            }
+        }
+        class_name
+    }
+
+    fn write_report(&self, pending_tasks: &HashMap<ObjectID, (ClassID, Vec<ObjectID>)>, chains: &TreeNode<ClassID, u64>) {
+        let max_types = self.session_info.get_parameter::<usize>("max_types").unwrap();
+
+        let clr = self.clr();
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = self.session_info.create_report("async_tasks.html".to_owned());
+        report.write_line("<h2>Async Tasks</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{} pending tasks in {} await chains</h4>",
+            pending_tasks.len(),
+            chains.get_inclusive_value()
+        ));
+
+        report.write_line("<h3>Pending Tasks By Type</h3>".to_owned());
+        report.write_line("<table><tr><th>Task</th><th>Count</th></tr>".to_owned());
+        let counts = pending_tasks.values().counts_by(|(class_id, _)| *class_id);
+        for (class_id, count) in counts.into_iter().sorted_by_key(|(_, count)| std::cmp::Reverse(*count)).take(max_types) {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{count}</td></tr>",
+                html_escape::encode_text(&Self::describe(clr, &name_resolver, class_id))
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Await Chains</h3>".to_owned());
+        report.write_line("<p>Each chain starts with the outermost async method and ends with the task it is ultimately waiting for.</p>".to_owned());
+        report.write_line("<ul>".to_owned());
+        for node in &chains.children {
+            node.print_html(&mut report, &|node| Self::format_html_line(clr, &name_resolver, node));
+        }
+        report.write_line("</ul>".to_owned());
+    }
+
+    fn format_html_line(clr: &ClrProfilerInfo, name_resolver: &CachedNameResolver, node: &TreeNode<ClassID, u64>) -> String {
+        let inclusive = node.get_inclusive_value();
+        let escaped_name = html_escape::encode_text(&Self::describe(clr, name_resolver, node.key)).to_string();
+
+        format!(
+            "<code>{escaped_name}</code> \
+            <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">hourglass_empty</i></div>"
+        )
+    }
+}
+
+impl CorProfilerCallback for AsyncTasksProfiler {
+    fn object_references(&mut self, object_id: ObjectID, class_id: ClassID, _object_ref_ids: &[ObjectID]) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        if self.is_task_class(class_id) {
+            self.task_object_ids.push((object_id, class_id));
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for AsyncTasksProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        // Start recording objects
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        info!("GC finished, {} tasks recorded", self.task_object_ids.len());
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        // Tasks are inspected while the runtime is still suspended, so that their state can't change
+        let pending_tasks = self.pending_tasks();
+        let chains = Self::build_chains(&pending_tasks);
+        self.write_report(&pending_tasks, &chains);
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for AsyncTasksProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        let clr = self.clr().clone();
+        let _ = thread::spawn(move || {
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        })
+        .join();
+
+        // Security timeout
+        detach_after_duration::<AsyncTasksProfiler>(self, 120);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for AsyncTasksProfiler {}
+impl CorProfilerCallback5 for AsyncTasksProfiler {}
+impl CorProfilerCallback6 for AsyncTasksProfiler {}
+impl CorProfilerCallback7 for AsyncTasksProfiler {}
+impl CorProfilerCallback8 for AsyncTasksProfiler {}
+impl CorProfilerCallback9 for AsyncTasksProfiler {}
+impl CorProfilerCallback10 for AsyncTasksProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_task_completed, state_machine_method_name, AsyncTasksProfiler};
+    use std::collections::HashMap;
+
+    #[test]
+    fn async_methods_are_named_after_their_state_machine() {
+        assert_eq!(
+            state_machine_method_name("MyApp.Worker.<RunAsync>d__4").as_deref(),
+            Some("MyApp.Worker.RunAsync")
+        );
+        assert_eq!(
+            state_machine_method_name("MyApp.Program.<<Main>b__0_0>d").as_deref(),
+            Some("MyApp.Program.<Main>b__0_0")
+        );
+        assert_eq!(state_machine_method_name("MyApp.Worker"), None);
+        assert!(is_task_completed(0x1000000));
+        assert!(!is_task_completed(0x2000));
+    }
+
+    #[test]
+    fn identical_await_chains_are_merged() {
+        // Two requests (0x10 and 0x20, of type 1) awaiting a method (type 2) awaiting a delay (type 3), and a lone pending task of type 3
+        let pending_tasks = HashMap::from([
+            (0x10, (1, vec![])),
+            (0x11, (2, vec![0x10])),
+            (0x12, (3, vec![0x11])),
+            (0x20, (1, vec![])),
+            (0x21, (2, vec![0x20])),
+            (0x22, (3, vec![0x21])),
+            (0x30, (3, vec![])),
+        ]);
+
+        let chains = AsyncTasksProfiler::build_chains(&pending_tasks);
+        assert_eq!(chains.get_inclusive_value(), 3);
+        assert_eq!(chains.children[0].key, 1);
+        assert_eq!(chains.children[0].get_inclusive_value(), 2);
+        assert_eq!(chains.children[0].children[0].children[0].key, 3);
+        assert_eq!(chains.children[1].key, 3);
+    }
+}
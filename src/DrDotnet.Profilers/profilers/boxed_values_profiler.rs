@@ -0,0 +1,366 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::api::ffi::{ClassID, FunctionID, ObjectID, ThreadID, DWORD, EVENTPIPE_PROVIDER, HRESULT, LPCGUID, UINT_PTR};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{
+    gc_events_provider, AllocationStats, AllocationTick, CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver, TreeNode,
+    ALLOCATION_TICK_EVENT_ID,
+};
+
+const PRIMITIVES: &[&str] = &[
+    "System.Boolean",
+    "System.Char",
+    "System.SByte",
+    "System.Byte",
+    "System.Int16",
+    "System.UInt16",
+    "System.Int32",
+    "System.UInt32",
+    "System.Int64",
+    "System.UInt64",
+    "System.IntPtr",
+    "System.UIntPtr",
+    "System.Single",
+    "System.Double",
+    "System.Decimal",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxedKind {
+    Primitive,
+    Enum,
+    Struct,
+}
+
+impl BoxedKind {
+    pub fn classify(class_name: &str, parent_class_name: &str) -> BoxedKind {
+        if PRIMITIVES.contains(&class_name) {
+            BoxedKind::Primitive
+        } else if parent_class_name == "System.Enum" {
+            BoxedKind::Enum
+        } else {
+            BoxedKind::Struct
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            BoxedKind::Primitive => "Primitive",
+            BoxedKind::Enum => "Enum",
+            BoxedKind::Struct => "Struct",
+        }
+    }
+}
+
+type BoxingTrees = HashMap<ClassID, TreeNode<FunctionID, AllocationStats>>;
+
+#[derive(Default)]
+pub struct BoxedValuesProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    // Kind of boxed value of each class met during the heap walk (None for reference types)
+    kinds: HashMap<ClassID, Option<BoxedKind>>,
+    census: HashMap<ClassID, AllocationStats>,
+    // Why boxing callstacks could not be sampled, if they couldn't
+    event_pipe_error: Option<HRESULT>,
+    trees_by_class: Arc<Mutex<BoxingTrees>>,
+    record_object_references: bool,
+}
+
+impl Profiler for BoxedValuesProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "453034DC-01A9-4600-B70A-32A75103C944".to_owned(),
+            name: "List boxed values".to_owned(),
+            description: "Perform a full blocking garbage collection and count the boxed primitives, enums and structs on the heap, per boxed type. Beforehand, the callstacks boxing values are sampled from the runtime's AllocationTick events (.NET 5 or later).".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define(
+                    "Duration",
+                    "duration_seconds",
+                    10,
+                    "The duration in seconds during which boxing callstacks are sampled before the heap is inspected",
+                ),
+                ProfilerParameter::define("Maximum types", "max_types", 50, "The maximum number of boxed types to display"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl BoxedValuesProfiler {
+    // Only value types have a box layout, which tells boxed values apart from other objects
+    fn kind(&mut self, class_id: ClassID) -> Option<BoxedKind> {
+        let clr = self.clr().clone();
+        *self.kinds.entry(class_id).or_insert_with(|| {
+            clr.get_box_class_layout(class_id).ok()?;
+            let parent_class_id = clr.get_class_id_info_2(class_id).map_or(0, |class_info| class_info.parent_class_id);
+            let parent_class_name = if parent_class_id == 0 {
+                String::new()
+            } else {
+                clr.get_class_name(parent_class_id)
+            };
+            Some(BoxedKind::classify(&clr.get_class_name(class_id), &parent_class_name))
+        })
+    }
+
+    fn write_report(&self) {
+        let max_types = self.session_info.get_parameter::<usize>("max_types").unwrap();
+
+        let total = self.census.values().fold(AllocationStats::default(), |mut total, stats| {
+            total += stats;
+            total
+        });
+
+        let name_resolver = CachedNameResolver::new(self.clr().clone());
+        let mut report = self.session_info.create_report("boxed_values.html".to_owned());
+        report.write_line("<h2>Boxed Values</h2>".to_owned());
+        report.write_line(format!("<h4>{} boxed values of {} types</h4>", total, self.census.len()));
+
+        report.write_line("<table><tr><th>Type</th><th>Kind</th><th>Instances / Bytes</th></tr>".to_owned());
+        for (class_id, stats) in self.census.iter().sorted_by(|a, b| b.1.bytes.cmp(&a.1.bytes)).take(max_types) {
+            let kind = self.kinds.get(class_id).copied().flatten().map_or("", BoxedKind::name);
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{kind}</td><td>{stats}</td></tr>",
+                html_escape::encode_text(&name_resolver.get_class_name(*class_id))
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Boxing Callstacks</h3>".to_owned());
+        if let Some(hresult) = &self.event_pipe_error {
+            report.write_line(format!(
+                "<p>⚠️ The callstacks boxing values could not be sampled: the EventPipe session could not be started ({hresult:?}). This requires .NET 5 or later.</p>"
+            ));
+            return;
+        }
+        report.write_line(
+            "<p>Boxings are sampled from the runtime's AllocationTick events, emitted roughly every 100 KB allocated: \
+            each sample stands for all the bytes allocated since the previous one, so counts and bytes are estimates.</p>"
+                .to_owned(),
+        );
+
+        let compare = &|a: &TreeNode<FunctionID, AllocationStats>, b: &TreeNode<FunctionID, AllocationStats>| {
+            b.get_inclusive_value().bytes.cmp(&a.get_inclusive_value().bytes)
+        };
+        let mut trees_by_class = self.trees_by_class.lock().unwrap();
+        for tree in trees_by_class.values_mut() {
+            tree.sort_by_iterative(compare);
+        }
+        for (class_id, tree) in trees_by_class.iter().sorted_by(|a, b| compare(a.1, b.1)).take(max_types) {
+            report.write_line(format!(
+                "<details><summary><code>{}</code> \
+                <div class=\"chip\"><span>{}</span><i class=\"material-icons\">radio_button_checked</i></div></summary>",
+                html_escape::encode_text(&name_resolver.get_class_name(*class_id)),
+                tree.get_inclusive_value()
+            ));
+            report.write_line("<ul>".to_owned());
+            for node in &tree.children {
+                node.print_html(&mut report, &|node| Self::format_html_line(&name_resolver, node));
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, AllocationStats>) -> String {
+        let inclusive = node.get_inclusive_value();
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">radio_button_checked</i></div>"
+        )
+    }
+}
+
+impl CorProfilerCallback for BoxedValuesProfiler {
+    fn object_references(&mut self, object_id: ObjectID, class_id: ClassID, _object_ref_ids: &[ObjectID]) -> Result<(), HRESULT> {
+        if !self.record_object_references || self.kind(class_id).is_none() {
+            return Ok(());
+        }
+
+        let size = self.clr().get_object_size_2(object_id).unwrap_or(0) as u64;
+        *self.census.entry(class_id).or_default() += &AllocationStats { count: 1, bytes: size };
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for BoxedValuesProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        // Start recording objects
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        info!("GC finished, {} boxed types recorded", self.census.len());
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        self.write_report();
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for BoxedValuesProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        // Object allocation notifications are immutable flags that can't be set when attaching,
+        // but the runtime still delivers its AllocationTick events through EventPipe.
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+            Some(ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_EVENT_PIPE),
+            profiler_info,
+            client_data,
+            client_data_length,
+        )
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+
+        let event_pipe_session = match clr.event_pipe_start_session(&[gc_events_provider()], false) {
+            Ok(session) => Some(session),
+            Err(hresult) => {
+                warn!("Could not sample boxing callstacks, only the heap will be inspected: {:?}", hresult);
+                self.event_pipe_error = Some(hresult);
+                None
+            }
+        };
+        let duration_seconds = if event_pipe_session.is_some() {
+            self.session_info.get_parameter::<u64>("duration_seconds").unwrap()
+        } else {
+            0
+        };
+
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        std::thread::spawn(move || {
+            // Let boxing callstacks be sampled before inspecting the heap
+            std::thread::sleep(std::time::Duration::from_secs(duration_seconds));
+
+            if let Some(session) = event_pipe_session {
+                if let Err(hresult) = clr.event_pipe_stop_session(session) {
+                    error!("Error stopping EventPipe session: {:?}", hresult);
+                }
+            }
+
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        });
+
+        // Security timeout
+        detach_after_duration::<BoxedValuesProfiler>(self, duration_seconds + 320);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for BoxedValuesProfiler {}
+impl CorProfilerCallback5 for BoxedValuesProfiler {}
+impl CorProfilerCallback6 for BoxedValuesProfiler {}
+impl CorProfilerCallback7 for BoxedValuesProfiler {}
+impl CorProfilerCallback8 for BoxedValuesProfiler {}
+impl CorProfilerCallback9 for BoxedValuesProfiler {}
+
+impl CorProfilerCallback10 for BoxedValuesProfiler {
+    fn event_pipe_event_delivered(
+        &mut self,
+        _provider: EVENTPIPE_PROVIDER,
+        event_id: DWORD,
+        event_version: DWORD,
+        _metadata_blob: &[u8],
+        event_data: &[u8],
+        _activity_id: LPCGUID,
+        _related_activity_id: LPCGUID,
+        _event_thread: ThreadID,
+        _stack_frames: &[UINT_PTR],
+    ) -> Result<(), HRESULT> {
+        if event_id != ALLOCATION_TICK_EVENT_ID || self.record_object_references {
+            return Ok(());
+        }
+
+        let allocation_tick = match AllocationTick::parse(event_version, event_data) {
+            Some(allocation_tick) => allocation_tick,
+            None => return Ok(()),
+        };
+
+        // Allocations happen on many threads at once, so the kind cache is left to the heap walk
+        if self.clr().get_box_class_layout(allocation_tick.class_id).is_err() {
+            return Ok(());
+        }
+
+        // Events of the session are delivered synchronously on the allocating thread,
+        // so the current callstack is the boxing callstack
+        let method_ids = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(self.clr().clone());
+
+        let mut trees_by_class = self.trees_by_class.lock().unwrap();
+        let tree = trees_by_class.entry(allocation_tick.class_id).or_insert_with(|| TreeNode::new(0));
+        let node = tree.add_sequence(method_ids);
+        *node.value.get_or_insert_with(AllocationStats::default) += &AllocationStats {
+            count: allocation_tick.estimated_count(),
+            bytes: allocation_tick.amount,
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BoxedKind;
+
+    #[test]
+    fn boxed_values_are_classified_by_type_and_parent() {
+        assert_eq!(BoxedKind::classify("System.Int32", "System.ValueType"), BoxedKind::Primitive);
+        assert_eq!(BoxedKind::classify("System.DayOfWeek", "System.Enum"), BoxedKind::Enum);
+        assert_eq!(
+            BoxedKind::classify("System.Collections.Generic.KeyValuePair<System.String, System.Int32>", "System.ValueType"),
+            BoxedKind::Struct
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::ops::AddAssign;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{FunctionID, ThreadID, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::session::Report;
+use crate::utils::{format_duration, CachedNameResolver, NameResolver, TreeNode};
+
+// Calls nested deeper than this are still timed, but not recorded in the call tree
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct CallStats {
+    pub calls: u64,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+impl AddAssign<&CallStats> for CallStats {
+    fn add_assign(&mut self, other: &Self) {
+        self.calls += other.calls;
+        self.inclusive += other.inclusive;
+        self.exclusive += other.exclusive;
+    }
+}
+
+struct Frame {
+    function_id: FunctionID,
+    entered: Instant,
+    children: Duration,
+}
+
+// Mirrors the managed callstack of a thread from enter/leave probes, and records
+// the number of calls and the wall time of every call path (caller first)
+#[derive(Default)]
+pub struct ShadowStack {
+    frames: Vec<Frame>,
+    path: Vec<FunctionID>,
+    pub paths: HashMap<Vec<FunctionID>, CallStats>,
+    // Function being unwound by an exception, between the unwind enter and leave callbacks
+    unwinding: Option<FunctionID>,
+}
+
+impl ShadowStack {
+    pub fn enter(&mut self, function_id: FunctionID, now: Instant) {
+        self.frames.push(Frame {
+            function_id,
+            entered: now,
+            children: Duration::ZERO,
+        });
+        self.path.push(function_id);
+    }
+
+    // Pops the frames above the leaving function (their leave probe was missed), then the function itself.
+    // Leaving a function that isn't on the shadow stack (entered before tracing started) is ignored.
+    pub fn leave(&mut self, function_id: FunctionID, now: Instant) {
+        if let Some(position) = self.frames.iter().rposition(|frame| frame.function_id == function_id) {
+            while self.frames.len() > position {
+                self.pop(now);
+            }
+        }
+    }
+
+    // Pops the function if it is on top of the shadow stack, for functions unwound by an exception
+    pub fn unwind(&mut self, function_id: FunctionID, now: Instant) {
+        if self.frames.last().is_some_and(|frame| frame.function_id == function_id) {
+            self.pop(now);
+        }
+    }
+
+    fn pop(&mut self, now: Instant) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = now.saturating_duration_since(frame.entered);
+
+        if self.path.len() <= MAX_DEPTH {
+            let stats = CallStats {
+                calls: 1,
+                inclusive: elapsed,
+                exclusive: elapsed.saturating_sub(frame.children),
+            };
+            match self.paths.get_mut(self.path.as_slice()) {
+                Some(path_stats) => *path_stats += &stats,
+                None => {
+                    self.paths.insert(self.path.clone(), stats);
+                }
+            }
+        }
+        self.path.pop();
+
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+}
+
+// Decides which functions get the enter/leave probes, from their full name
+pub struct HookFilter {
+    prefixes: Vec<String>,
+}
+
+impl HookFilter {
+    pub fn new(namespaces: &str) -> Self {
+        let prefixes = namespaces
+            .split(',')
+            .map(|prefix| prefix.trim().to_lowercase())
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        HookFilter { prefixes }
+    }
+
+    // No namespace means every function is traced. Matching is case insensitive, as parameters are lowercased.
+    pub fn matches(&self, method_name: &str) -> bool {
+        if self.prefixes.is_empty() {
+            return true;
+        }
+        let method_name = method_name.to_lowercase();
+        self.prefixes.iter().any(|prefix| method_name.starts_with(prefix.as_str()))
+    }
+}
+
+#[derive(Default)]
+pub struct CallTreeProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    shadow_stacks: Arc<Mutex<HashMap<ThreadID, ShadowStack>>>,
+    // Why calls can't be traced, if the probes could not be installed
+    probes_error: Option<HRESULT>,
+}
+
+impl Profiler for CallTreeProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "67E8604B-CA00-4B64-8D2A-8AE5C65875CA".to_owned(),
+            name: "Trace exact call tree".to_owned(),
+            description: "Instruments methods of the given namespaces with enter/leave probes and builds the exact call tree with call counts and inclusive/exclusive wall time. Unlike sampling, short but frequent calls are accounted for, at the cost of a high overhead on traced methods.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define(
+                    "Namespaces",
+                    "namespaces",
+                    "",
+                    "Comma separated list of namespaces (or type name prefixes) of the methods to trace. Every method is traced if empty, which can slow the application down considerably.",
+                ),
+                ProfilerParameter::define("Maximum methods", "max_methods", 50, "The maximum number of methods to list by exclusive time"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl CallTreeProfiler {
+    // The runtime only allows enter/leave hooks (COR_PRF_MONITOR_ENTERLEAVE) for profilers loaded at startup. Once attached,
+    // calls can only be traced by injecting probes in the IL of rejitted methods, which isn't implemented yet.
+    fn install_probes(&self, _hook_filter: &HookFilter) -> Result<(), HRESULT> {
+        Err(HRESULT::E_NOTIMPL)
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, shadow_stacks: Arc<Mutex<HashMap<ThreadID, ShadowStack>>>, probes_error: Option<HRESULT>) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        std::thread::sleep(Duration::from_secs(duration_seconds));
+
+        // Stop tracing while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let mut paths: HashMap<Vec<FunctionID>, CallStats> = HashMap::new();
+        for (_, shadow_stack) in shadow_stacks.lock().unwrap().drain() {
+            for (path, stats) in shadow_stack.paths {
+                *paths.entry(path).or_default() += &stats;
+            }
+        }
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("call_tree.html".to_owned());
+        report.write_line("<h2>Call Tree</h2>".to_owned());
+        if let Some(hresult) = probes_error {
+            report.write_line(format!(
+                "<p>⚠️ No call was traced: the enter/leave probes could not be installed ({hresult:?}).</p>"
+            ));
+        }
+
+        let max_methods = session_info.get_parameter::<usize>("max_methods").unwrap();
+        Self::write_methods(&mut report, &name_resolver, &paths, duration_seconds, max_methods);
+
+        let mut tree = TreeNode::build_from_sequences(&paths, 0);
+        tree.sort_by_iterative(&|a: &TreeNode<FunctionID, CallStats>, b: &TreeNode<FunctionID, CallStats>| {
+            b.value.unwrap_or_default().inclusive.cmp(&a.value.unwrap_or_default().inclusive)
+        });
+        report.write_line("<h3>Call Tree</h3>".to_owned());
+        report.write_line("<ul>".to_owned());
+        for node in &tree.children {
+            node.print_html(&mut report, &|node| Self::format_html_line(&name_resolver, node));
+        }
+        report.write_line("</ul>".to_owned());
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn write_methods(
+        report: &mut Report,
+        name_resolver: &CachedNameResolver,
+        paths: &HashMap<Vec<FunctionID>, CallStats>,
+        duration_seconds: u64,
+        max_methods: usize,
+    ) {
+        let mut methods: HashMap<FunctionID, CallStats> = HashMap::new();
+        for (path, stats) in paths {
+            let (function_id, callers) = match path.split_last() {
+                Some(split) => split,
+                None => continue,
+            };
+            let method = methods.entry(*function_id).or_default();
+            method.calls += stats.calls;
+            method.exclusive += stats.exclusive;
+            // Recursive calls are already part of the inclusive time of the outermost call
+            if !callers.contains(function_id) {
+                method.inclusive += stats.inclusive;
+            }
+        }
+
+        let calls: u64 = methods.values().map(|method| method.calls).sum();
+        report.write_line(format!(
+            "<h4>{calls} calls of {} methods traced in {duration_seconds} seconds</h4>",
+            methods.len()
+        ));
+
+        report.write_line("<h3>Methods By Exclusive Time</h3>".to_owned());
+        report.write_line("<table><tr><th>Method</th><th>Calls</th><th>Exclusive</th><th>Inclusive</th><th>Avg inclusive</th></tr>".to_owned());
+        for (function_id, method) in methods.iter().sorted_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive)).take(max_methods) {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape::encode_text(&name_resolver.get_full_method_name(*function_id, 0)),
+                method.calls,
+                format_duration(method.exclusive),
+                format_duration(method.inclusive),
+                format_duration(method.inclusive / method.calls.max(1) as u32)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, CallStats>) -> String {
+        let stats = node.value.unwrap_or_default();
+
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{} calls</span><i class=\"material-icons\">call</i></div> \
+            <div class=\"chip\"><span>{}</span><i class=\"material-icons\">radio_button_checked</i></div> \
+            <div class=\"chip\"><span>{}</span><i class=\"material-icons\">radio_button_unchecked</i></div>",
+            stats.calls,
+            format_duration(stats.inclusive),
+            format_duration(stats.exclusive)
+        )
+    }
+}
+
+impl CorProfilerCallback for CallTreeProfiler {
+    // Exceptions are unwound on the thread that threw them, so the function being unwound is kept in its shadow stack
+    fn exception_unwind_function_enter(&mut self, function_id: FunctionID) -> Result<(), HRESULT> {
+        let thread_id = self.clr().get_current_thread_id()?;
+        if let Some(shadow_stack) = self.shadow_stacks.lock().unwrap().get_mut(&thread_id) {
+            shadow_stack.unwinding = Some(function_id);
+        }
+        Ok(())
+    }
+
+    fn exception_unwind_function_leave(&mut self) -> Result<(), HRESULT> {
+        let now = Instant::now();
+        let thread_id = self.clr().get_current_thread_id()?;
+        // Functions unwound by an exception don't go through their leave probe
+        if let Some(shadow_stack) = self.shadow_stacks.lock().unwrap().get_mut(&thread_id) {
+            if let Some(function_id) = shadow_stack.unwinding.take() {
+                shadow_stack.unwind(function_id, now);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for CallTreeProfiler {}
+
+impl CorProfilerCallback3 for CallTreeProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_EXCEPTIONS | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_REJIT,
+            None,
+            profiler_info,
+            client_data,
+            client_data_length,
+        )?;
+
+        let namespaces = self.session_info().get_parameter::<String>("namespaces").unwrap();
+        let hook_filter = HookFilter::new(&namespaces);
+        if let Err(hresult) = self.install_probes(&hook_filter) {
+            warn!("Could not install enter/leave probes: {:?}", hresult);
+            self.probes_error = Some(hresult);
+        }
+
+        Ok(())
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let shadow_stacks = self.shadow_stacks.clone();
+        let probes_error = self.probes_error.take();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || CallTreeProfiler::profile(session_info, clr, shadow_stacks, probes_error));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for CallTreeProfiler {}
+impl CorProfilerCallback5 for CallTreeProfiler {}
+impl CorProfilerCallback6 for CallTreeProfiler {}
+impl CorProfilerCallback7 for CallTreeProfiler {}
+impl CorProfilerCallback8 for CallTreeProfiler {}
+impl CorProfilerCallback9 for CallTreeProfiler {}
+impl CorProfilerCallback10 for CallTreeProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallStats, ShadowStack};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn shadow_stack_records_inclusive_and_exclusive_time() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        let mut shadow_stack = ShadowStack::default();
+        shadow_stack.enter(1, at(0));
+        shadow_stack.enter(2, at(1));
+        shadow_stack.leave(2, at(4));
+        shadow_stack.enter(2, at(5));
+        // Unwound by an exception, without leave probe
+        shadow_stack.enter(3, at(6));
+        shadow_stack.unwind(3, at(7));
+        shadow_stack.leave(2, at(8));
+        shadow_stack.leave(1, at(10));
+
+        assert_eq!(
+            shadow_stack.paths[&vec![1]],
+            CallStats {
+                calls: 1,
+                inclusive: Duration::from_millis(10),
+                exclusive: Duration::from_millis(4),
+            }
+        );
+        assert_eq!(
+            shadow_stack.paths[&vec![1, 2]],
+            CallStats {
+                calls: 2,
+                inclusive: Duration::from_millis(6),
+                exclusive: Duration::from_millis(5),
+            }
+        );
+        assert_eq!(shadow_stack.paths[&vec![1, 2, 3]].calls, 1);
+    }
+
+    #[test]
+    fn missed_leave_probes_are_popped() {
+        let start = Instant::now();
+        let mut shadow_stack = ShadowStack::default();
+        shadow_stack.enter(1, start);
+        shadow_stack.enter(2, start);
+        shadow_stack.leave(1, start + Duration::from_millis(2));
+        // Leaving a function entered before tracing started is ignored
+        shadow_stack.leave(9, start + Duration::from_millis(3));
+
+        assert_eq!(shadow_stack.paths.len(), 2);
+        assert_eq!(shadow_stack.paths[&vec![1, 2]].inclusive, Duration::from_millis(2));
+    }
+}
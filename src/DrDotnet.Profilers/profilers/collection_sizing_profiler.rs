@@ -0,0 +1,414 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::ops::AddAssign;
+use std::thread;
+
+use crate::api::ffi::{ClassID, CorOpenFlags, ObjectID, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, NameResolver};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollectionKind {
+    List,
+    Dictionary,
+    HashSet,
+    Queue,
+    StringBuilder,
+}
+
+impl CollectionKind {
+    pub fn from_class_name(class_name: &str) -> Option<CollectionKind> {
+        if class_name == "System.Text.StringBuilder" {
+            return Some(CollectionKind::StringBuilder);
+        }
+        // Generic collections only, not their nested types (enumerators, entries, ...)
+        if !class_name.ends_with('>') {
+            return None;
+        }
+        match class_name.split('<').next() {
+            Some("System.Collections.Generic.List") => Some(CollectionKind::List),
+            Some("System.Collections.Generic.Dictionary") => Some(CollectionKind::Dictionary),
+            Some("System.Collections.Generic.HashSet") => Some(CollectionKind::HashSet),
+            Some("System.Collections.Generic.Queue") => Some(CollectionKind::Queue),
+            _ => None,
+        }
+    }
+
+    // Names of the backing array field, of the count field and of the count of free entries field (if any)
+    fn field_names(self) -> (&'static str, &'static str, Option<&'static str>) {
+        match self {
+            CollectionKind::List => ("_items", "_size", None),
+            CollectionKind::Dictionary | CollectionKind::HashSet => ("_entries", "_count", Some("_freeCount")),
+            CollectionKind::Queue => ("_array", "_size", None),
+            // Each chunk of a StringBuilder is a StringBuilder itself
+            CollectionKind::StringBuilder => ("m_ChunkChars", "m_ChunkLength", None),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CollectionLayout {
+    array_offset: u32,
+    count_offset: u32,
+    free_count_offset: Option<u32>,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct CollectionStats {
+    pub instances: u64,
+    pub empty: u64,
+    pub oversized: u64,
+    pub elements: u64,
+    pub capacity: u64,
+    pub wasted_bytes: u64,
+}
+
+impl CollectionStats {
+    // Sizing of a single collection holding count elements in a backing array of the given capacity and size.
+    // The wasted bytes are the share of the backing array that is not used.
+    pub fn of(count: u64, capacity: u64, array_bytes: u64, oversized_ratio: f64) -> CollectionStats {
+        let count = count.min(capacity);
+        CollectionStats {
+            instances: 1,
+            empty: (count == 0) as u64,
+            oversized: (count > 0 && capacity as f64 >= oversized_ratio * count as f64) as u64,
+            elements: count,
+            capacity,
+            wasted_bytes: (array_bytes * (capacity - count)).checked_div(capacity).unwrap_or(0),
+        }
+    }
+}
+
+impl AddAssign<&CollectionStats> for CollectionStats {
+    fn add_assign(&mut self, other: &Self) {
+        self.instances += other.instances;
+        self.empty += other.empty;
+        self.oversized += other.oversized;
+        self.elements += other.elements;
+        self.capacity += other.capacity;
+        self.wasted_bytes += other.wasted_bytes;
+    }
+}
+
+#[derive(Default)]
+pub struct CollectionSizingProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    // Collection kind of each class met during the heap walk (None for other classes)
+    kinds: HashMap<ClassID, Option<CollectionKind>>,
+    layouts: HashMap<ClassID, Option<CollectionLayout>>,
+    collections: Vec<(ObjectID, ClassID)>,
+    // Class of the first object found referencing each collection
+    holders: HashMap<ObjectID, ClassID>,
+    record_object_references: bool,
+}
+
+impl Profiler for CollectionSizingProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "8DE5233B-704E-4A4F-9F3F-75C7223EFFFA".to_owned(),
+            name: "List oversized and empty collections".to_owned(),
+            description: "Perform a full blocking garbage collection and inspect the surviving List, Dictionary, HashSet, Queue and StringBuilder instances. Compares their count to the capacity of their backing array, and lists the memory wasted by unused capacity per collection type and per type holding the collections, flagging empty and oversized collections.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define(
+                    "Oversized ratio",
+                    "oversized_ratio",
+                    4.0,
+                    "A collection is flagged as oversized when its capacity is at least this many times its count",
+                ),
+                ProfilerParameter::define("Maximum rows", "max_rows", 50, "The maximum number of rows to display per table"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl CollectionSizingProfiler {
+    fn kind(&mut self, class_id: ClassID) -> Option<CollectionKind> {
+        let clr = self.clr().clone();
+        *self
+            .kinds
+            .entry(class_id)
+            .or_insert_with(|| CollectionKind::from_class_name(&clr.get_class_name(class_id)))
+    }
+
+    // Finds the offsets of the fields of a collection by walking up the type hierarchy
+    fn find_layout(clr: &ClrProfilerInfo, class_id: ClassID, kind: CollectionKind) -> Option<CollectionLayout> {
+        let (array_name, count_name, free_count_name) = kind.field_names();
+        let (mut array_offset, mut count_offset, mut free_count_offset) = (None, None, None);
+
+        let mut class_id = class_id;
+        while class_id != 0 {
+            let class_info = clr.get_class_id_info_2(class_id).ok()?;
+            let metadata = clr.get_module_metadata(class_info.module_id, CorOpenFlags::ofRead).ok()?;
+            let class_layout = clr.get_class_layout(class_id).ok()?;
+
+            for field in &class_layout.field_offset {
+                let Ok(field_props) = metadata.get_field_props(field.ridOfField) else {
+                    continue;
+                };
+                if field_props.name == array_name {
+                    array_offset = Some(field.ulOffset);
+                } else if field_props.name == count_name {
+                    count_offset = Some(field.ulOffset);
+                } else if Some(field_props.name.as_str()) == free_count_name {
+                    free_count_offset = Some(field.ulOffset);
+                }
+            }
+
+            class_id = class_info.parent_class_id;
+        }
+
+        if free_count_name.is_some() && free_count_offset.is_none() {
+            return None;
+        }
+        Some(CollectionLayout {
+            array_offset: array_offset?,
+            count_offset: count_offset?,
+            free_count_offset,
+        })
+    }
+
+    fn sizing(&mut self, object_id: ObjectID, class_id: ClassID, oversized_ratio: f64) -> Option<CollectionStats> {
+        let kind = self.kind(class_id)?;
+        let clr = self.clr().clone();
+        let layout = (*self.layouts.entry(class_id).or_insert_with(|| Self::find_layout(&clr, class_id, kind)))?;
+
+        let read_i32 = |offset: u32| unsafe { *((object_id + offset as usize) as *const i32) };
+        let free_count = layout.free_count_offset.map_or(0, read_i32);
+        let count = (read_i32(layout.count_offset) - free_count).max(0) as u64;
+
+        let array_id = unsafe { *((object_id + layout.array_offset as usize) as *const ObjectID) };
+        let (capacity, array_bytes) = match array_id {
+            0 => (0, 0),
+            _ => (
+                clr.get_array_object_info(array_id, 1).map_or(0, |info| info.dimension_sizes[0] as u64),
+                clr.get_object_size_2(array_id).unwrap_or(0) as u64,
+            ),
+        };
+
+        Some(CollectionStats::of(count, capacity, array_bytes, oversized_ratio))
+    }
+
+    fn write_report(&mut self) {
+        let oversized_ratio = self.session_info.get_parameter::<f64>("oversized_ratio").unwrap();
+        let max_rows = self.session_info.get_parameter::<usize>("max_rows").unwrap();
+
+        let mut by_collection_type: HashMap<ClassID, CollectionStats> = HashMap::new();
+        let mut by_holder: HashMap<(ClassID, ClassID), CollectionStats> = HashMap::new();
+        for (object_id, class_id) in std::mem::take(&mut self.collections) {
+            let Some(stats) = self.sizing(object_id, class_id, oversized_ratio) else {
+                continue;
+            };
+            *by_collection_type.entry(class_id).or_default() += &stats;
+            let holder = self.holders.get(&object_id).copied().unwrap_or(0);
+            *by_holder.entry((holder, class_id)).or_default() += &stats;
+        }
+
+        let name_resolver = CachedNameResolver::new(self.clr().clone());
+        let class_name = |class_id: ClassID| match class_id {
+            0 => "(root)".to_owned(),
+            _ => html_escape::encode_text(&name_resolver.get_class_name(class_id)).into_owned(),
+        };
+
+        let mut report = self.session_info.create_report("collection_sizing.html".to_owned());
+        report.write_line("<h2>Collection Sizing</h2>".to_owned());
+        report.write_line(format!(
+            "<p>Collections are flagged as oversized when their capacity is at least {oversized_ratio} times their count. \
+            The wasted bytes are the unused part of their backing arrays. Allocation callbacks can't be enabled once attached, \
+            so collections are attributed to the type of the object holding them rather than to their allocation site.</p>"
+        ));
+
+        report.write_line("<h3>Wasted Capacity By Collection Type</h3>".to_owned());
+        report.write_line(
+            "<table><tr><th>Type</th><th>Instances</th><th>Empty</th><th>Oversized</th><th>Elements</th><th>Capacity</th><th>Wasted Bytes</th></tr>".to_owned(),
+        );
+        for (class_id, stats) in by_collection_type
+            .iter()
+            .sorted_by(|a, b| b.1.wasted_bytes.cmp(&a.1.wasted_bytes))
+            .take(max_rows)
+        {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                class_name(*class_id),
+                stats.instances,
+                stats.empty,
+                stats.oversized,
+                stats.elements,
+                stats.capacity,
+                stats.wasted_bytes
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Wasted Capacity By Holding Type</h3>".to_owned());
+        report.write_line(
+            "<table><tr><th>Holding Type</th><th>Collection Type</th><th>Instances</th><th>Empty</th><th>Oversized</th><th>Wasted Bytes</th></tr>".to_owned(),
+        );
+        for ((holder_id, class_id), stats) in by_holder.iter().sorted_by(|a, b| b.1.wasted_bytes.cmp(&a.1.wasted_bytes)).take(max_rows) {
+            let flag = if stats.empty + stats.oversized > 0 { "⚠️ " } else { "" };
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td><code>{}</code></td><td>{}</td><td>{flag}{}</td><td>{flag}{}</td><td>{}</td></tr>",
+                class_name(*holder_id),
+                class_name(*class_id),
+                stats.instances,
+                stats.empty,
+                stats.oversized,
+                stats.wasted_bytes
+            ));
+        }
+        report.write_line("</table>".to_owned());
+    }
+}
+
+impl CorProfilerCallback for CollectionSizingProfiler {
+    fn object_references(&mut self, object_id: ObjectID, class_id: ClassID, object_ref_ids: &[ObjectID]) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        if self.kind(class_id).is_some() {
+            self.collections.push((object_id, class_id));
+        }
+
+        for &reference_id in object_ref_ids {
+            if reference_id == 0 || self.holders.contains_key(&reference_id) {
+                continue;
+            }
+            let Ok(reference_class_id) = self.clr().get_class_from_object(reference_id) else {
+                continue;
+            };
+            if self.kind(reference_class_id).is_some() {
+                self.holders.insert(reference_id, class_id);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for CollectionSizingProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        // Start recording objects
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        info!("GC finished, {} collections recorded", self.collections.len());
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        self.write_report();
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for CollectionSizingProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        let clr = self.clr().clone();
+        let _ = thread::spawn(move || {
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        })
+        .join();
+
+        // Security timeout
+        detach_after_duration::<CollectionSizingProfiler>(self, 320);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for CollectionSizingProfiler {}
+impl CorProfilerCallback5 for CollectionSizingProfiler {}
+impl CorProfilerCallback6 for CollectionSizingProfiler {}
+impl CorProfilerCallback7 for CollectionSizingProfiler {}
+impl CorProfilerCallback8 for CollectionSizingProfiler {}
+impl CorProfilerCallback9 for CollectionSizingProfiler {}
+impl CorProfilerCallback10 for CollectionSizingProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{CollectionKind, CollectionStats};
+
+    #[test]
+    fn collections_are_recognized_by_class_name() {
+        assert_eq!(
+            CollectionKind::from_class_name("System.Collections.Generic.List<System.String>"),
+            Some(CollectionKind::List)
+        );
+        assert_eq!(
+            CollectionKind::from_class_name("System.Collections.Generic.Dictionary<System.Int32, System.String>"),
+            Some(CollectionKind::Dictionary)
+        );
+        assert_eq!(
+            CollectionKind::from_class_name("System.Collections.Generic.Dictionary<System.Int32, System.String>.Entry"),
+            None
+        );
+        assert_eq!(
+            CollectionKind::from_class_name("System.Text.StringBuilder"),
+            Some(CollectionKind::StringBuilder)
+        );
+    }
+
+    #[test]
+    fn sizing_flags_empty_and_oversized_collections() {
+        let oversized = CollectionStats::of(4, 16, 160, 4.0);
+        assert_eq!((oversized.empty, oversized.oversized, oversized.wasted_bytes), (0, 1, 120));
+
+        let empty = CollectionStats::of(0, 4, 56, 4.0);
+        assert_eq!((empty.empty, empty.oversized, empty.wasted_bytes), (1, 0, 56));
+
+        let full = CollectionStats::of(3, 4, 40, 4.0);
+        assert_eq!((full.empty, full.oversized, full.wasted_bytes), (0, 0, 10));
+    }
+}
@@ -0,0 +1,264 @@
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::thread;
+
+use crate::api::ffi::{ClassID, GCHandleID, ObjectID, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{AllocationStats, CachedNameResolver, NameResolver};
+
+// Entries of ConditionalWeakTables sharing the same key type and value type
+#[derive(Default)]
+pub struct DependentHandleGroup {
+    pub values: AllocationStats,
+    // Objects reachable from the values of the group, values included
+    pub reachable: AllocationStats,
+    pub truncated: bool,
+    value_ids: Vec<ObjectID>,
+}
+
+// Groups entries (key class, value class, value object, value size) by key type and value type
+pub fn group_entries(entries: &[(ClassID, ClassID, ObjectID, u64)]) -> HashMap<(ClassID, ClassID), DependentHandleGroup> {
+    let mut groups: HashMap<(ClassID, ClassID), DependentHandleGroup> = HashMap::new();
+    for &(key_class_id, value_class_id, value_id, size) in entries {
+        let group = groups.entry((key_class_id, value_class_id)).or_default();
+        group.values += &AllocationStats { count: 1, bytes: size };
+        group.value_ids.push(value_id);
+    }
+    groups
+}
+
+#[derive(Default)]
+pub struct ConditionalWeakTablesProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    // Key and value of each dependent handle
+    dependent_handles: Vec<(ObjectID, ObjectID)>,
+    record_object_references: bool,
+}
+
+impl Profiler for ConditionalWeakTablesProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "0F637811-0EEF-4550-A54D-60F7BF5B5AA6".to_owned(),
+            name: "List objects kept alive by ConditionalWeakTables".to_owned(),
+            description: "Perform a full blocking garbage collection and list the values kept alive by ConditionalWeakTables (dependent handles), grouped by the type of their key, along with the memory reachable from them.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Maximum rows", "max_rows", 50, "The maximum number of key and value types to display"),
+                ProfilerParameter::define(
+                    "Maximum objects per group",
+                    "max_objects_per_group",
+                    1000000,
+                    "The maximum number of objects to visit from the values of a group. Memory reachable from a group is truncated past this limit.",
+                ),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl ConditionalWeakTablesProfiler {
+    fn compute_groups(&self, max_objects_per_group: usize) -> HashMap<(ClassID, ClassID), DependentHandleGroup> {
+        let clr = self.clr();
+
+        let entries = self
+            .dependent_handles
+            .iter()
+            .filter_map(|&(key_id, value_id)| {
+                let key_class_id = clr.get_class_from_object(key_id).ok()?;
+                let value_class_id = clr.get_class_from_object(value_id).ok()?;
+                let size = clr.get_object_size_2(value_id).unwrap_or(0) as u64;
+                Some((key_class_id, value_class_id, value_id, size))
+            })
+            .collect_vec();
+
+        let mut groups = group_entries(&entries);
+        for group in groups.values_mut() {
+            let mut visited: HashSet<ObjectID> = HashSet::new();
+            let mut queue: VecDeque<ObjectID> = group.value_ids.iter().copied().collect();
+
+            while let Some(object_id) = queue.pop_front() {
+                if !visited.insert(object_id) {
+                    continue;
+                }
+                if visited.len() > max_objects_per_group {
+                    group.truncated = true;
+                    break;
+                }
+
+                let size = clr.get_object_size_2(object_id).unwrap_or(0) as u64;
+                group.reachable += &AllocationStats { count: 1, bytes: size };
+
+                let reference_object_ids = Vec::<ObjectID>::new();
+                // We must pass this data as a pointer for callback to mutate it with actual object references ids
+                let references_ptr_c = &reference_object_ids as *const Vec<ObjectID> as *mut std::ffi::c_void;
+                let _ = clr.enumerate_object_references(object_id, crate::utils::enum_references_callback, references_ptr_c);
+                queue.extend(reference_object_ids.into_iter().filter(|&reference_id| reference_id != 0));
+            }
+        }
+
+        groups
+    }
+
+    fn write_report(&self, groups: &HashMap<(ClassID, ClassID), DependentHandleGroup>) {
+        let max_rows = self.session_info.get_parameter::<usize>("max_rows").unwrap();
+
+        let name_resolver = CachedNameResolver::new(self.clr().clone());
+        let mut report = self.session_info.create_report("conditional_weak_tables.html".to_owned());
+        report.write_line("<h2>ConditionalWeakTables</h2>".to_owned());
+        report.write_line(format!(
+            "<p>{} ConditionalWeakTable entries were found. A value is kept alive as long as its key is, \
+            along with the memory reachable from it, even if nothing else references it.</p>",
+            self.dependent_handles.len()
+        ));
+
+        report.write_line("<table><tr><th>Key Type</th><th>Value Type</th><th>Values</th><th>Reachable From Values</th></tr>".to_owned());
+        let sorted = groups
+            .iter()
+            .sorted_by_key(|(_, group)| std::cmp::Reverse(group.reachable.bytes))
+            .take(max_rows);
+        for ((key_class_id, value_class_id), group) in sorted {
+            let truncated = if group.truncated { "⚠️ truncated " } else { "" };
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td><code>{}</code></td><td>{}</td><td>{truncated}{}</td></tr>",
+                html_escape::encode_text(&name_resolver.get_class_name(*key_class_id)),
+                html_escape::encode_text(&name_resolver.get_class_name(*value_class_id)),
+                group.values,
+                group.reachable
+            ));
+        }
+        report.write_line("</table>".to_owned());
+    }
+}
+
+impl CorProfilerCallback for ConditionalWeakTablesProfiler {}
+
+impl CorProfilerCallback2 for ConditionalWeakTablesProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        // Start recording dependent handles
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        info!("GC finished, {} dependent handles recorded", self.dependent_handles.len());
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let max_objects_per_group = self.session_info.get_parameter::<usize>("max_objects_per_group").unwrap();
+        let groups = self.compute_groups(max_objects_per_group);
+        self.write_report(&groups);
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for ConditionalWeakTablesProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        // Dependent handles are reported along with the other GC events
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        let clr = self.clr().clone();
+        let _ = thread::spawn(move || {
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        })
+        .join();
+
+        // Security timeout
+        detach_after_duration::<ConditionalWeakTablesProfiler>(self, 320);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for ConditionalWeakTablesProfiler {}
+
+impl CorProfilerCallback5 for ConditionalWeakTablesProfiler {
+    fn conditional_weak_table_element_references(
+        &mut self,
+        key_ref_ids: &[ObjectID],
+        value_ref_ids: &[ObjectID],
+        _root_ids: &[GCHandleID],
+    ) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        // Entries whose key or value has been collected are of no interest
+        let handles = key_ref_ids
+            .iter()
+            .zip(value_ref_ids)
+            .filter(|(&key_id, &value_id)| key_id != 0 && value_id != 0);
+        self.dependent_handles.extend(handles.map(|(&key_id, &value_id)| (key_id, value_id)));
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback6 for ConditionalWeakTablesProfiler {}
+impl CorProfilerCallback7 for ConditionalWeakTablesProfiler {}
+impl CorProfilerCallback8 for ConditionalWeakTablesProfiler {}
+impl CorProfilerCallback9 for ConditionalWeakTablesProfiler {}
+impl CorProfilerCallback10 for ConditionalWeakTablesProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::group_entries;
+    use crate::utils::AllocationStats;
+
+    #[test]
+    fn entries_are_grouped_by_key_and_value_types() {
+        let entries = [(1, 10, 0x100, 24), (1, 10, 0x200, 24), (1, 20, 0x300, 40), (2, 10, 0x400, 24)];
+        let groups = group_entries(&entries);
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[&(1, 10)].values, AllocationStats { count: 2, bytes: 48 });
+        assert_eq!(groups[&(1, 20)].values, AllocationStats { count: 1, bytes: 40 });
+        assert_eq!(groups[&(2, 10)].value_ids, vec![0x400]);
+    }
+}
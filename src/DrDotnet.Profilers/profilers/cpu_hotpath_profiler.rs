@@ -7,7 +7,6 @@ use crate::api::*;
 use crate::macros::*;
 use crate::profilers::*;
 use crate::rust_protobuf_protos::interop::*;
-use crate::session::Report;
 use crate::utils::{NameResolver, StackSnapshotCallbackReceiver, TreeNode};
 
 #[derive(Default)]
@@ -185,39 +184,23 @@ impl CpuHotpathProfiler {
         report.write_line("<h2>Hotpaths</h2>".to_owned());
         report.write_line(format!("<h3>{} Tree</h3>", if caller_to_callee { "Callers to Callees" } else { "Callees to Callers" }));
         report.write_line(format!("<h4>{} samples of {} roots</h4>", total_samples, tree.children.len()));
-        tree.children.iter().for_each(|node| Self::print_html(&clr, &node, &mut report, total_samples));
+        tree.children.iter().for_each(|node| node.print_html(&mut report, &|node| Self::format_html_line(&clr, node, total_samples)));
 
         if let Err(e) = clr.request_profiler_detach(3000) {
             error!("Could not detach for reason: {:?}", e);
         }
     }
 
-    fn print_html(clr: &ClrProfilerInfo, node: &TreeNode<usize, usize>, report: &mut Report, total_samples: usize) {
+    fn format_html_line(clr: &ClrProfilerInfo, node: &TreeNode<usize, usize>, total_samples: usize) -> String {
         let percentage_exclusive = 100f64 * node.value.unwrap_or_default() as f64 / total_samples as f64;
         let percentage_inclusive = 100f64 * node.get_inclusive_value() as f64 / total_samples as f64;
 
         let mut method_name: String = clr.get_full_method_name(node.key, 0);
         let escaped_class_name = html_escape::encode_text(&mut method_name);
 
-        let has_children = node.children.len() > 0;
-
-        let line: String = format!("<code>{escaped_class_name}</code> \
+        format!("<code>{escaped_class_name}</code> \
             <div class=\"chip\"><span>{percentage_inclusive:.2} %</span><i class=\"material-icons\">radio_button_checked</i></div> \
-            <div class=\"chip\"><span>{percentage_exclusive:.2} %</span><i class=\"material-icons\">radio_button_unchecked</i></div>");
-
-        if has_children {
-            report.write_line(format!(
-                "<details><summary>{line}</summary>"
-            ));
-            report.write_line(format!("<ul>"));
-            for child in &node.children {
-                Self::print_html(clr, child, report, total_samples);
-            }
-            report.write_line(format!("</ul>"));
-            report.write_line(format!("</details>"));
-        } else {
-            report.write_line(format!("<li>{line}</li>"));
-        }
+            <div class=\"chip\"><span>{percentage_exclusive:.2} %</span><i class=\"material-icons\">radio_button_unchecked</i></div>")
     }
 }
 
@@ -263,3 +246,4 @@ impl CorProfilerCallback6 for CpuHotpathProfiler {}
 impl CorProfilerCallback7 for CpuHotpathProfiler {}
 impl CorProfilerCallback8 for CpuHotpathProfiler {}
 impl CorProfilerCallback9 for CpuHotpathProfiler {}
+impl CorProfilerCallback10 for CpuHotpathProfiler {}
@@ -0,0 +1,290 @@
+use itertools::Itertools;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::thread;
+use thousands::Separable;
+
+use crate::api::ffi::{ClassID, CorElementType, ObjectID, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, NameResolver};
+
+const PREVIEW_BYTES: usize = 16;
+
+// Copies of a single array content
+pub struct ArrayDuplicates {
+    pub class_id: ClassID,
+    pub length: u64,
+    pub copies: u64,
+    // Size of a single copy, header included
+    pub object_bytes: u64,
+    pub preview: Vec<u8>,
+}
+
+impl ArrayDuplicates {
+    pub fn wasted_bytes(&self) -> u64 {
+        self.copies.saturating_sub(1) * self.object_bytes
+    }
+}
+
+#[derive(Default)]
+pub struct DuplicatedArraysProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    // Size of the elements of each array class met, or None if it isn't a single dimension array of primitives
+    element_sizes: HashMap<ClassID, Option<usize>>,
+    array_object_ids: Vec<(ObjectID, ClassID)>,
+    record_object_references: bool,
+}
+
+impl Profiler for DuplicatedArraysProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "491AC509-56EE-4368-9EB0-B6880376A07D".to_owned(),
+            name: "List duplicated arrays".to_owned(),
+            description: "Perform a full blocking garbage collection and list the arrays of primitives (byte[], char[], int[], ...) sharing the same content, along with the number of copies and the bytes wasted by duplicates.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Top", "top_count", 100, "The number of duplicated array contents to list in the report."),
+                ProfilerParameter::define(
+                    "Minimum length",
+                    "min_length",
+                    16,
+                    "The minimum number of elements of an array for its content to be compared. Tiny arrays are cheap to duplicate but costly to hash.",
+                ),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl DuplicatedArraysProfiler {
+    // Size in bytes of an array element, for primitive element types only
+    pub fn element_size(element_type: &CorElementType) -> Option<usize> {
+        match element_type {
+            CorElementType::ELEMENT_TYPE_BOOLEAN | CorElementType::ELEMENT_TYPE_I1 | CorElementType::ELEMENT_TYPE_U1 => Some(1),
+            CorElementType::ELEMENT_TYPE_CHAR | CorElementType::ELEMENT_TYPE_I2 | CorElementType::ELEMENT_TYPE_U2 => Some(2),
+            CorElementType::ELEMENT_TYPE_I4 | CorElementType::ELEMENT_TYPE_U4 | CorElementType::ELEMENT_TYPE_R4 => Some(4),
+            CorElementType::ELEMENT_TYPE_I8 | CorElementType::ELEMENT_TYPE_U8 | CorElementType::ELEMENT_TYPE_R8 => Some(8),
+            CorElementType::ELEMENT_TYPE_I | CorElementType::ELEMENT_TYPE_U => Some(std::mem::size_of::<usize>()),
+            _ => None,
+        }
+    }
+
+    pub fn hex_preview(bytes: &[u8], total_bytes: usize) -> String {
+        let hex = bytes.iter().map(|byte| format!("{byte:02x}")).join(" ");
+        if total_bytes > bytes.len() {
+            hex + " ..."
+        } else {
+            hex
+        }
+    }
+
+    fn element_size_of_class(&mut self, class_id: ClassID) -> Option<usize> {
+        let clr = self.clr().clone();
+        *self.element_sizes.entry(class_id).or_insert_with(|| match clr.is_array_class(class_id) {
+            Ok(array_class_info) if array_class_info.rank == 1 => Self::element_size(&array_class_info.element_type),
+            _ => None,
+        })
+    }
+
+    // Groups the recorded arrays by class and content. Contents are compared through a 64 bits hash, along with their length.
+    fn group_duplicates(&self) -> Vec<ArrayDuplicates> {
+        let clr = self.clr();
+        let min_length = self.session_info.get_parameter::<u64>("min_length").unwrap();
+
+        let mut duplicates: HashMap<(ClassID, u64, u64), ArrayDuplicates> = HashMap::new();
+        for &(object_id, class_id) in &self.array_object_ids {
+            let Some(element_size) = self.element_sizes.get(&class_id).copied().flatten() else {
+                continue;
+            };
+            let Ok(array_info) = clr.get_array_object_info(object_id, 1) else {
+                continue;
+            };
+            let length = array_info.dimension_sizes[0] as u64;
+            if length < min_length || array_info.data.is_null() {
+                continue;
+            }
+
+            let content = unsafe { std::slice::from_raw_parts(array_info.data, length as usize * element_size) };
+            let mut hasher = DefaultHasher::new();
+            hasher.write(content);
+
+            duplicates
+                .entry((class_id, length, hasher.finish()))
+                .or_insert_with(|| ArrayDuplicates {
+                    class_id,
+                    length,
+                    copies: 0,
+                    object_bytes: clr.get_object_size_2(object_id).unwrap_or(0) as u64,
+                    preview: content[..content.len().min(PREVIEW_BYTES)].to_vec(),
+                })
+                .copies += 1;
+        }
+
+        duplicates.into_values().filter(|duplicates| duplicates.copies > 1).collect()
+    }
+
+    fn write_report(&self, duplicates: &[ArrayDuplicates]) {
+        let top_count = self.session_info.get_parameter::<usize>("top_count").unwrap();
+        let total_wasted_bytes: u64 = duplicates.iter().map(ArrayDuplicates::wasted_bytes).sum();
+
+        let name_resolver = CachedNameResolver::new(self.clr().clone());
+        let mut report = self.session_info.create_report("duplicated_arrays.html".to_owned());
+        report.write_line("<h2>Duplicated Arrays</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{} array contents are duplicated, wasting {} B</h4>",
+            duplicates.len().separate_with_commas(),
+            total_wasted_bytes.separate_with_commas()
+        ));
+
+        report.write_line("<table><tr><th>Copies</th><th>Type</th><th>Length</th><th>Wasted Bytes</th><th>Content</th></tr>".to_owned());
+        for duplicate in duplicates
+            .iter()
+            .sorted_by_key(|duplicate| std::cmp::Reverse(duplicate.wasted_bytes()))
+            .take(top_count)
+        {
+            let element_size = self.element_sizes.get(&duplicate.class_id).copied().flatten().unwrap_or(1);
+            report.write_line(format!(
+                "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+                duplicate.copies.separate_with_commas(),
+                html_escape::encode_text(&name_resolver.get_class_name(duplicate.class_id)),
+                duplicate.length.separate_with_commas(),
+                duplicate.wasted_bytes().separate_with_commas(),
+                Self::hex_preview(&duplicate.preview, duplicate.length as usize * element_size)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+    }
+}
+
+impl CorProfilerCallback for DuplicatedArraysProfiler {
+    fn object_references(&mut self, object_id: ObjectID, class_id: ClassID, _object_ref_ids: &[ObjectID]) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        if self.element_size_of_class(class_id).is_some() {
+            self.array_object_ids.push((object_id, class_id));
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for DuplicatedArraysProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        // Start recording objects
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        info!("GC finished, {} arrays recorded", self.array_object_ids.len());
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        // Array contents are read while the runtime is still suspended, so that objects can neither move nor be collected
+        let duplicates = self.group_duplicates();
+        self.write_report(&duplicates);
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for DuplicatedArraysProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        let clr = self.clr().clone();
+        let _ = thread::spawn(move || {
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        })
+        .join();
+
+        // Security timeout
+        detach_after_duration::<DuplicatedArraysProfiler>(self, 120);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for DuplicatedArraysProfiler {}
+impl CorProfilerCallback5 for DuplicatedArraysProfiler {}
+impl CorProfilerCallback6 for DuplicatedArraysProfiler {}
+impl CorProfilerCallback7 for DuplicatedArraysProfiler {}
+impl CorProfilerCallback8 for DuplicatedArraysProfiler {}
+impl CorProfilerCallback9 for DuplicatedArraysProfiler {}
+impl CorProfilerCallback10 for DuplicatedArraysProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArrayDuplicates, DuplicatedArraysProfiler};
+    use crate::api::ffi::CorElementType;
+
+    #[test]
+    fn only_primitive_elements_are_compared() {
+        assert_eq!(DuplicatedArraysProfiler::element_size(&CorElementType::ELEMENT_TYPE_U1), Some(1));
+        assert_eq!(DuplicatedArraysProfiler::element_size(&CorElementType::ELEMENT_TYPE_CHAR), Some(2));
+        assert_eq!(DuplicatedArraysProfiler::element_size(&CorElementType::ELEMENT_TYPE_R8), Some(8));
+        assert_eq!(DuplicatedArraysProfiler::element_size(&CorElementType::ELEMENT_TYPE_CLASS), None);
+    }
+
+    #[test]
+    fn all_copies_but_one_are_wasted() {
+        let duplicates = ArrayDuplicates {
+            class_id: 0,
+            length: 4,
+            copies: 3,
+            object_bytes: 32,
+            preview: vec![0x0a, 0xff],
+        };
+        assert_eq!(duplicates.wasted_bytes(), 64);
+        assert_eq!(DuplicatedArraysProfiler::hex_preview(&duplicates.preview, 4), "0a ff ...");
+    }
+}
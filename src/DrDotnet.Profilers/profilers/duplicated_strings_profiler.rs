@@ -235,6 +235,7 @@ impl CorProfilerCallback6 for DuplicatedStringsProfiler {}
 impl CorProfilerCallback7 for DuplicatedStringsProfiler {}
 impl CorProfilerCallback8 for DuplicatedStringsProfiler {}
 impl CorProfilerCallback9 for DuplicatedStringsProfiler {}
+impl CorProfilerCallback10 for DuplicatedStringsProfiler {}
 
 #[cfg(test)]
 mod tests {
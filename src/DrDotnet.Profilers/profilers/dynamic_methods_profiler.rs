@@ -0,0 +1,381 @@
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{FunctionID, ThreadID, HRESULT, LPCBYTE};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{format_duration, format_size, CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver, TreeNode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DynamicMethodKind {
+    CompiledExpression,
+    CompiledRegex,
+    Serializer,
+    Lightweight,
+}
+
+impl DynamicMethodKind {
+    // Guesses what generated a dynamic method from the name it was given
+    pub fn from_name(name: &str) -> DynamicMethodKind {
+        if name.starts_with("lambda_method") {
+            DynamicMethodKind::CompiledExpression
+        } else if name.starts_with("Regex") {
+            DynamicMethodKind::CompiledRegex
+        } else if name.contains("Serializ") || name.contains("Deserializ") {
+            DynamicMethodKind::Serializer
+        } else {
+            DynamicMethodKind::Lightweight
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            DynamicMethodKind::CompiledExpression => "Compiled Expression",
+            DynamicMethodKind::CompiledRegex => "Compiled Regex",
+            DynamicMethodKind::Serializer => "Serializer",
+            DynamicMethodKind::Lightweight => "Other (DynamicMethod)",
+        }
+    }
+}
+
+// Returns the first frame of a callstack (leaf first) that belongs to the application rather than to the framework
+pub fn call_site(frames: &[String]) -> Option<&String> {
+    frames
+        .iter()
+        .find(|frame| !frame.starts_with("System.") && !frame.starts_with("Microsoft."))
+        .or(frames.first())
+}
+
+pub struct DynamicMethod {
+    pub kind: DynamicMethodKind,
+    // Callstack the dynamic method was first compiled from, leaf first
+    pub method_ids: Vec<FunctionID>,
+    pub jit_time: Duration,
+    pub code_size: u64,
+    pub unloaded: bool,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct DynamicMethodStats {
+    pub compiled: u64,
+    pub unloaded: u64,
+    pub jit_time: Duration,
+    pub code_size: u64,
+}
+
+impl DynamicMethodStats {
+    pub fn record(&mut self, method: &DynamicMethod) {
+        self.compiled += 1;
+        if method.unloaded {
+            self.unloaded += 1;
+        }
+        self.jit_time += method.jit_time;
+        self.code_size += method.code_size;
+    }
+
+    pub fn alive(&self) -> u64 {
+        self.compiled - self.unloaded
+    }
+}
+
+#[derive(Default)]
+pub struct DynamicMethods {
+    methods: Vec<DynamicMethod>,
+    // Index of the dynamic method currently behind each function id, as ids are reused once methods are unloaded
+    live: HashMap<FunctionID, usize>,
+    pending: HashMap<(ThreadID, FunctionID), (Instant, DynamicMethodKind, Vec<FunctionID>)>,
+}
+
+#[derive(Default)]
+pub struct DynamicMethodsProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    unloads_monitored: bool,
+    dynamic_methods: Arc<Mutex<DynamicMethods>>,
+}
+
+impl Profiler for DynamicMethodsProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "56140F98-27EA-47DD-82B6-04B3ABEDC947".to_owned(),
+            name: "List dynamic methods".to_owned(),
+            description: "Counts the dynamic methods (compiled expressions, compiled regexes, serializers, DynamicMethod) compiled and unloaded during the session, along with their JIT time, code size and the callstack they were compiled from. Flags call sites that keep generating new dynamic methods.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define(
+                    "Leak threshold",
+                    "leak_threshold",
+                    10,
+                    "The number of dynamic methods a call site must compile during the session to be flagged",
+                ),
+                ProfilerParameter::define("Maximum call sites", "max_call_sites", 50, "The maximum number of call sites to display"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl DynamicMethodsProfiler {
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, dynamic_methods: Arc<Mutex<DynamicMethods>>, unloads_monitored: bool) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(duration_seconds));
+
+        // Stop monitoring compilations while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let leak_threshold = session_info.get_parameter::<u64>("leak_threshold").unwrap();
+        let max_call_sites = session_info.get_parameter::<usize>("max_call_sites").unwrap();
+        let dynamic_methods = dynamic_methods.lock().unwrap();
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut total = DynamicMethodStats::default();
+        let mut by_kind: HashMap<DynamicMethodKind, DynamicMethodStats> = HashMap::new();
+        let mut by_call_site: HashMap<String, (DynamicMethodStats, BTreeSet<DynamicMethodKind>)> = HashMap::new();
+        let mut callstacks = TreeNode::new(0);
+        for method in &dynamic_methods.methods {
+            total.record(method);
+            by_kind.entry(method.kind).or_default().record(method);
+
+            let frames = method
+                .method_ids
+                .iter()
+                .map(|method_id| name_resolver.get_full_method_name(*method_id, 0))
+                .collect_vec();
+            let call_site = call_site(&frames).cloned().unwrap_or_else(|| "Unknown".to_owned());
+            let (stats, kinds) = by_call_site.entry(call_site).or_default();
+            stats.record(method);
+            kinds.insert(method.kind);
+
+            *callstacks.add_sequence(method.method_ids.iter().copied()).value.get_or_insert(0) += 1;
+        }
+
+        let mut report = session_info.create_report("dynamic_methods.html".to_owned());
+        report.write_line("<h2>Dynamic Methods</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{} dynamic methods compiled, {} unloaded, for {} of JIT time and {} of code</h4>",
+            total.compiled,
+            total.unloaded,
+            format_duration(total.jit_time),
+            format_size(total.code_size)
+        ));
+        report.write_line(
+            "<p>Dynamic methods are compiled the first time they are invoked, usually right after being created. \
+            Their callstack is the one of this first invocation.</p>"
+                .to_owned(),
+        );
+        if !unloads_monitored {
+            report.write_line("<p>⚠️ The runtime doesn't support monitoring dynamic method unloads, every dynamic method is considered alive.</p>".to_owned());
+        }
+
+        report.write_line("<h3>By Kind</h3>".to_owned());
+        report.write_line("<table><tr><th>Kind</th><th>Compiled</th><th>Unloaded</th><th>JIT Time</th><th>Code Size</th></tr>".to_owned());
+        for (kind, stats) in by_kind.iter().sorted_by_key(|(kind, _)| **kind) {
+            report.write_line(format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                kind.title(),
+                stats.compiled,
+                stats.unloaded,
+                format_duration(stats.jit_time),
+                format_size(stats.code_size)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Call Sites</h3>".to_owned());
+        report.write_line(format!(
+            "<p>Call sites compiling {leak_threshold} dynamic methods or more during the session are flagged: \
+            dynamic methods are meant to be created once and cached.</p>"
+        ));
+        report.write_line("<table><tr><th>Call Site</th><th>Kinds</th><th>Compiled</th><th>Alive</th><th>JIT Time</th><th>Code Size</th></tr>".to_owned());
+        let sorted = by_call_site.iter().sorted_by_key(|(_, (stats, _))| std::cmp::Reverse(stats.compiled));
+        for (call_site, (stats, kinds)) in sorted.take(max_call_sites) {
+            let flag = if stats.compiled >= leak_threshold { "⚠️ " } else { "" };
+            report.write_line(format!(
+                "<tr><td>{flag}<code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape::encode_text(call_site),
+                kinds.iter().map(|kind| kind.title()).join(", "),
+                stats.compiled,
+                stats.alive(),
+                format_duration(stats.jit_time),
+                format_size(stats.code_size)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Callstacks</h3>".to_owned());
+        callstacks.sort_by_iterative(&|a: &TreeNode<FunctionID, u64>, b: &TreeNode<FunctionID, u64>| b.get_inclusive_value().cmp(&a.get_inclusive_value()));
+        report.write_line("<ul>".to_owned());
+        for node in &callstacks.children {
+            node.print_html(&mut report, &|node| Self::format_html_line(&name_resolver, node));
+        }
+        report.write_line("</ul>".to_owned());
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, u64>) -> String {
+        let inclusive = node.get_inclusive_value();
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">build</i></div>"
+        )
+    }
+}
+
+impl CorProfilerCallback for DynamicMethodsProfiler {}
+impl CorProfilerCallback2 for DynamicMethodsProfiler {}
+
+impl CorProfilerCallback3 for DynamicMethodsProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        // Dynamic method compilations are reported along with regular JIT compilations
+        let events = ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_JIT_COMPILATION | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT;
+
+        self.unloads_monitored = true;
+        if self
+            .init(
+                events,
+                Some(ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_DYNAMIC_FUNCTION_UNLOADS),
+                profiler_info.clone(),
+                client_data,
+                client_data_length,
+            )
+            .is_err()
+        {
+            warn!("Could not monitor dynamic method unloads, falling back to compilations only");
+            self.unloads_monitored = false;
+            self.init(events, None, profiler_info, client_data, client_data_length)?;
+        }
+
+        Ok(())
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let dynamic_methods = self.dynamic_methods.clone();
+        let unloads_monitored = self.unloads_monitored;
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || DynamicMethodsProfiler::profile(session_info, clr, dynamic_methods, unloads_monitored));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for DynamicMethodsProfiler {}
+impl CorProfilerCallback5 for DynamicMethodsProfiler {}
+impl CorProfilerCallback6 for DynamicMethodsProfiler {}
+impl CorProfilerCallback7 for DynamicMethodsProfiler {}
+
+impl CorProfilerCallback8 for DynamicMethodsProfiler {
+    fn dynamic_method_jit_compilation_started(
+        &mut self,
+        function_id: FunctionID,
+        _is_safe_to_block: bool,
+        _il_header: LPCBYTE,
+        _il_header_length: u32,
+    ) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let Ok(thread_id) = clr.get_current_thread_id() else {
+            return Ok(());
+        };
+        let kind = clr
+            .get_dynamic_function_info(function_id)
+            .map_or(DynamicMethodKind::Lightweight, |info| DynamicMethodKind::from_name(&info.name));
+
+        // We are on the compiling thread, so the current callstack is the one invoking the dynamic method
+        let method_ids = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(clr);
+
+        let mut dynamic_methods = self.dynamic_methods.lock().unwrap();
+        dynamic_methods.pending.insert((thread_id, function_id), (Instant::now(), kind, method_ids));
+
+        Ok(())
+    }
+
+    fn dynamic_method_jit_compilation_finished(&mut self, function_id: FunctionID, _hr_status: HRESULT, _is_safe_to_block: bool) -> Result<(), HRESULT> {
+        let clr = self.clr();
+        let Ok(thread_id) = clr.get_current_thread_id() else {
+            return Ok(());
+        };
+        let code_size = clr
+            .get_code_info_2(function_id)
+            .map_or(0, |code_infos| code_infos.iter().map(|code_info| code_info.size as u64).sum());
+
+        let mut dynamic_methods = self.dynamic_methods.lock().unwrap();
+        if let Some((started, kind, method_ids)) = dynamic_methods.pending.remove(&(thread_id, function_id)) {
+            let index = dynamic_methods.methods.len();
+            dynamic_methods.methods.push(DynamicMethod {
+                kind,
+                method_ids,
+                jit_time: started.elapsed(),
+                code_size,
+                unloaded: false,
+            });
+            dynamic_methods.live.insert(function_id, index);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback9 for DynamicMethodsProfiler {
+    fn dynamic_method_unloaded(&mut self, function_id: FunctionID) -> Result<(), HRESULT> {
+        let mut dynamic_methods = self.dynamic_methods.lock().unwrap();
+        if let Some(index) = dynamic_methods.live.remove(&function_id) {
+            dynamic_methods.methods[index].unloaded = true;
+        }
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback10 for DynamicMethodsProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{call_site, DynamicMethodKind};
+
+    #[test]
+    fn dynamic_methods_are_classified_by_name() {
+        assert_eq!(DynamicMethodKind::from_name("lambda_method42"), DynamicMethodKind::CompiledExpression);
+        assert_eq!(DynamicMethodKind::from_name("Regex1_Scan"), DynamicMethodKind::CompiledRegex);
+        assert_eq!(DynamicMethodKind::from_name("ReadCustomerFromXmlDeserializer"), DynamicMethodKind::Serializer);
+        assert_eq!(DynamicMethodKind::from_name("IL_STUB_PInvoke"), DynamicMethodKind::Lightweight);
+    }
+
+    #[test]
+    fn call_site_is_the_first_application_frame() {
+        let frames = vec![
+            "System.Linq.Expressions.Interpreter.LightLambda.Run".to_owned(),
+            "MyApp.OrdersController.Get".to_owned(),
+            "Microsoft.AspNetCore.Mvc.Infrastructure.ActionMethodExecutor.Execute".to_owned(),
+        ];
+        assert_eq!(call_site(&frames).map(String::as_str), Some("MyApp.OrdersController.Get"));
+        assert_eq!(
+            call_site(&frames[..1]).map(String::as_str),
+            Some("System.Linq.Expressions.Interpreter.LightLambda.Run")
+        );
+        assert_eq!(call_site(&[]), None);
+    }
+}
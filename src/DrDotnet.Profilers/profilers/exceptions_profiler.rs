@@ -1,47 +1,754 @@
 use dashmap::DashMap;
+use itertools::Itertools;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
+use crate::api::ffi::{ClassID, CorOpenFlags, FunctionID, ObjectID, ThreadID};
 use crate::api::*;
 use crate::macros::*;
 use crate::profilers::*;
-use crate::utils::NameResolver;
+use crate::session::Report;
+use crate::utils::{CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver, TreeNode};
+
+// Limits the number of throw-site stacks captured per second, so that a flood of exceptions
+// doesn't stall the application with stack snapshots.
+#[derive(Default)]
+pub struct ThrowSiteBudget {
+    max_per_second: u64,
+    second: u64,
+    taken: u64,
+}
+
+impl ThrowSiteBudget {
+    pub fn new(max_per_second: u64) -> Self {
+        ThrowSiteBudget {
+            max_per_second,
+            ..ThrowSiteBudget::default()
+        }
+    }
+
+    // Returns true if a stack can be captured for an exception thrown at the given second of the session
+    pub fn try_take(&mut self, second: u64) -> bool {
+        if second != self.second {
+            self.second = second;
+            self.taken = 0;
+        }
+        if self.taken >= self.max_per_second {
+            return false;
+        }
+        self.taken += 1;
+        true
+    }
+}
+
+// Throw-site callstacks (throwing method first) per exception type
+#[derive(Default)]
+pub struct ThrowSites {
+    budget: ThrowSiteBudget,
+    trees: HashMap<String, TreeNode<FunctionID, u64>>,
+    skipped: u64,
+}
+
+// Exceptions in an inner exception chain are not followed deeper than this
+const MAX_INNER_EXCEPTIONS: usize = 8;
+// Messages are truncated to this number of characters before being grouped
+const MAX_MESSAGE_LENGTH: usize = 300;
+
+// Replaces the variable parts of an exception message (numbers, hexadecimal values and GUIDs)
+// with placeholders, so that messages of the same kind are grouped under a single template.
+pub fn normalize_message(message: &str) -> String {
+    let chars: Vec<char> = message.chars().take(MAX_MESSAGE_LENGTH).collect();
+    let mut template = String::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        // Placeholders only replace whole tokens, so that "Int32" or "utf8" are left untouched
+        let token_start = i == 0 || !(chars[i - 1].is_alphanumeric() || chars[i - 1] == '_');
+        if token_start && is_guid(&chars[i..]) {
+            template.push_str("{guid}");
+            i += 36;
+        } else if token_start && chars[i].is_ascii_digit() {
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || ((chars[i] == '.' || chars[i] == ',') && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())))
+            {
+                i += 1;
+            }
+            template.push_str("{n}");
+        } else {
+            template.push(chars[i]);
+            i += 1;
+        }
+    }
+    template
+}
+
+// Returns the template of an exception message. Exceptions created without a message have a null _message field,
+// and their Message property builds a default text from the exception type instead.
+pub fn message_template(message: Option<&str>, class_name: &str) -> String {
+    match message {
+        Some(message) => normalize_message(message),
+        None => format!("Exception of type '{class_name}' was thrown."),
+    }
+}
+
+fn is_guid(chars: &[char]) -> bool {
+    chars.len() >= 36
+        && chars[..36]
+            .iter()
+            .enumerate()
+            .all(|(i, c)| if matches!(i, 8 | 13 | 18 | 23) { *c == '-' } else { c.is_ascii_hexdigit() })
+        && chars.get(36).is_none_or(|c| !c.is_alphanumeric())
+}
+
+// Offsets of the System.Exception fields read from thrown exceptions
+#[derive(Clone, Copy)]
+pub struct ExceptionLayout {
+    message_offset: u32,
+    inner_exception_offset: u32,
+}
+
+// Message templates occurrences per exception type, and inner exception chains per exception type
+#[derive(Default)]
+pub struct ExceptionMessages {
+    templates: HashMap<String, HashMap<String, u64>>,
+    inner_chains: HashMap<String, HashMap<Vec<String>, u64>>,
+}
+
+// An exception travelling up the stack of a thread, from its throw to its catcher
+pub struct InFlightException {
+    name: String,
+    throw_method: FunctionID,
+    thrown_at: Duration,
+    frames_unwound: u64,
+    catcher_found: bool,
+}
+
+// A catch block being executed, which swallows the exception unless it throws again
+pub struct ActiveCatcher {
+    function_id: FunctionID,
+    rethrown: bool,
+}
+
+#[derive(Default)]
+pub struct ThreadFlow {
+    in_flight: Option<InFlightException>,
+    catcher: Option<ActiveCatcher>,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct CatchSite {
+    pub caught: u64,
+    pub swallowed: u64,
+    pub frames_unwound: u64,
+    pub types: HashMap<String, u64>,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct ThrowCatchPair {
+    pub count: u64,
+    pub unwind_time: Duration,
+    pub frames_unwound: u64,
+}
+
+// Follows exceptions from their throw to their catcher, on each thread
+#[derive(Default)]
+pub struct ExceptionFlow {
+    threads: HashMap<ThreadID, ThreadFlow>,
+    pub catch_sites: HashMap<FunctionID, CatchSite>,
+    pub pairs: HashMap<(FunctionID, FunctionID), ThrowCatchPair>,
+    pub unhandled: HashMap<String, u64>,
+}
+
+impl ExceptionFlow {
+    pub fn thrown(&mut self, thread_id: ThreadID, name: String, now: Duration) {
+        let thread = self.threads.entry(thread_id).or_default();
+        // Throwing from a catch block means the caught exception is rethrown or wrapped
+        if let Some(catcher) = thread.catcher.as_mut() {
+            catcher.rethrown = true;
+        }
+        // A previous exception still looking for its catcher is never caught when another one is thrown outside of a catch block
+        if let Some(previous) = thread.in_flight.take() {
+            if !previous.catcher_found && thread.catcher.is_none() {
+                *self.unhandled.entry(previous.name).or_insert(0) += 1;
+            }
+        }
+        thread.in_flight = Some(InFlightException {
+            name,
+            throw_method: 0,
+            thrown_at: now,
+            frames_unwound: 0,
+            catcher_found: false,
+        });
+    }
+
+    pub fn search_function_enter(&mut self, thread_id: ThreadID, function_id: FunctionID) {
+        // The search phase starts from the throwing method
+        if let Some(exception) = self.in_flight(thread_id) {
+            if exception.throw_method == 0 {
+                exception.throw_method = function_id;
+            }
+        }
+    }
+
+    pub fn catcher_found(&mut self, thread_id: ThreadID) {
+        if let Some(exception) = self.in_flight(thread_id) {
+            exception.catcher_found = true;
+        }
+    }
+
+    pub fn unwind_function_leave(&mut self, thread_id: ThreadID) {
+        if let Some(exception) = self.in_flight(thread_id) {
+            exception.frames_unwound += 1;
+        }
+    }
+
+    pub fn catcher_enter(&mut self, thread_id: ThreadID, function_id: FunctionID, now: Duration) {
+        self.catcher_leave(thread_id);
+
+        let thread = self.threads.entry(thread_id).or_default();
+        thread.catcher = Some(ActiveCatcher { function_id, rethrown: false });
+        let exception = thread.in_flight.take().unwrap_or(InFlightException {
+            name: "unknown".to_owned(),
+            throw_method: 0,
+            thrown_at: now,
+            frames_unwound: 0,
+            catcher_found: true,
+        });
+
+        let catch_site = self.catch_sites.entry(function_id).or_default();
+        catch_site.caught += 1;
+        catch_site.frames_unwound += exception.frames_unwound;
+        *catch_site.types.entry(exception.name).or_insert(0) += 1;
+
+        let pair = self.pairs.entry((exception.throw_method, function_id)).or_default();
+        pair.count += 1;
+        pair.unwind_time += now.saturating_sub(exception.thrown_at);
+        pair.frames_unwound += exception.frames_unwound;
+    }
+
+    pub fn catcher_leave(&mut self, thread_id: ThreadID) {
+        let catcher = match self.threads.get_mut(&thread_id).and_then(|thread| thread.catcher.take()) {
+            Some(catcher) => catcher,
+            None => return,
+        };
+        if !catcher.rethrown {
+            self.catch_sites.entry(catcher.function_id).or_default().swallowed += 1;
+        }
+    }
+
+    // Settles exceptions and catch blocks still in progress at the end of the session.
+    // Exceptions for which no managed catcher was found are considered unhandled.
+    pub fn finish(&mut self) {
+        let thread_ids: Vec<ThreadID> = self.threads.keys().copied().collect();
+        for thread_id in thread_ids {
+            self.catcher_leave(thread_id);
+            if let Some(exception) = self.threads.get_mut(&thread_id).and_then(|thread| thread.in_flight.take()) {
+                if !exception.catcher_found {
+                    *self.unhandled.entry(exception.name).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    fn in_flight(&mut self, thread_id: ThreadID) -> Option<&mut InFlightException> {
+        self.threads.get_mut(&thread_id).and_then(|thread| thread.in_flight.as_mut())
+    }
+}
+
+// Number of exceptions thrown per second of the session, per exception type
+#[derive(Default)]
+pub struct ExceptionTimeline {
+    pub per_type: HashMap<String, Vec<u64>>,
+}
+
+// Consecutive seconds during which exceptions were thrown well above the usual rate
+#[derive(Debug, PartialEq)]
+pub struct Burst {
+    pub start: usize,
+    pub end: usize,
+    pub peak: u64,
+    pub dominant_type: String,
+}
+
+impl ExceptionTimeline {
+    pub fn record(&mut self, name: &str, second: usize) {
+        let per_second = self.per_type.entry(name.to_owned()).or_default();
+        if per_second.len() <= second {
+            per_second.resize(second + 1, 0);
+        }
+        per_second[second] += 1;
+    }
+
+    pub fn count(&self, name: &str, second: usize) -> u64 {
+        self.per_type.get(name).and_then(|per_second| per_second.get(second)).copied().unwrap_or(0)
+    }
+
+    pub fn totals(&self, seconds: usize) -> Vec<u64> {
+        (0..seconds)
+            .map(|second| self.per_type.keys().map(|name| self.count(name, second)).sum())
+            .collect()
+    }
+
+    // The baseline is the median rate, so that bursts don't raise it.
+    // A second is part of a burst when its rate exceeds the baseline (at least 1/s) times the multiplier.
+    pub fn detect_bursts(&self, seconds: usize, multiplier: f64) -> Vec<Burst> {
+        let totals = self.totals(seconds);
+        let baseline = totals.iter().sorted().nth(totals.len() / 2).copied().unwrap_or(0).max(1);
+        let threshold = baseline as f64 * multiplier;
+
+        let mut bursts = Vec::new();
+        let mut second = 0;
+        while second < totals.len() {
+            if totals[second] as f64 <= threshold {
+                second += 1;
+                continue;
+            }
+
+            let start = second;
+            while second < totals.len() && totals[second] as f64 > threshold {
+                second += 1;
+            }
+            let dominant_type = self
+                .per_type
+                .keys()
+                .max_by_key(|name| (start..second).map(|second| self.count(name, second)).sum::<u64>())
+                .cloned()
+                .unwrap_or_default();
+            bursts.push(Burst {
+                start,
+                end: second - 1,
+                peak: totals[start..second].iter().copied().max().unwrap_or(0),
+                dominant_type,
+            });
+        }
+        bursts
+    }
+}
 
 #[derive(Default)]
 pub struct ExceptionsProfiler {
     clr_profiler_info: ClrProfilerInfo,
     session_info: SessionInfo,
     exceptions: DashMap<String, AtomicIsize>,
+    capture_stacks: bool,
+    start: Option<Instant>,
+    throw_sites: Arc<Mutex<ThrowSites>>,
+    capture_messages: bool,
+    // Resolved from the first exception whose layout can be read, as exceptions are thrown from several threads at once
+    exception_layout: OnceLock<ExceptionLayout>,
+    string_layout: Option<StringLayout>,
+    messages: Arc<Mutex<ExceptionMessages>>,
+    flow: Arc<Mutex<ExceptionFlow>>,
+    timeline: Arc<Mutex<ExceptionTimeline>>,
 }
 
 impl Profiler for ExceptionsProfiler {
     profiler_getset!();
 
     fn profiler_info() -> ProfilerInfo {
-        return ProfilerInfo {
+        ProfilerInfo {
             uuid: "805A308B-061C-47F3-9B30-F785C3186E82".to_owned(),
             name: "Count thrown exceptions by type".to_owned(),
             description: "Lists occuring exceptions by importance.\nHandled exceptions are also listed.".to_owned(),
-            parameters: vec![ProfilerParameter {
-                name: "Duration".to_owned(),
-                key: "duration".to_owned(),
-                description: "The profiling duration in seconds".to_owned(),
-                type_: ParameterType::INT.into(),
-                value: "10".to_owned(),
-                ..std::default::Default::default()
-            }],
+            parameters: vec![
+                ProfilerParameter {
+                    name: "Duration".to_owned(),
+                    key: "duration".to_owned(),
+                    description: "The profiling duration in seconds".to_owned(),
+                    type_: ParameterType::INT.into(),
+                    value: "10".to_owned(),
+                    ..std::default::Default::default()
+                },
+                ProfilerParameter::define(
+                    "Capture throw sites",
+                    "capture_stacks",
+                    true,
+                    "If set, the callstack of the throwing thread is captured and throw sites are listed per exception type in a tree view",
+                ),
+                ProfilerParameter::define(
+                    "Maximum stacks per second",
+                    "max_stacks_per_second",
+                    100,
+                    "The maximum number of throw-site callstacks captured per second. Exceptions thrown above this budget are still counted.",
+                ),
+                ProfilerParameter::define(
+                    "Capture messages",
+                    "capture_messages",
+                    true,
+                    "If set, the message and inner exceptions of thrown exceptions are read and grouped per exception type, with numbers and GUIDs normalized",
+                ),
+                ProfilerParameter::define(
+                    "Maximum catch sites",
+                    "max_catch_sites",
+                    20,
+                    "The maximum number of catch sites and throw/catch pairs to display",
+                ),
+                ProfilerParameter::define(
+                    "Burst multiplier",
+                    "burst_multiplier",
+                    5.0,
+                    "A second is reported as part of a burst when its exception rate exceeds the median rate of the session times this multiplier",
+                ),
+            ],
             ..std::default::Default::default()
+        }
+    }
+}
+
+impl ExceptionsProfiler {
+    fn profile(
+        session_info: SessionInfo,
+        clr: ClrProfilerInfo,
+        throw_sites: Arc<Mutex<ThrowSites>>,
+        messages: Arc<Mutex<ExceptionMessages>>,
+        flow: Arc<Mutex<ExceptionFlow>>,
+        timeline: Arc<Mutex<ExceptionTimeline>>,
+    ) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(duration_seconds));
+
+        // Stop monitoring exceptions while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        // Method names must be resolved before detaching
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("exceptions.html".to_owned());
+
+        let burst_multiplier = session_info.get_parameter::<f64>("burst_multiplier").unwrap();
+        Self::write_timeline(&mut report, &timeline.lock().unwrap(), duration_seconds as usize, burst_multiplier);
+
+        let max_catch_sites = session_info.get_parameter::<usize>("max_catch_sites").unwrap();
+        let mut flow = flow.lock().unwrap();
+        flow.finish();
+        Self::write_flow(&mut report, &name_resolver, &flow, max_catch_sites);
+
+        if session_info.get_parameter::<bool>("capture_messages").unwrap() {
+            Self::write_messages(&mut report, &messages.lock().unwrap());
+        }
+        if session_info.get_parameter::<bool>("capture_stacks").unwrap() {
+            Self::write_throw_sites(&mut report, &name_resolver, &mut throw_sites.lock().unwrap());
+        }
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn write_timeline(report: &mut Report, timeline: &ExceptionTimeline, seconds: usize, burst_multiplier: f64) {
+        // Types thrown the most get their own column, others are summed up
+        const MAX_TYPE_COLUMNS: usize = 5;
+
+        let bursts = timeline.detect_bursts(seconds, burst_multiplier);
+        report.write_line("<h2>Exception Timeline</h2>".to_owned());
+        if bursts.is_empty() {
+            report.write_line(format!("<h4>No burst above {burst_multiplier} times the median rate</h4>"));
+        }
+        for burst in &bursts {
+            report.write_line(format!(
+                "<p>⚠️ Burst from second {} to second {} (peak {}/s), mostly <code>{}</code></p>",
+                burst.start,
+                burst.end,
+                burst.peak,
+                html_escape::encode_text(&burst.dominant_type)
+            ));
+        }
+
+        let columns: Vec<&String> = timeline
+            .per_type
+            .iter()
+            .sorted_by_key(|(_, per_second)| std::cmp::Reverse(per_second.iter().sum::<u64>()))
+            .map(|(name, _)| name)
+            .take(MAX_TYPE_COLUMNS)
+            .collect();
+        let headers = columns
+            .iter()
+            .map(|name| format!("<th><code>{}</code></th>", html_escape::encode_text(name)))
+            .join("");
+        report.write_line(format!("<table><tr><th>Second</th><th>Total</th>{headers}<th>Other</th><th></th></tr>"));
+
+        let totals = timeline.totals(seconds);
+        for (second, total) in totals.iter().enumerate() {
+            let counts: Vec<u64> = columns.iter().map(|name| timeline.count(name, second)).collect();
+            let other = total - counts.iter().sum::<u64>();
+            let cells = counts.iter().map(|count| format!("<td>{count}</td>")).join("");
+            let flag = if bursts.iter().any(|burst| (burst.start..=burst.end).contains(&second)) {
+                "⚠️ burst"
+            } else {
+                ""
+            };
+            report.write_line(format!("<tr><td>{second}</td><td>{total}</td>{cells}<td>{other}</td><td>{flag}</td></tr>"));
+        }
+        report.write_line("</table>".to_owned());
+    }
+
+    fn write_flow(report: &mut Report, name_resolver: &CachedNameResolver, flow: &ExceptionFlow, max_catch_sites: usize) {
+        let method_name = |function_id: FunctionID| match function_id {
+            0 => "unknown".to_owned(),
+            _ => html_escape::encode_text(&name_resolver.get_full_method_name(function_id, 0)).into_owned(),
+        };
+
+        let caught: u64 = flow.catch_sites.values().map(|catch_site| catch_site.caught).sum();
+        let unhandled: u64 = flow.unhandled.values().sum();
+        report.write_line("<h2>Exception Flow</h2>".to_owned());
+        report.write_line(format!("<h4>{caught} exceptions caught, {unhandled} without a managed catcher</h4>"));
+        for (exception_name, count) in flow.unhandled.iter().sorted_by_key(|(_, count)| std::cmp::Reverse(**count)) {
+            report.write_line(format!(
+                "<p>⚠️ <code>{}</code> unhandled {count} times</p>",
+                html_escape::encode_text(exception_name)
+            ));
+        }
+
+        report.write_line("<h3>Catch Sites Swallowing The Most Exceptions</h3>".to_owned());
+        report
+            .write_line("<table><tr><th>Catch method</th><th>Swallowed</th><th>Caught</th><th>Avg frames unwound</th><th>Exception types</th></tr>".to_owned());
+        for (function_id, catch_site) in flow
+            .catch_sites
+            .iter()
+            .sorted_by_key(|(_, catch_site)| std::cmp::Reverse((catch_site.swallowed, catch_site.caught)))
+            .take(max_catch_sites)
+        {
+            let types = catch_site
+                .types
+                .iter()
+                .sorted_by_key(|(_, count)| std::cmp::Reverse(**count))
+                .map(|(name, count)| format!("<code>{}</code> ({count})", html_escape::encode_text(name)))
+                .join(", ");
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{:.1}</td><td>{types}</td></tr>",
+                method_name(*function_id),
+                catch_site.swallowed,
+                catch_site.caught,
+                catch_site.frames_unwound as f64 / catch_site.caught.max(1) as f64
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Costliest Throw / Catch Pairs</h3>".to_owned());
+        report.write_line(
+            "<table><tr><th>Throw method</th><th>Catch method</th><th>Count</th><th>Total unwind time</th><th>Avg unwind time</th><th>Avg frames unwound</th></tr>"
+                .to_owned(),
+        );
+        for ((throw_method, catch_method), pair) in flow
+            .pairs
+            .iter()
+            .sorted_by_key(|(_, pair)| std::cmp::Reverse(pair.unwind_time))
+            .take(max_catch_sites)
+        {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td><code>{}</code></td><td>{}</td><td>{:.3} ms</td><td>{:.3} ms</td><td>{:.1}</td></tr>",
+                method_name(*throw_method),
+                method_name(*catch_method),
+                pair.count,
+                pair.unwind_time.as_secs_f64() * 1000.0,
+                pair.unwind_time.as_secs_f64() * 1000.0 / pair.count.max(1) as f64,
+                pair.frames_unwound as f64 / pair.count.max(1) as f64
+            ));
+        }
+        report.write_line("</table>".to_owned());
+    }
+
+    fn write_messages(report: &mut Report, messages: &ExceptionMessages) {
+        report.write_line("<h2>Exception Messages</h2>".to_owned());
+        for (exception_name, templates) in messages
+            .templates
+            .iter()
+            .sorted_by_key(|(_, templates)| std::cmp::Reverse(templates.values().sum::<u64>()))
+        {
+            report.write_line(format!("<h3><code>{}</code></h3>", html_escape::encode_text(exception_name)));
+            report.write_line("<table><tr><th>Message</th><th>Count</th></tr>".to_owned());
+            for (template, count) in templates.iter().sorted_by_key(|(_, count)| std::cmp::Reverse(**count)) {
+                report.write_line(format!("<tr><td><code>{}</code></td><td>{count}</td></tr>", html_escape::encode_text(template)));
+            }
+            report.write_line("</table>".to_owned());
+
+            if let Some(inner_chains) = messages.inner_chains.get(exception_name) {
+                report.write_line("<details><summary>Inner exceptions</summary><ul>".to_owned());
+                for (chain, count) in inner_chains.iter().sorted_by_key(|(_, count)| std::cmp::Reverse(**count)) {
+                    let chain = chain
+                        .iter()
+                        .map(|inner| format!("<code>{}</code>", html_escape::encode_text(inner)))
+                        .join(" ➜ ");
+                    report.write_line(format!(
+                        "<li>{chain} <div class=\"chip\"><span>{count}</span><i class=\"material-icons\">radio_button_checked</i></div></li>"
+                    ));
+                }
+                report.write_line("</ul></details>".to_owned());
+            }
+        }
+    }
+
+    fn write_throw_sites(report: &mut Report, name_resolver: &CachedNameResolver, throw_sites: &mut ThrowSites) {
+        let compare = &|a: &TreeNode<FunctionID, u64>, b: &TreeNode<FunctionID, u64>| b.get_inclusive_value().cmp(&a.get_inclusive_value());
+        for tree in throw_sites.trees.values_mut() {
+            tree.sort_by_iterative(compare);
+        }
+
+        report.write_line("<h2>Exception Throw Sites</h2>".to_owned());
+        if throw_sites.skipped > 0 {
+            report.write_line(format!(
+                "<p>⚠️ The stacks of {} exceptions were not captured because the sampling budget was exceeded. Shares are relative to captured stacks.</p>",
+                throw_sites.skipped
+            ));
+        }
+
+        for (exception_name, tree) in throw_sites.trees.iter().sorted_by(|a, b| compare(a.1, b.1)) {
+            let total = tree.get_inclusive_value();
+            report.write_line(format!(
+                "<details><summary><code>{}</code> \
+                <div class=\"chip\"><span>{total}</span><i class=\"material-icons\">radio_button_checked</i></div></summary>",
+                html_escape::encode_text(exception_name)
+            ));
+            report.write_line("<ul>".to_owned());
+            for node in &tree.children {
+                node.print_html(report, &|node| Self::format_html_line(name_resolver, node, total));
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, u64>, total: u64) -> String {
+        let inclusive = node.get_inclusive_value();
+        let share = 100.0 * inclusive as f64 / total.max(1) as f64;
+
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">radio_button_checked</i></div> \
+            <div class=\"chip\"><span>{share:.1}%</span><i class=\"material-icons\">pie_chart</i></div>"
+        )
+    }
+
+    // Finds the offsets of the System.Exception message and inner exception fields by walking up the type hierarchy
+    fn find_exception_layout(clr: &ClrProfilerInfo, class_id: ClassID) -> Option<ExceptionLayout> {
+        let mut class_id = class_id;
+        while class_id != 0 {
+            let class_info = clr.get_class_id_info_2(class_id).ok()?;
+            let metadata = clr.get_module_metadata(class_info.module_id, CorOpenFlags::ofRead).ok()?;
+            let class_layout = clr.get_class_layout(class_id).ok()?;
+
+            let mut message_offset = None;
+            let mut inner_exception_offset = None;
+            for field in &class_layout.field_offset {
+                match metadata.get_field_props(field.ridOfField).map(|field_props| field_props.name).as_deref() {
+                    Ok("_message") => message_offset = Some(field.ulOffset),
+                    Ok("_innerException") => inner_exception_offset = Some(field.ulOffset),
+                    _ => {}
+                }
+            }
+            if let (Some(message_offset), Some(inner_exception_offset)) = (message_offset, inner_exception_offset) {
+                return Some(ExceptionLayout {
+                    message_offset,
+                    inner_exception_offset,
+                });
+            }
+
+            class_id = class_info.parent_class_id;
+        }
+        None
+    }
+
+    // Reads the object reference stored in a field of an object
+    fn read_reference(object_id: ObjectID, offset: u32) -> ObjectID {
+        unsafe { *((object_id + offset as usize) as *const ObjectID) }
+    }
+
+    fn record_messages(&self, name: &str, object_id: ObjectID, class_id: ClassID) {
+        // Failures aren't cached, so that the layout is looked up again from the next exception thrown
+        let exception_layout = match self.exception_layout.get() {
+            Some(exception_layout) => *exception_layout,
+            None => match Self::find_exception_layout(self.clr(), class_id) {
+                Some(exception_layout) => *self.exception_layout.get_or_init(|| exception_layout),
+                None => return,
+            },
         };
+        let Some(string_layout) = self.string_layout.as_ref() else {
+            return;
+        };
+
+        let read_message = |object_id: ObjectID, class_name: &str| {
+            let message = match Self::read_reference(object_id, exception_layout.message_offset) {
+                0 => None,
+                message_id => Some(ClrProfilerInfo::get_string_value(string_layout, &message_id)),
+            };
+            message_template(message.as_deref(), class_name)
+        };
+
+        let template = read_message(object_id, name);
+
+        let mut inner_chain = Vec::new();
+        let mut inner_id = Self::read_reference(object_id, exception_layout.inner_exception_offset);
+        while inner_id != 0 && inner_chain.len() < MAX_INNER_EXCEPTIONS {
+            let inner_name = match self.clr().get_class_from_object(inner_id) {
+                Ok(inner_class_id) => self.clr().get_class_name(inner_class_id),
+                Err(_) => break,
+            };
+            inner_chain.push(format!("{inner_name}: {}", read_message(inner_id, &inner_name)));
+            inner_id = Self::read_reference(inner_id, exception_layout.inner_exception_offset);
+        }
+
+        let mut messages = self.messages.lock().unwrap();
+        *messages.templates.entry(name.to_owned()).or_default().entry(template).or_insert(0) += 1;
+        if !inner_chain.is_empty() {
+            *messages.inner_chains.entry(name.to_owned()).or_default().entry(inner_chain).or_insert(0) += 1;
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.start.map(|start| start.elapsed()).unwrap_or_default()
+    }
+
+    fn current_thread_id(&self) -> ThreadID {
+        self.clr().get_current_thread_id().unwrap_or(0)
+    }
+
+    fn record_throw_site(&self, name: String) {
+        let second = self.elapsed().as_secs();
+        {
+            let mut throw_sites = self.throw_sites.lock().unwrap();
+            if !throw_sites.budget.try_take(second) {
+                throw_sites.skipped += 1;
+                return;
+            }
+        }
+
+        // We are on the throwing thread, so the current callstack is the throw-site callstack
+        let method_ids = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(self.clr().clone());
+
+        let mut throw_sites = self.throw_sites.lock().unwrap();
+        let tree = throw_sites.trees.entry(name).or_insert_with(|| TreeNode::new(0));
+        let node = tree.add_sequence(method_ids);
+        *node.value.get_or_insert(0) += 1;
     }
 }
 
 impl CorProfilerCallback for ExceptionsProfiler {
     fn exception_thrown(&mut self, thrown_object_id: ffi::ObjectID) -> Result<(), ffi::HRESULT> {
         let clr = self.clr();
-        let name = match clr.get_class_from_object(thrown_object_id) {
-            Ok(class_id) => clr.get_class_name(class_id),
-            _ => "unknown".to_owned(),
+        let class_id = clr.get_class_from_object(thrown_object_id).ok();
+        let name = match class_id {
+            Some(class_id) => clr.get_class_name(class_id),
+            None => "unknown".to_owned(),
         };
 
+        if let (true, Some(class_id)) = (self.capture_messages, class_id) {
+            self.record_messages(&name, thrown_object_id, class_id);
+        }
+
+        if self.capture_stacks {
+            self.record_throw_site(name.clone());
+        }
+
+        let (thread_id, now) = (self.current_thread_id(), self.elapsed());
+        self.flow.lock().unwrap().thrown(thread_id, name.clone(), now);
+        self.timeline.lock().unwrap().record(&name, now.as_secs() as usize);
+
         let key = name;
         match self.exceptions.get_mut(&key) {
             Some(pair) => {
@@ -54,6 +761,36 @@ impl CorProfilerCallback for ExceptionsProfiler {
 
         Ok(())
     }
+
+    fn exception_search_function_enter(&mut self, function_id: FunctionID) -> Result<(), ffi::HRESULT> {
+        let thread_id = self.current_thread_id();
+        self.flow.lock().unwrap().search_function_enter(thread_id, function_id);
+        Ok(())
+    }
+
+    fn exception_search_catcher_found(&mut self, _function_id: FunctionID) -> Result<(), ffi::HRESULT> {
+        let thread_id = self.current_thread_id();
+        self.flow.lock().unwrap().catcher_found(thread_id);
+        Ok(())
+    }
+
+    fn exception_unwind_function_leave(&mut self) -> Result<(), ffi::HRESULT> {
+        let thread_id = self.current_thread_id();
+        self.flow.lock().unwrap().unwind_function_leave(thread_id);
+        Ok(())
+    }
+
+    fn exception_catcher_enter(&mut self, function_id: FunctionID, _object_id: ObjectID) -> Result<(), ffi::HRESULT> {
+        let (thread_id, now) = (self.current_thread_id(), self.elapsed());
+        self.flow.lock().unwrap().catcher_enter(thread_id, function_id, now);
+        Ok(())
+    }
+
+    fn exception_catcher_leave(&mut self) -> Result<(), ffi::HRESULT> {
+        let thread_id = self.current_thread_id();
+        self.flow.lock().unwrap().catcher_leave(thread_id);
+        Ok(())
+    }
 }
 
 impl CorProfilerCallback2 for ExceptionsProfiler {}
@@ -65,29 +802,44 @@ impl CorProfilerCallback3 for ExceptionsProfiler {
         client_data: *const std::os::raw::c_void,
         client_data_length: u32,
     ) -> Result<(), ffi::HRESULT> {
+        self.start = Some(Instant::now());
         self.init(
-            ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_EXCEPTIONS,
+            ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_EXCEPTIONS | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
             None,
             profiler_info,
             client_data,
             client_data_length,
-        )
+        )?;
+
+        self.capture_stacks = self.session_info().get_parameter::<bool>("capture_stacks").unwrap();
+        let max_stacks_per_second = self.session_info().get_parameter::<u64>("max_stacks_per_second").unwrap();
+        self.throw_sites.lock().unwrap().budget = ThrowSiteBudget::new(max_stacks_per_second);
+
+        self.capture_messages = self.session_info().get_parameter::<bool>("capture_messages").unwrap();
+        self.string_layout = self.clr().get_string_layout_2().ok();
+
+        Ok(())
     }
 
     fn profiler_attach_complete(&mut self) -> Result<(), ffi::HRESULT> {
-        let duration_seconds = self.session_info().get_parameter::<u64>("duration").unwrap();
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let throw_sites = self.throw_sites.clone();
+        let messages = self.messages.clone();
+        let flow = self.flow.clone();
+        let timeline = self.timeline.clone();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || ExceptionsProfiler::profile(session_info, clr, throw_sites, messages, flow, timeline));
 
-        detach_after_duration::<ExceptionsProfiler>(&self, duration_seconds);
         Ok(())
     }
 
     fn profiler_detach_succeeded(&mut self) -> Result<(), ffi::HRESULT> {
         let mut report = self.session_info().create_report("summary.md".to_owned());
 
-        report.write_line(format!("# Exceptions Report"));
-        report.write_line(format!("## Exceptions by Occurrences"));
-
-        use itertools::Itertools;
+        report.write_line("# Exceptions Report".to_owned());
+        report.write_line("## Exceptions by Occurrences".to_owned());
 
         for exception in self.exceptions.iter().sorted_by_key(|x| -x.value().load(Ordering::Relaxed)) {
             report.write_line(format!("- {}: {}", exception.key(), exception.value().load(Ordering::Relaxed)));
@@ -107,3 +859,108 @@ impl CorProfilerCallback6 for ExceptionsProfiler {}
 impl CorProfilerCallback7 for ExceptionsProfiler {}
 impl CorProfilerCallback8 for ExceptionsProfiler {}
 impl CorProfilerCallback9 for ExceptionsProfiler {}
+impl CorProfilerCallback10 for ExceptionsProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{message_template, normalize_message, Burst, ExceptionFlow, ExceptionTimeline, ThrowSiteBudget};
+    use std::time::Duration;
+
+    #[test]
+    fn throw_site_budget_resets_every_second() {
+        let mut budget = ThrowSiteBudget::new(2);
+        assert!(budget.try_take(0));
+        assert!(budget.try_take(0));
+        assert!(!budget.try_take(0));
+        assert!(budget.try_take(1));
+    }
+
+    #[test]
+    fn messages_are_normalized() {
+        assert_eq!(
+            normalize_message("Order 12345 not found for tenant 3f2504e0-4f89-11d3-9a0c-0305e82c3301 after 2.5s"),
+            "Order {n} not found for tenant {guid} after {n}"
+        );
+        assert_eq!(
+            normalize_message("Value was either too large or too small for an Int32 at 0x7ffe12"),
+            "Value was either too large or too small for an Int32 at {n}"
+        );
+    }
+
+    #[test]
+    fn null_messages_fall_back_to_the_default_text() {
+        assert_eq!(
+            message_template(None, "App.OrderNotFoundException"),
+            "Exception of type 'App.OrderNotFoundException' was thrown."
+        );
+        assert_eq!(
+            message_template(Some("Order 42 not found"), "App.OrderNotFoundException"),
+            "Order {n} not found"
+        );
+    }
+
+    #[test]
+    fn exceptions_are_followed_from_throw_to_catch() {
+        let mut flow = ExceptionFlow::default();
+
+        // Thrown in method 1, unwound through method 2 and caught and swallowed in method 3
+        flow.thrown(7, "System.TimeoutException".to_owned(), Duration::from_millis(10));
+        flow.search_function_enter(7, 1);
+        flow.search_function_enter(7, 2);
+        flow.catcher_found(7);
+        flow.unwind_function_leave(7);
+        flow.unwind_function_leave(7);
+        flow.catcher_enter(7, 3, Duration::from_millis(14));
+        flow.catcher_leave(7);
+
+        // Caught in method 3 and rethrown, then never caught
+        flow.thrown(7, "System.TimeoutException".to_owned(), Duration::from_millis(20));
+        flow.catcher_enter(7, 3, Duration::from_millis(21));
+        flow.thrown(7, "System.TimeoutException".to_owned(), Duration::from_millis(22));
+        flow.catcher_leave(7);
+        flow.finish();
+
+        let catch_site = &flow.catch_sites[&3];
+        assert_eq!(catch_site.caught, 2);
+        assert_eq!(catch_site.swallowed, 1);
+        assert_eq!(catch_site.frames_unwound, 2);
+        assert_eq!(flow.pairs[&(1, 3)].unwind_time, Duration::from_millis(4));
+        assert_eq!(flow.unhandled["System.TimeoutException"], 1);
+    }
+
+    #[test]
+    fn exceptions_replaced_before_being_caught_are_unhandled() {
+        let mut flow = ExceptionFlow::default();
+
+        flow.thrown(7, "System.TimeoutException".to_owned(), Duration::from_millis(10));
+        flow.thrown(7, "System.InvalidOperationException".to_owned(), Duration::from_millis(20));
+        flow.finish();
+
+        assert_eq!(flow.unhandled["System.TimeoutException"], 1);
+        assert_eq!(flow.unhandled["System.InvalidOperationException"], 1);
+    }
+
+    #[test]
+    fn bursts_are_detected_above_baseline() {
+        let mut timeline = ExceptionTimeline::default();
+        for second in 0..10 {
+            timeline.record("System.IO.IOException", second);
+        }
+        for second in 4..6 {
+            for _ in 0..20 {
+                timeline.record("System.Threading.Tasks.TaskCanceledException", second);
+            }
+        }
+
+        let bursts = timeline.detect_bursts(10, 5.0);
+        assert_eq!(
+            bursts,
+            vec![Burst {
+                start: 4,
+                end: 5,
+                peak: 21,
+                dominant_type: "System.Threading.Tasks.TaskCanceledException".to_owned(),
+            }]
+        );
+    }
+}
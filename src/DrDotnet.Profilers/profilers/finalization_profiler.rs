@@ -0,0 +1,217 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{ClassID, CorOpenFlags, ObjectID, COR_PRF_FINALIZER_FLAGS, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, NameResolver};
+
+const DISPOSABLE_INTERFACES: &[&str] = &["System.IDisposable", "System.IAsyncDisposable"];
+
+// Tokens are prefixed with the metadata table they belong to
+const TOKEN_TYPE_MASK: u32 = 0xFF000000;
+const MD_TYPE_REF: u32 = 0x01000000;
+const MD_TYPE_DEF: u32 = 0x02000000;
+
+// Number of objects of a type queued for finalization, per second of the session
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct TypeFinalizations {
+    pub per_second: Vec<u64>,
+}
+
+impl TypeFinalizations {
+    pub fn record(&mut self, second: usize) {
+        if self.per_second.len() <= second {
+            self.per_second.resize(second + 1, 0);
+        }
+        self.per_second[second] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.per_second.iter().sum()
+    }
+
+    pub fn peak(&self) -> u64 {
+        self.per_second.iter().copied().max().unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+pub struct FinalizationProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    start: Option<Instant>,
+    finalizations: Arc<Mutex<HashMap<ClassID, TypeFinalizations>>>,
+}
+
+impl Profiler for FinalizationProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "8BFD2BD7-3377-4353-B202-8DF3D065BB13".to_owned(),
+            name: "List objects queued for finalization".to_owned(),
+            description: "Counts objects queued for finalization per type along with their rate over time, and flags types implementing IDisposable whose instances still reach the finalizer queue, which usually means a missed Dispose().".to_owned(),
+            parameters: vec![ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds")],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl FinalizationProfiler {
+    // Returns true if the type or one of its base types implements IDisposable or IAsyncDisposable
+    fn is_disposable(clr: &ClrProfilerInfo, class_id: ClassID) -> bool {
+        let mut class_id = class_id;
+        while class_id != 0 {
+            let class_info = match clr.get_class_id_info_2(class_id) {
+                Ok(class_info) => class_info,
+                Err(_) => return false,
+            };
+
+            if let Ok(metadata) = clr.get_module_metadata(class_info.module_id, CorOpenFlags::ofRead) {
+                let interfaces = metadata.enum_interface_impls(class_info.token).unwrap_or_default();
+                let implements_disposable = interfaces.into_iter().any(|interface| {
+                    let interface_name = match interface & TOKEN_TYPE_MASK {
+                        MD_TYPE_REF => metadata.get_type_ref_props(interface).ok(),
+                        MD_TYPE_DEF => metadata.get_type_def_props(interface).ok().map(|type_props| type_props.name),
+                        // Generic interfaces (TypeSpec) can't be IDisposable
+                        _ => None,
+                    };
+                    interface_name.is_some_and(|name| DISPOSABLE_INTERFACES.contains(&name.as_str()))
+                });
+                if implements_disposable {
+                    return true;
+                }
+            }
+
+            class_id = class_info.parent_class_id;
+        }
+        false
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, finalizations: Arc<Mutex<HashMap<ClassID, TypeFinalizations>>>) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        std::thread::sleep(Duration::from_secs(duration_seconds));
+
+        // Stop monitoring finalizations while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let finalizations = finalizations.lock().unwrap();
+        let total: u64 = finalizations.values().map(|type_finalizations| type_finalizations.total()).sum();
+        let seconds = duration_seconds.max(1) as f64;
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("finalization.html".to_owned());
+        report.write_line("<h2>Finalization</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{total} objects of {} types queued for finalization in {duration_seconds} seconds</h4>",
+            finalizations.len()
+        ));
+
+        report.write_line("<table><tr><th>Type</th><th>Queued</th><th>Average / s</th><th>Peak / s</th><th></th></tr>".to_owned());
+        for (class_id, type_finalizations) in finalizations.iter().sorted_by(|a, b| b.1.total().cmp(&a.1.total())) {
+            let class_name = name_resolver.get_class_name(*class_id);
+            let flag = if Self::is_disposable(&clr, *class_id) {
+                "⚠️ IDisposable: Dispose() was probably not called"
+            } else {
+                ""
+            };
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{:.2}</td><td>{}</td><td>{flag}</td></tr>",
+                html_escape::encode_text(&class_name),
+                type_finalizations.total(),
+                type_finalizations.total() as f64 / seconds,
+                type_finalizations.peak()
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Timeline</h3>".to_owned());
+        report.write_line("<table><tr><th>Second</th><th>Queued</th></tr>".to_owned());
+        for second in 0..duration_seconds as usize {
+            let queued: u64 = finalizations
+                .values()
+                .map(|type_finalizations| type_finalizations.per_second.get(second).copied().unwrap_or(0))
+                .sum();
+            report.write_line(format!("<tr><td>{second}</td><td>{queued}</td></tr>"));
+        }
+        report.write_line("</table>".to_owned());
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+}
+
+impl CorProfilerCallback for FinalizationProfiler {}
+
+impl CorProfilerCallback2 for FinalizationProfiler {
+    fn finalizeable_object_queued(&mut self, _finalizer_flags: COR_PRF_FINALIZER_FLAGS, object_id: ObjectID) -> Result<(), HRESULT> {
+        let class_id = match self.clr().get_class_from_object(object_id) {
+            Ok(class_id) => class_id,
+            Err(_) => return Ok(()),
+        };
+        let second = self.start.map(|start| start.elapsed().as_secs()).unwrap_or(0) as usize;
+
+        self.finalizations.lock().unwrap().entry(class_id).or_default().record(second);
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for FinalizationProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.start = Some(Instant::now());
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let finalizations = self.finalizations.clone();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || FinalizationProfiler::profile(session_info, clr, finalizations));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for FinalizationProfiler {}
+impl CorProfilerCallback5 for FinalizationProfiler {}
+impl CorProfilerCallback6 for FinalizationProfiler {}
+impl CorProfilerCallback7 for FinalizationProfiler {}
+impl CorProfilerCallback8 for FinalizationProfiler {}
+impl CorProfilerCallback9 for FinalizationProfiler {}
+impl CorProfilerCallback10 for FinalizationProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::TypeFinalizations;
+
+    #[test]
+    fn finalizations_are_counted_per_second() {
+        let mut type_finalizations = TypeFinalizations::default();
+        type_finalizations.record(0);
+        type_finalizations.record(3);
+        type_finalizations.record(3);
+
+        assert_eq!(type_finalizations.per_second, vec![1, 0, 0, 2]);
+        assert_eq!(type_finalizations.total(), 3);
+        assert_eq!(type_finalizations.peak(), 2);
+    }
+}
@@ -0,0 +1,303 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::api::ffi::{ClassID, ObjectID, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{format_size, AllocationStats, CachedNameResolver, NameResolver};
+
+// Objects are laid out contiguously in the ranges reported by the GC, each one aligned on the pointer size
+pub fn aligned_object_size(size: usize) -> usize {
+    let alignment = std::mem::size_of::<usize>();
+    size.div_ceil(alignment) * alignment
+}
+
+// Surviving objects of a GC (or of a type), split between the ones the GC moved and the ones it left in place
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Movement {
+    pub moved: AllocationStats,
+    pub kept: AllocationStats,
+}
+
+impl Movement {
+    pub fn record(&mut self, moved: bool, size: u64) {
+        let stats = AllocationStats { count: 1, bytes: size };
+        if moved {
+            self.moved += &stats;
+        } else {
+            self.kept += &stats;
+        }
+    }
+
+    pub fn add(&mut self, other: &Movement) {
+        self.moved += &other.moved;
+        self.kept += &other.kept;
+    }
+
+    pub fn moved_share(&self) -> f64 {
+        let total = self.moved.bytes + self.kept.bytes;
+        if total == 0 {
+            0.0
+        } else {
+            self.moved.bytes as f64 * 100.0 / total as f64
+        }
+    }
+}
+
+pub struct GcMovement {
+    pub generation: usize,
+    pub movement: Movement,
+}
+
+#[derive(Default)]
+pub struct Compactions {
+    gcs: Vec<GcMovement>,
+    by_class: HashMap<ClassID, Movement>,
+    // Ranges of surviving objects reported during the current GC, as (start after the GC, length, moved)
+    pending_ranges: Vec<(ObjectID, usize, bool)>,
+}
+
+#[derive(Default)]
+pub struct GCCompactionProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    compactions: Arc<Mutex<Compactions>>,
+}
+
+impl Profiler for GCCompactionProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "648B754F-AF96-4BBE-BD11-037338421FDD".to_owned(),
+            name: "List objects moved by GCs".to_owned(),
+            description: "Measures, for each garbage collection, how many surviving objects and bytes were moved by compaction versus kept in place, broken down by generation and by type. Shows which types make compacting GCs expensive.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define("Maximum types", "max_types", 50, "The maximum number of types to display"),
+                ProfilerParameter::define("Maximum GCs", "max_gcs", 100, "The maximum number of garbage collections to list individually"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl GCCompactionProfiler {
+    // Walks the objects of a contiguous range, recording each of them. The GC must be finished so that objects are at their new location.
+    fn record_range(clr: &ClrProfilerInfo, compactions: &mut Compactions, start: ObjectID, length: usize, moved: bool) {
+        let Some(gc) = compactions.gcs.last_mut() else {
+            return;
+        };
+
+        let mut range_movement = Movement::default();
+        let mut classes: Vec<(ClassID, u64)> = Vec::new();
+        let mut object_id = start;
+        while object_id < start + length {
+            let size = clr.get_object_size_2(object_id).unwrap_or(0);
+            if size == 0 {
+                break;
+            }
+            if let Ok(class_id) = clr.get_class_from_object(object_id) {
+                classes.push((class_id, size as u64));
+            }
+            range_movement.record(moved, size as u64);
+            object_id += aligned_object_size(size);
+        }
+
+        gc.movement.add(&range_movement);
+        for (class_id, size) in classes {
+            compactions.by_class.entry(class_id).or_default().record(moved, size);
+        }
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, compactions: Arc<Mutex<Compactions>>) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(duration_seconds));
+
+        // Stop receiving GC events while the report is being written
+        if let Err(hresult) = clr.set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let max_types = session_info.get_parameter::<usize>("max_types").unwrap();
+        let max_gcs = session_info.get_parameter::<usize>("max_gcs").unwrap();
+        let compactions = compactions.lock().unwrap();
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("gc_compaction.html".to_owned());
+        report.write_line("<h2>GC Compaction</h2>".to_owned());
+        report.write_line(format!(
+            "<p>{} garbage collections happened during the profiling session. Surviving objects are either moved by compacting GCs, \
+            or kept in place by sweeping GCs and by compacting GCs when they are already packed or pinned.</p>",
+            compactions.gcs.len()
+        ));
+
+        report.write_line("<h3>By Generation</h3>".to_owned());
+        report.write_line("<table><tr><th>Generation</th><th>GCs</th><th>Compacting GCs</th><th>Moved</th><th>Kept</th><th>Moved Bytes</th></tr>".to_owned());
+        for (generation, gcs) in &compactions.gcs.iter().sorted_by_key(|gc| gc.generation).group_by(|gc| gc.generation) {
+            let gcs = gcs.collect_vec();
+            let mut movement = Movement::default();
+            for gc in &gcs {
+                movement.add(&gc.movement);
+            }
+            report.write_line(format!(
+                "<tr><td>Gen {generation}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1} %</td></tr>",
+                gcs.len(),
+                gcs.iter().filter(|gc| gc.movement.moved.count > 0).count(),
+                movement.moved,
+                movement.kept,
+                movement.moved_share()
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>By Type</h3>".to_owned());
+        report.write_line("<table><tr><th>Type</th><th>Moved</th><th>Kept</th><th>Moved Bytes</th></tr>".to_owned());
+        for (class_id, movement) in compactions
+            .by_class
+            .iter()
+            .sorted_by_key(|(_, movement)| std::cmp::Reverse(movement.moved.bytes))
+            .take(max_types)
+        {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{:.1} %</td></tr>",
+                html_escape::encode_text(&name_resolver.get_class_name(*class_id)),
+                movement.moved,
+                movement.kept,
+                movement.moved_share()
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>By GC</h3>".to_owned());
+        report.write_line("<table><tr><th>GC</th><th>Generation</th><th>Moved</th><th>Kept</th><th>Moved Size</th></tr>".to_owned());
+        for (index, gc) in compactions.gcs.iter().enumerate().take(max_gcs) {
+            report.write_line(format!(
+                "<tr><td>#{}</td><td>Gen {}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                index + 1,
+                gc.generation,
+                gc.movement.moved,
+                gc.movement.kept,
+                format_size(gc.movement.moved.bytes)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+}
+
+impl CorProfilerCallback for GCCompactionProfiler {}
+
+impl CorProfilerCallback2 for GCCompactionProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        let generation = ClrProfilerInfo::get_gc_gen(generation_collected);
+        debug!("GC started on gen {} for reason {:?}", generation, reason);
+
+        // Full GCs also collect the LOH and the POH, which are reported as gen 2
+        self.compactions.lock().unwrap().gcs.push(GcMovement {
+            generation: generation.clamp(0, 2) as usize,
+            movement: Movement::default(),
+        });
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr();
+        let mut compactions = self.compactions.lock().unwrap();
+        let ranges = std::mem::take(&mut compactions.pending_ranges);
+        for (start, length, moved) in ranges {
+            Self::record_range(clr, &mut compactions, start, length, moved);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for GCCompactionProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let compactions = self.compactions.clone();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || GCCompactionProfiler::profile(session_info, clr, compactions));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for GCCompactionProfiler {
+    // Called by compacting GCs. Objects can't be inspected while the GC is moving them, so ranges are only walked
+    // once the GC is finished, at their new location.
+    fn moved_references_2(&mut self, old_object_ids: &[ObjectID], new_object_ids: &[ObjectID], object_lengths: &[usize]) -> Result<(), HRESULT> {
+        let mut compactions = self.compactions.lock().unwrap();
+        for i in 0..old_object_ids.len() {
+            compactions
+                .pending_ranges
+                .push((new_object_ids[i], object_lengths[i], old_object_ids[i] != new_object_ids[i]));
+        }
+        Ok(())
+    }
+
+    // Called by non compacting GCs, where every surviving object stays in place
+    fn surviving_references_2(&mut self, object_ids: &[ObjectID], object_lengths: &[usize]) -> Result<(), HRESULT> {
+        let mut compactions = self.compactions.lock().unwrap();
+        for i in 0..object_ids.len() {
+            compactions.pending_ranges.push((object_ids[i], object_lengths[i], false));
+        }
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback5 for GCCompactionProfiler {}
+impl CorProfilerCallback6 for GCCompactionProfiler {}
+impl CorProfilerCallback7 for GCCompactionProfiler {}
+impl CorProfilerCallback8 for GCCompactionProfiler {}
+impl CorProfilerCallback9 for GCCompactionProfiler {}
+impl CorProfilerCallback10 for GCCompactionProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{aligned_object_size, Movement};
+    use crate::utils::AllocationStats;
+
+    #[test]
+    fn object_sizes_are_aligned_on_pointer_size() {
+        let alignment = std::mem::size_of::<usize>();
+        assert_eq!(aligned_object_size(alignment * 3), alignment * 3);
+        assert_eq!(aligned_object_size(alignment * 3 + 1), alignment * 4);
+    }
+
+    #[test]
+    fn movement_splits_moved_and_kept_bytes() {
+        let mut movement = Movement::default();
+        movement.record(true, 24);
+        movement.record(false, 48);
+        movement.record(false, 24);
+
+        assert_eq!(movement.moved, AllocationStats { count: 1, bytes: 24 });
+        assert_eq!(movement.kept, AllocationStats { count: 2, bytes: 72 });
+        assert_eq!(movement.moved_share(), 25.0);
+        assert_eq!(Movement::default().moved_share(), 0.0);
+    }
+}
@@ -0,0 +1,319 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{ClassID, FunctionID, GCHandleID, ObjectID, COR_PRF_GC_ROOT_FLAGS, COR_PRF_GC_ROOT_KIND, HRESULT, UINT_PTR};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HandleKind {
+    Strong,
+    Pinned,
+    Weak,
+    RefCounted,
+    // The handle was not seen in a garbage collection yet
+    Unknown,
+}
+
+impl HandleKind {
+    pub fn from_root_flags(flags: COR_PRF_GC_ROOT_FLAGS) -> Self {
+        if flags.contains(COR_PRF_GC_ROOT_FLAGS::COR_PRF_GC_ROOT_PINNING) {
+            HandleKind::Pinned
+        } else if flags.contains(COR_PRF_GC_ROOT_FLAGS::COR_PRF_GC_ROOT_WEAKREF) {
+            HandleKind::Weak
+        } else if flags.contains(COR_PRF_GC_ROOT_FLAGS::COR_PRF_GC_ROOT_REFCOUNTED) {
+            HandleKind::RefCounted
+        } else {
+            HandleKind::Strong
+        }
+    }
+}
+
+impl Display for HandleKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandleRecord {
+    pub kind: HandleKind,
+    // Type of the object the handle points to, or 0 if it doesn't point to any object
+    pub class_id: ClassID,
+    // Managed callstack of the thread that created the handle, from the leaf frame to the root frame
+    pub creation_stack: Vec<FunctionID>,
+    // Elapsed time since the beginning of the session
+    pub created_at: Duration,
+}
+
+// Handles sharing the same creation callstack, target type and kind
+pub struct HandleGroup<'a> {
+    pub creation_stack: &'a [FunctionID],
+    pub class_id: ClassID,
+    pub kind: HandleKind,
+    pub count: usize,
+    pub oldest: Duration,
+}
+
+// Groups live handles by creation site and target type, the largest groups first
+pub fn group_handles<'a>(handles: impl Iterator<Item = &'a HandleRecord>) -> Vec<HandleGroup<'a>> {
+    handles
+        .into_group_map_by(|handle| (handle.creation_stack.as_slice(), handle.class_id, handle.kind))
+        .into_iter()
+        .map(|((creation_stack, class_id, kind), handles)| HandleGroup {
+            creation_stack,
+            class_id,
+            kind,
+            count: handles.len(),
+            oldest: handles.iter().map(|handle| handle.created_at).min().unwrap_or_default(),
+        })
+        .sorted_by(|a, b| b.count.cmp(&a.count).then(a.oldest.cmp(&b.oldest)))
+        .collect()
+}
+
+#[derive(Default)]
+pub struct GCHandlesState {
+    // Handles created since the profiler attached and not destroyed yet
+    pub live_handles: HashMap<GCHandleID, HandleRecord>,
+    pub created: u64,
+    pub destroyed: u64,
+}
+
+#[derive(Default)]
+pub struct GCHandlesProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    start: Option<Instant>,
+    state: Arc<Mutex<GCHandlesState>>,
+}
+
+impl Profiler for GCHandlesProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "C1A46223-D4A7-46DC-9889-2D1B417E4449".to_owned(),
+            name: "List leaked GC handles".to_owned(),
+            description: "Tracks GC handles created while attached along with the type of the object they point to and the callstack that created them, and lists handles that were never destroyed grouped by creation site, target type and kind (strong, pinned, weak).".to_owned(),
+            parameters: vec![ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds")],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl GCHandlesProfiler {
+    // Frames of GCHandle itself don't tell where the handle comes from
+    fn get_creation_site(name_resolver: &CachedNameResolver, creation_stack: &[FunctionID]) -> String {
+        creation_stack
+            .iter()
+            .map(|method_id| name_resolver.get_full_method_name(*method_id, 0))
+            .find(|method_name| !method_name.starts_with("System.Runtime.InteropServices.GCHandle"))
+            .unwrap_or_else(|| "Unknown (created by the runtime)".to_owned())
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, state: Arc<Mutex<GCHandlesState>>, start: Instant) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        std::thread::sleep(Duration::from_secs(duration_seconds));
+
+        // Handle kinds and targets are only known once handles are seen as roots in a garbage collection
+        if let Err(hresult) = clr.force_gc() {
+            error!("Could not force a garbage collection: {:?}", hresult);
+        }
+
+        // Stop monitoring handles while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let state = state.lock().unwrap();
+        let groups = group_handles(state.live_handles.values());
+        let now = start.elapsed();
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("gc_handles.html".to_owned());
+        report.write_line("<h2>GC Handles</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{} handles created and {} destroyed in {duration_seconds} seconds, {} never destroyed</h4>",
+            state.created,
+            state.destroyed,
+            state.live_handles.len()
+        ));
+
+        for group in groups {
+            let creation_site = Self::get_creation_site(&name_resolver, group.creation_stack);
+            let class_name = match group.class_id {
+                0 => "No target".to_owned(),
+                class_id => name_resolver.get_class_name(class_id),
+            };
+            report.write_line(format!(
+                "<details><summary><code>{}</code> → <code>{}</code> \
+                <div class=\"chip\"><span>{} {}</span><i class=\"material-icons\">link</i></div> \
+                <div class=\"chip\"><span>{} s</span><i class=\"material-icons\">hourglass_empty</i></div></summary>",
+                html_escape::encode_text(&creation_site),
+                html_escape::encode_text(&class_name),
+                group.count,
+                group.kind,
+                now.saturating_sub(group.oldest).as_secs()
+            ));
+            report.write_line("<ul>".to_owned());
+            for method_id in group.creation_stack {
+                let method_name = name_resolver.get_full_method_name(*method_id, 0);
+                report.write_line(format!("<li><code>{}</code></li>", html_escape::encode_text(&method_name)));
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+}
+
+impl CorProfilerCallback for GCHandlesProfiler {}
+
+impl CorProfilerCallback2 for GCHandlesProfiler {
+    fn handle_created(&mut self, handle_id: GCHandleID, initial_object_id: ObjectID) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let class_id = match initial_object_id {
+            0 => 0,
+            object_id => clr.get_class_from_object(object_id).unwrap_or(0),
+        };
+        // This callback is raised on the thread creating the handle
+        let creation_stack = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(clr);
+        let record = HandleRecord {
+            kind: HandleKind::Unknown,
+            class_id,
+            creation_stack,
+            created_at: self.start.map(|start| start.elapsed()).unwrap_or_default(),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.created += 1;
+        state.live_handles.insert(handle_id, record);
+        Ok(())
+    }
+
+    fn handle_destroyed(&mut self, handle_id: GCHandleID) -> Result<(), HRESULT> {
+        let mut state = self.state.lock().unwrap();
+        // Handles created before the profiler attached are not tracked
+        if state.live_handles.remove(&handle_id).is_some() {
+            state.destroyed += 1;
+        }
+        Ok(())
+    }
+
+    fn root_references_2(
+        &mut self,
+        root_ref_ids: &[ObjectID],
+        root_kinds: &[COR_PRF_GC_ROOT_KIND],
+        root_flags: &[COR_PRF_GC_ROOT_FLAGS],
+        root_ids: &[UINT_PTR],
+    ) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let mut state = self.state.lock().unwrap();
+
+        // For handle roots, the root id is the handle itself
+        for i in 0..root_ref_ids.len() {
+            if root_kinds[i] != COR_PRF_GC_ROOT_KIND::COR_PRF_GC_ROOT_HANDLE {
+                continue;
+            }
+            if let Some(handle) = state.live_handles.get_mut(&root_ids[i]) {
+                handle.kind = HandleKind::from_root_flags(root_flags[i]);
+                if root_ref_ids[i] != 0 {
+                    handle.class_id = clr.get_class_from_object(root_ref_ids[i]).unwrap_or(handle.class_id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for GCHandlesProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.start = Some(Instant::now());
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+            None,
+            profiler_info,
+            client_data,
+            client_data_length,
+        )
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let state = self.state.clone();
+        let start = self.start.unwrap_or_else(Instant::now);
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || GCHandlesProfiler::profile(session_info, clr, state, start));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for GCHandlesProfiler {}
+impl CorProfilerCallback5 for GCHandlesProfiler {}
+impl CorProfilerCallback6 for GCHandlesProfiler {}
+impl CorProfilerCallback7 for GCHandlesProfiler {}
+impl CorProfilerCallback8 for GCHandlesProfiler {}
+impl CorProfilerCallback9 for GCHandlesProfiler {}
+impl CorProfilerCallback10 for GCHandlesProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_handles, HandleKind, HandleRecord};
+    use crate::api::ffi::COR_PRF_GC_ROOT_FLAGS;
+    use std::time::Duration;
+
+    fn handle(creation_stack: Vec<usize>, class_id: usize, created_at_ms: u64) -> HandleRecord {
+        HandleRecord {
+            kind: HandleKind::Pinned,
+            class_id,
+            creation_stack,
+            created_at: Duration::from_millis(created_at_ms),
+        }
+    }
+
+    #[test]
+    fn handle_kinds_are_read_from_root_flags() {
+        assert_eq!(HandleKind::from_root_flags(COR_PRF_GC_ROOT_FLAGS::empty()), HandleKind::Strong);
+        assert_eq!(HandleKind::from_root_flags(COR_PRF_GC_ROOT_FLAGS::COR_PRF_GC_ROOT_PINNING), HandleKind::Pinned);
+        assert_eq!(HandleKind::from_root_flags(COR_PRF_GC_ROOT_FLAGS::COR_PRF_GC_ROOT_WEAKREF), HandleKind::Weak);
+    }
+
+    #[test]
+    fn handles_are_grouped_by_creation_site_and_type() {
+        let handles = vec![
+            handle(vec![1, 2], 10, 500),
+            handle(vec![1, 2], 10, 100),
+            handle(vec![1, 2], 11, 0),
+            handle(vec![3], 10, 0),
+        ];
+
+        let groups = group_handles(handles.iter());
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].creation_stack, &[1, 2]);
+        assert_eq!(groups[0].class_id, 10);
+        assert_eq!(groups[0].count, 2);
+        assert_eq!(groups[0].oldest, Duration::from_millis(100));
+    }
+}
@@ -1,5 +1,6 @@
 use deepsize::DeepSizeOf;
-use std::collections::HashMap;
+use itertools::Itertools;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Formatter};
 use std::hash::BuildHasherDefault;
 use std::ops::AddAssign;
@@ -8,11 +9,12 @@ use std::thread;
 use thousands::{digits, Separable, SeparatorPolicy};
 
 use crate::api::*;
+use crate::api::CorProfilerInfo;
 use crate::ffi::*;
 use crate::macros::*;
 use crate::profilers::*;
 use crate::session::Report;
-use crate::utils::{CachedNameResolver, NameResolver, SimpleHasher, TreeNode};
+use crate::utils::{format_size, CachedNameResolver, DominatorTree, NameResolver, SimpleHasher, TreeNode};
 
 #[derive(Default)]
 pub struct GCSurvivorsProfiler {
@@ -56,6 +58,57 @@ impl Display for References {
     }
 }
 
+// Memory of the objects of a type or of a dominator tree node: their count, their own size and the size they retain
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct RetainedStats {
+    pub count: usize,
+    pub shallow: u64,
+    pub retained: u64,
+}
+
+impl AddAssign<&RetainedStats> for RetainedStats {
+    fn add_assign(&mut self, other: &Self) {
+        self.count += other.count;
+        self.shallow += other.shallow;
+        self.retained += other.retained;
+    }
+}
+
+// Graph of the surviving objects. Node 0 is a virtual entry referencing every root.
+#[derive(Default)]
+struct ObjectGraph {
+    indices: HashMap<ObjectID, usize, BuildHasherDefault<SimpleHasher>>,
+    objects: Vec<ObjectID>,
+    classes: Vec<ClassID>,
+    sizes: Vec<u64>,
+    successors: Vec<Vec<usize>>,
+}
+
+impl ObjectGraph {
+    fn new() -> Self {
+        let mut graph = ObjectGraph::default();
+        graph.objects.push(0);
+        graph.classes.push(0);
+        graph.sizes.push(0);
+        graph.successors.push(Vec::new());
+        graph
+    }
+
+    // Returns the index of the node of the given object, and whether it was just added
+    fn node(&mut self, clr: &ClrProfilerInfo, object_id: ObjectID) -> (usize, bool) {
+        if let Some(&index) = self.indices.get(&object_id) {
+            return (index, false);
+        }
+        let index = self.objects.len();
+        self.indices.insert(object_id, index);
+        self.objects.push(object_id);
+        self.classes.push(clr.get_class_from_object(object_id).unwrap_or(0));
+        self.sizes.push(clr.get_object_size_2(object_id).unwrap_or(0) as u64);
+        self.successors.push(Vec::new());
+        (index, true)
+    }
+}
+
 impl Profiler for GCSurvivorsProfiler {
     profiler_getset!();
 
@@ -89,6 +142,12 @@ impl Profiler for GCSurvivorsProfiler {
                     4,
                     "The maximum depth while drilling through retention paths. This helps to reduce the tree size, improving performance and readability.",
                 ),
+                ProfilerParameter::define(
+                    "Compute retained sizes",
+                    "retained_sizes",
+                    false,
+                    "If true, also build the dominator tree of surviving objects to report the memory exclusively retained by each type and object, which is the memory that would be freed if they went away. Objects reachable from several roots are only counted once. This requires more time and memory.",
+                ),
             ],
             ..std::default::Default::default()
         };
@@ -280,11 +339,197 @@ impl GCSurvivorsProfiler {
             self.print_html(&tree_node, 0, &mut report);
         }
 
+        if self.session_info().get_parameter::<bool>("retained_sizes").unwrap() {
+            self.write_retained_sizes(&mut report);
+        }
+
         info!("Report written in {} ms", now.elapsed().as_millis());
 
         Ok(())
     }
 
+    fn build_object_graph(&self) -> ObjectGraph {
+        let clr = self.clr();
+        let mut graph = ObjectGraph::new();
+        let mut queue = VecDeque::new();
+
+        for &root_id in &self.root_objects {
+            if root_id == 0 {
+                continue;
+            }
+            let (index, added) = graph.node(clr, root_id);
+            graph.successors[DominatorTree::ENTRY].push(index);
+            if added {
+                queue.push_back(index);
+            }
+        }
+
+        while let Some(index) = queue.pop_front() {
+            let reference_object_ids = Vec::<ObjectID>::new();
+            // We must pass this data as a pointer for callback to mutate it with actual object references ids
+            let references_ptr_c = &reference_object_ids as *const Vec<ObjectID> as *mut std::ffi::c_void;
+            let _ = clr.enumerate_object_references(graph.objects[index], crate::utils::enum_references_callback, references_ptr_c);
+
+            for reference_id in reference_object_ids {
+                if reference_id == 0 {
+                    continue;
+                }
+                let (reference_index, added) = graph.node(clr, reference_id);
+                graph.successors[index].push(reference_index);
+                if added {
+                    queue.push_back(reference_index);
+                }
+            }
+        }
+
+        graph
+    }
+
+    // Groups the given dominator tree nodes by type, recursively down to the maximum depth
+    fn group_dominated(
+        graph: &ObjectGraph,
+        children: &[Vec<usize>],
+        retained: &[u64],
+        nodes: &[usize],
+        depth: usize,
+        max_depth: usize,
+        retained_bytes_threshold: u64,
+    ) -> Vec<TreeNode<ClassID, RetainedStats>> {
+        let mut groups: HashMap<ClassID, Vec<usize>> = HashMap::new();
+        for &node in nodes {
+            groups.entry(graph.classes[node]).or_default().push(node);
+        }
+
+        let mut tree_nodes = Vec::new();
+        for (class_id, group) in groups {
+            let stats = group.iter().fold(RetainedStats::default(), |mut stats, &node| {
+                stats += &RetainedStats {
+                    count: 1,
+                    shallow: graph.sizes[node],
+                    retained: retained[node],
+                };
+                stats
+            });
+            if stats.retained < retained_bytes_threshold {
+                continue;
+            }
+
+            let mut tree_node = TreeNode::new(class_id);
+            tree_node.value = Some(stats);
+            if depth < max_depth {
+                let dominated: Vec<usize> = group.iter().flat_map(|&node| children[node].iter().copied()).collect();
+                tree_node.children = Self::group_dominated(graph, children, retained, &dominated, depth + 1, max_depth, retained_bytes_threshold);
+            }
+            tree_nodes.push(tree_node);
+        }
+
+        tree_nodes.sort_by_key(|tree_node| std::cmp::Reverse(tree_node.value.unwrap_or_default().retained));
+        tree_nodes
+    }
+
+    // Sums for each type the memory retained by its instances. Instances dominated by another instance
+    // of the same type are skipped, as their retained memory is already included in the dominating one.
+    fn retained_by_type(graph: &ObjectGraph, children: &[Vec<usize>], retained: &[u64]) -> HashMap<ClassID, RetainedStats> {
+        let mut by_type: HashMap<ClassID, RetainedStats> = HashMap::new();
+        let mut active: HashMap<ClassID, usize> = HashMap::new();
+
+        // Iterative depth first search, with exit markers to know which types dominate the current node
+        let mut stack: Vec<(usize, bool)> = children[DominatorTree::ENTRY].iter().map(|&node| (node, false)).collect();
+        while let Some((node, exit)) = stack.pop() {
+            let class_id = graph.classes[node];
+            if exit {
+                *active.get_mut(&class_id).unwrap() -= 1;
+                continue;
+            }
+
+            let nb_active = active.entry(class_id).or_default();
+            let stats = by_type.entry(class_id).or_default();
+            stats.count += 1;
+            stats.shallow += graph.sizes[node];
+            if *nb_active == 0 {
+                stats.retained += retained[node];
+            }
+            *nb_active += 1;
+
+            stack.push((node, true));
+            stack.extend(children[node].iter().map(|&child| (child, false)));
+        }
+
+        by_type
+    }
+
+    fn write_retained_sizes(&self, report: &mut Report) {
+        info!("Computing dominator tree...");
+
+        let now = std::time::Instant::now();
+
+        let max_depth = self.session_info().get_parameter::<usize>("max_depth").unwrap();
+        let retained_bytes_threshold = self.session_info().get_parameter::<u64>("retained_bytes_threshold").unwrap();
+
+        let graph = self.build_object_graph();
+        let dominators = DominatorTree::new(&graph.successors);
+        let retained = dominators.retained_sizes(&graph.sizes);
+        let children = dominators.children();
+
+        info!("Dominator tree of {} objects computed in {} ms", graph.objects.len() - 1, now.elapsed().as_millis());
+
+        let policy = SeparatorPolicy {
+            separator: ",",
+            groups: &[3],
+            digits: digits::ASCII_DECIMAL,
+        };
+
+        report.write_line("<h3>Retained Sizes</h3>".to_owned());
+        report.write_line("Retained sizes are computed from the dominator tree of the surviving objects: an object dominates another if every path from the roots to the latter goes through it. The retained size of an object is the memory that would be freed if it went away, and objects reachable from several paths are only counted once.".to_owned());
+
+        report.write_line("<h4>Retained Size By Type</h4>".to_owned());
+        report.write_line("<table><tr><th>Type</th><th>Instances</th><th>Shallow Bytes</th><th>Retained Bytes</th></tr>".to_owned());
+        let by_type = Self::retained_by_type(&graph, &children, &retained);
+        for (class_id, stats) in by_type.iter().sorted_by(|a, b| b.1.retained.cmp(&a.1.retained)) {
+            if stats.retained < retained_bytes_threshold {
+                break;
+            }
+            let class_name = self.name_resolver.get_class_name(*class_id);
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape::encode_text(&class_name),
+                stats.count.separate_by_policy(policy),
+                stats.shallow.separate_by_policy(policy),
+                stats.retained.separate_by_policy(policy)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h4>Dominator Tree</h4>".to_owned());
+        let tree_nodes = Self::group_dominated(
+            &graph,
+            &children,
+            &retained,
+            &children[DominatorTree::ENTRY],
+            0,
+            max_depth,
+            retained_bytes_threshold,
+        );
+        for tree_node in &tree_nodes {
+            tree_node.print_html(report, &|node| self.format_retained_line(node));
+        }
+    }
+
+    fn format_retained_line(&self, node: &TreeNode<ClassID, RetainedStats>) -> String {
+        let stats = node.value.unwrap_or_default();
+        let class_name = self.name_resolver.get_class_name(node.key);
+        let escaped_class_name = html_escape::encode_text(&class_name);
+
+        format!(
+            "<code>{escaped_class_name}</code> \
+            <div class=\"chip\"><span>{} retained</span><i class=\"material-icons\">radio_button_checked</i></div> \
+            <div class=\"chip\"><span>{} / {}</span><i class=\"material-icons\">radio_button_unchecked</i></div>",
+            format_size(stats.retained),
+            stats.count,
+            format_size(stats.shallow)
+        )
+    }
+
     fn print_html(&self, tree: &TreeNode<ClassID, References>, depth: usize, report: &mut Report) {
 
         let binding = References::default();
@@ -459,3 +704,4 @@ impl CorProfilerCallback6 for GCSurvivorsProfiler {}
 impl CorProfilerCallback7 for GCSurvivorsProfiler {}
 impl CorProfilerCallback8 for GCSurvivorsProfiler {}
 impl CorProfilerCallback9 for GCSurvivorsProfiler {}
+impl CorProfilerCallback10 for GCSurvivorsProfiler {}
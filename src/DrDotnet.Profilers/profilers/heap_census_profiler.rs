@@ -0,0 +1,229 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::thread;
+
+use crate::api::ffi::{ClassID, ObjectID, COR_PRF_GC_GENERATION, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::session::Report;
+use crate::utils::{AllocationStats, CachedNameResolver, NameResolver, GENERATION_NAMES};
+
+// Live instances of a type, in total and per generation. Objects whose generation can't be retrieved only count in the total.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct TypeCensus {
+    pub total: AllocationStats,
+    pub per_generation: [AllocationStats; 5],
+}
+
+impl TypeCensus {
+    pub fn record(&mut self, generation: Option<COR_PRF_GC_GENERATION>, size: u64) {
+        let stats = AllocationStats { count: 1, bytes: size };
+        self.total += &stats;
+        if let Some(generation) = generation {
+            self.per_generation[generation as usize] += &stats;
+        }
+    }
+
+    pub fn add(&mut self, other: &TypeCensus) {
+        self.total += &other.total;
+        for (stats, other_stats) in self.per_generation.iter_mut().zip(other.per_generation.iter()) {
+            *stats += other_stats;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HeapCensusProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    census: HashMap<ClassID, TypeCensus>,
+    record_object_references: bool,
+}
+
+impl Profiler for HeapCensusProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "7B84CEDE-F64A-4CFC-A1EC-D90C9073F532".to_owned(),
+            name: "List live objects per type".to_owned(),
+            description: "Perform a full blocking garbage collection and count the surviving instances and bytes of each type, broken down by generation. Unlike the GC survivors profiler, no retention tree is built, which makes it fast even on large heaps.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define(
+                    "Sort by size",
+                    "sort_by_size",
+                    true,
+                    "If true, sort the types by total size (bytes). Otherwise, sort by count of instances.",
+                ),
+                ProfilerParameter::define("Maximum types", "max_types", 100, "The maximum number of types to display"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl HeapCensusProfiler {
+    fn write_report(&self) {
+        let sort_by_size = self.session_info.get_parameter::<bool>("sort_by_size").unwrap();
+        let max_types = self.session_info.get_parameter::<usize>("max_types").unwrap();
+
+        let mut totals = TypeCensus::default();
+        for census in self.census.values() {
+            totals.add(census);
+        }
+
+        let name_resolver = CachedNameResolver::new(self.clr().clone());
+        let mut report = self.session_info.create_report("heap_census.html".to_owned());
+        report.write_line("<h2>Heap Census</h2>".to_owned());
+        report.write_line(format!("<h4>{} live objects of {} types</h4>", totals.total, self.census.len()));
+
+        let generation_headers: String = GENERATION_NAMES.iter().map(|name| format!("<th>{name}</th>")).collect();
+        report.write_line(format!("<table><tr><th>Type</th><th>Total</th>{generation_headers}</tr>"));
+
+        let write_row = |report: &mut Report, name: &str, census: &TypeCensus| {
+            let generation_cells: String = census.per_generation.iter().map(|stats| format!("<td>{stats}</td>")).collect();
+            report.write_line(format!("<tr><td>{}</td><td>{}</td>{generation_cells}</tr>", name, census.total));
+        };
+
+        write_row(&mut report, "<b>All types</b>", &totals);
+
+        let sorted = self.census.iter().sorted_by(|a, b| {
+            if sort_by_size {
+                b.1.total.bytes.cmp(&a.1.total.bytes)
+            } else {
+                b.1.total.count.cmp(&a.1.total.count)
+            }
+        });
+        for (class_id, census) in sorted.take(max_types) {
+            let class_name = name_resolver.get_class_name(*class_id);
+            write_row(&mut report, &format!("<code>{}</code>", html_escape::encode_text(&class_name)), census);
+        }
+        report.write_line("</table>".to_owned());
+    }
+}
+
+impl CorProfilerCallback for HeapCensusProfiler {
+    fn object_references(&mut self, object_id: ObjectID, class_id: ClassID, _object_ref_ids: &[ObjectID]) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        let clr = self.clr();
+        let size = clr.get_object_size_2(object_id).unwrap_or(0) as u64;
+        let generation = clr.get_object_generation(object_id).ok().map(|range| range.generation);
+        self.census.entry(class_id).or_default().record(generation, size);
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for HeapCensusProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        // Start recording objects
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        info!("GC finished, {} types recorded", self.census.len());
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        self.write_report();
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for HeapCensusProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        let clr = self.clr().clone();
+        let _ = thread::spawn(move || {
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        })
+        .join();
+
+        // Security timeout
+        detach_after_duration::<HeapCensusProfiler>(self, 120);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for HeapCensusProfiler {}
+impl CorProfilerCallback5 for HeapCensusProfiler {}
+impl CorProfilerCallback6 for HeapCensusProfiler {}
+impl CorProfilerCallback7 for HeapCensusProfiler {}
+impl CorProfilerCallback8 for HeapCensusProfiler {}
+impl CorProfilerCallback9 for HeapCensusProfiler {}
+impl CorProfilerCallback10 for HeapCensusProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::TypeCensus;
+    use crate::api::ffi::COR_PRF_GC_GENERATION;
+    use crate::utils::AllocationStats;
+
+    #[test]
+    fn census_breaks_down_instances_per_generation() {
+        let mut census = TypeCensus::default();
+        census.record(Some(COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_0), 24);
+        census.record(Some(COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_2), 24);
+        census.record(Some(COR_PRF_GC_GENERATION::COR_PRF_GC_GEN_2), 48);
+        census.record(None, 24);
+
+        assert_eq!(census.total, AllocationStats { count: 4, bytes: 120 });
+        assert_eq!(census.per_generation[0], AllocationStats { count: 1, bytes: 24 });
+        assert_eq!(census.per_generation[2], AllocationStats { count: 2, bytes: 72 });
+
+        let mut totals = TypeCensus::default();
+        totals.add(&census);
+        totals.add(&census);
+        assert_eq!(totals.per_generation[2], AllocationStats { count: 4, bytes: 144 });
+    }
+}
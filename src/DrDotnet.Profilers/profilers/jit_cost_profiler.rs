@@ -0,0 +1,407 @@
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{FunctionID, ModuleID, ReJITID, ThreadID, COR_PRF_JIT_CACHE, DWORD, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{format_duration, CachedNameResolver, NameResolver};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompilationKind {
+    Jit,
+    ReJit,
+    // Lookup of precompiled (ReadyToRun) code, and whether it was found
+    CachedSearch(bool),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compilation {
+    pub function_id: FunctionID,
+    pub kind: CompilationKind,
+    pub os_thread_id: DWORD,
+    // Elapsed time since the beginning of the session
+    pub started_at: Duration,
+    pub duration: Duration,
+}
+
+#[derive(Default)]
+pub struct JitState {
+    pub compilations: Vec<Compilation>,
+    // Compilations in progress per thread, as several threads can compile the same method at the same time
+    pending: HashMap<(ThreadID, FunctionID), Instant>,
+    pending_searches: HashMap<(ThreadID, FunctionID), Instant>,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct MethodJitCost {
+    pub compilations: u64,
+    pub rejits: u64,
+    pub total: Duration,
+    pub max: Duration,
+    pub os_thread_ids: HashSet<DWORD>,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct TimelineBucket {
+    pub compilations: u64,
+    pub total: Duration,
+}
+
+// Aggregates JIT compilations per method. Cached searches are left out since nothing gets compiled.
+pub fn aggregate_per_method(compilations: &[Compilation]) -> HashMap<FunctionID, MethodJitCost> {
+    let mut per_method: HashMap<FunctionID, MethodJitCost> = HashMap::new();
+    for compilation in compilations.iter().filter(|c| !matches!(c.kind, CompilationKind::CachedSearch(_))) {
+        let cost = per_method.entry(compilation.function_id).or_default();
+        cost.compilations += 1;
+        if compilation.kind == CompilationKind::ReJit {
+            cost.rejits += 1;
+        }
+        cost.total += compilation.duration;
+        cost.max = cost.max.max(compilation.duration);
+        cost.os_thread_ids.insert(compilation.os_thread_id);
+    }
+    per_method
+}
+
+// Buckets JIT compilations by the time they started at
+pub fn build_timeline(compilations: &[Compilation], resolution: Duration, session_duration: Duration) -> Vec<TimelineBucket> {
+    let resolution_ms = resolution.as_millis().max(1);
+    let bucket_count = (session_duration.as_millis() / resolution_ms + 1) as usize;
+    let mut timeline = vec![TimelineBucket::default(); bucket_count];
+    for compilation in compilations.iter().filter(|c| !matches!(c.kind, CompilationKind::CachedSearch(_))) {
+        let index = ((compilation.started_at.as_millis() / resolution_ms) as usize).min(bucket_count - 1);
+        timeline[index].compilations += 1;
+        timeline[index].total += compilation.duration;
+    }
+    timeline
+}
+
+#[derive(Default)]
+pub struct JitCostProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    start: Option<Instant>,
+    cache_searches_monitored: bool,
+    state: Arc<Mutex<JitState>>,
+}
+
+impl Profiler for JitCostProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "185F93B4-7597-42A3-8BAC-D6DA90E37D2D".to_owned(),
+            name: "List JIT compilation costs".to_owned(),
+            description: "Measures how long each method takes to be JIT compiled, on which thread and whether it is compiled again later (tiered compilation or ReJIT). Lists the most expensive methods, the JIT time per assembly and a timeline of JIT activity.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define("Maximum methods", "max_methods", 50, "The maximum number of methods to display"),
+                ProfilerParameter::define(
+                    "Timeline resolution",
+                    "timeline_resolution_ms",
+                    1000,
+                    "Duration of each interval of the JIT activity timeline in milliseconds",
+                ),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl JitCostProfiler {
+    fn started(&self, function_id: FunctionID, searching: bool) {
+        let thread_id = match self.clr().get_current_thread_id() {
+            Ok(thread_id) => thread_id,
+            Err(_) => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let pending = if searching { &mut state.pending_searches } else { &mut state.pending };
+        pending.insert((thread_id, function_id), Instant::now());
+    }
+
+    fn finished(&self, function_id: FunctionID, kind: CompilationKind) {
+        let clr = self.clr();
+        let thread_id = match clr.get_current_thread_id() {
+            Ok(thread_id) => thread_id,
+            Err(_) => return,
+        };
+        let os_thread_id = clr.get_thread_info(thread_id).unwrap_or(0);
+        let session_start = self.start.unwrap_or_else(Instant::now);
+
+        let mut state = self.state.lock().unwrap();
+        let pending = if matches!(kind, CompilationKind::CachedSearch(_)) {
+            &mut state.pending_searches
+        } else {
+            &mut state.pending
+        };
+        if let Some(started) = pending.remove(&(thread_id, function_id)) {
+            state.compilations.push(Compilation {
+                function_id,
+                kind,
+                os_thread_id,
+                started_at: started.saturating_duration_since(session_start),
+                duration: started.elapsed(),
+            });
+        }
+    }
+
+    fn get_assembly_name(clr: &ClrProfilerInfo, module_id: ModuleID) -> String {
+        clr.get_module_info(module_id)
+            .and_then(|module_info| clr.get_assembly_info(module_info.assembly_id))
+            .map(|assembly_info| assembly_info.name)
+            .unwrap_or_else(|_| "Unknown".to_owned())
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, state: Arc<Mutex<JitState>>, cache_searches_monitored: bool) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        let max_methods = session_info.get_parameter::<usize>("max_methods").unwrap();
+        let timeline_resolution_ms = session_info.get_parameter::<u64>("timeline_resolution_ms").unwrap();
+        let session_duration = Duration::from_secs(duration_seconds);
+        std::thread::sleep(session_duration);
+
+        // Stop monitoring compilations while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let state = state.lock().unwrap();
+        let per_method = aggregate_per_method(&state.compilations);
+        let timeline = build_timeline(&state.compilations, Duration::from_millis(timeline_resolution_ms), session_duration);
+        let total = per_method.values().map(|cost| cost.total).sum::<Duration>();
+        let compilations = per_method.values().map(|cost| cost.compilations).sum::<u64>();
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("jit_cost.html".to_owned());
+        report.write_line("<h2>JIT Compilation Cost</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{compilations} compilations of {} methods, for a total of {}</h4>",
+            per_method.len(),
+            format_duration(total)
+        ));
+
+        if cache_searches_monitored {
+            let searches = state.compilations.iter().filter_map(|c| match c.kind {
+                CompilationKind::CachedSearch(found) => Some(found),
+                _ => None,
+            });
+            let (found, not_found): (Vec<bool>, Vec<bool>) = searches.partition(|found| *found);
+            report.write_line(format!(
+                "<p>Precompiled code was found for {} methods and missing for {} methods.</p>",
+                found.len(),
+                not_found.len()
+            ));
+        } else {
+            report.write_line("<p>⚠️ The runtime doesn't allow monitoring precompiled code searches once attached.</p>".to_owned());
+        }
+
+        report.write_line("<h3>Most Expensive Methods</h3>".to_owned());
+        report
+            .write_line("<table><tr><th>Method</th><th>Compilations</th><th>Total</th><th>Slowest</th><th>Recompiled</th><th>OS Threads</th></tr>".to_owned());
+        for (function_id, cost) in per_method.iter().sorted_by(|a, b| b.1.total.cmp(&a.1.total)).take(max_methods) {
+            let method_name = name_resolver.get_full_method_name(*function_id, 0);
+            let recompiled = match (cost.compilations, cost.rejits) {
+                (1, _) => "No".to_owned(),
+                (_, 0) => "Yes".to_owned(),
+                (_, rejits) => format!("Yes ({rejits} ReJIT)"),
+            };
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{recompiled}</td><td>{}</td></tr>",
+                html_escape::encode_text(&method_name),
+                cost.compilations,
+                format_duration(cost.total),
+                format_duration(cost.max),
+                cost.os_thread_ids.iter().sorted().join(", ")
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>JIT Time per Assembly</h3>".to_owned());
+        let mut per_module: HashMap<ModuleID, (u64, Duration)> = HashMap::new();
+        for (function_id, cost) in per_method.iter() {
+            if let Ok(function_info) = clr.get_function_info(*function_id) {
+                let entry = per_module.entry(function_info.module_id).or_default();
+                entry.0 += cost.compilations;
+                entry.1 += cost.total;
+            }
+        }
+        let mut per_assembly: HashMap<String, (u64, Duration)> = HashMap::new();
+        for (module_id, (module_compilations, module_total)) in per_module {
+            let entry = per_assembly.entry(Self::get_assembly_name(&clr, module_id)).or_default();
+            entry.0 += module_compilations;
+            entry.1 += module_total;
+        }
+        report.write_line("<table><tr><th>Assembly</th><th>Compilations</th><th>Total</th></tr>".to_owned());
+        for (assembly_name, (assembly_compilations, assembly_total)) in per_assembly.iter().sorted_by(|a, b| b.1 .1.cmp(&a.1 .1)) {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{assembly_compilations}</td><td>{}</td></tr>",
+                html_escape::encode_text(assembly_name),
+                format_duration(*assembly_total)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Timeline</h3>".to_owned());
+        report.write_line("<table><tr><th>From</th><th>Compilations</th><th>JIT Time</th></tr>".to_owned());
+        for (index, bucket) in timeline.iter().enumerate() {
+            report.write_line(format!(
+                "<tr><td>{} ms</td><td>{}</td><td>{}</td></tr>",
+                index as u64 * timeline_resolution_ms,
+                bucket.compilations,
+                format_duration(bucket.total)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+}
+
+impl CorProfilerCallback for JitCostProfiler {
+    fn jit_compilation_started(&mut self, function_id: FunctionID, _is_safe_to_block: bool) -> Result<(), HRESULT> {
+        self.started(function_id, false);
+        Ok(())
+    }
+
+    fn jit_compilation_finished(&mut self, function_id: FunctionID, _hr_status: HRESULT, _is_safe_to_block: bool) -> Result<(), HRESULT> {
+        self.finished(function_id, CompilationKind::Jit);
+        Ok(())
+    }
+
+    fn jit_cached_function_search_started(&mut self, function_id: FunctionID, _use_cached_function: bool) -> Result<(), HRESULT> {
+        self.started(function_id, true);
+        Ok(())
+    }
+
+    fn jit_cached_function_search_finished(&mut self, function_id: FunctionID, result: COR_PRF_JIT_CACHE) -> Result<(), HRESULT> {
+        let found = result == COR_PRF_JIT_CACHE::COR_PRF_CACHED_FUNCTION_FOUND;
+        self.finished(function_id, CompilationKind::CachedSearch(found));
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for JitCostProfiler {}
+
+impl CorProfilerCallback3 for JitCostProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.start = Some(Instant::now());
+
+        let events = ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_JIT_COMPILATION | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_REJIT;
+        self.cache_searches_monitored = true;
+        if self
+            .init(
+                events | ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_CACHE_SEARCHES,
+                None,
+                profiler_info.clone(),
+                client_data,
+                client_data_length,
+            )
+            .is_err()
+        {
+            // Cache searches are not allowed after attach on most runtimes
+            warn!("Could not monitor precompiled code searches, falling back to JIT compilations only");
+            self.cache_searches_monitored = false;
+            self.init(events, None, profiler_info, client_data, client_data_length)?;
+        }
+
+        Ok(())
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let state = self.state.clone();
+        let cache_searches_monitored = self.cache_searches_monitored;
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || JitCostProfiler::profile(session_info, clr, state, cache_searches_monitored));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for JitCostProfiler {
+    fn rejit_compilation_started(&mut self, function_id: FunctionID, _rejit_id: ReJITID, _is_safe_to_block: bool) -> Result<(), HRESULT> {
+        self.started(function_id, false);
+        Ok(())
+    }
+
+    fn rejit_compilation_finished(&mut self, function_id: FunctionID, _rejit_id: ReJITID, _hr_status: HRESULT, _is_safe_to_block: bool) -> Result<(), HRESULT> {
+        self.finished(function_id, CompilationKind::ReJit);
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback5 for JitCostProfiler {}
+impl CorProfilerCallback6 for JitCostProfiler {}
+impl CorProfilerCallback7 for JitCostProfiler {}
+impl CorProfilerCallback8 for JitCostProfiler {}
+impl CorProfilerCallback9 for JitCostProfiler {}
+impl CorProfilerCallback10 for JitCostProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate_per_method, build_timeline, Compilation, CompilationKind};
+    use std::time::Duration;
+
+    fn compilation(function_id: usize, kind: CompilationKind, started_at_ms: u64, duration_ms: u64) -> Compilation {
+        Compilation {
+            function_id,
+            kind,
+            os_thread_id: 1,
+            started_at: Duration::from_millis(started_at_ms),
+            duration: Duration::from_millis(duration_ms),
+        }
+    }
+
+    #[test]
+    fn methods_compiled_several_times_are_aggregated() {
+        let compilations = vec![
+            compilation(1, CompilationKind::Jit, 0, 5),
+            compilation(1, CompilationKind::ReJit, 100, 3),
+            compilation(2, CompilationKind::Jit, 100, 1),
+            compilation(3, CompilationKind::CachedSearch(true), 100, 1),
+        ];
+
+        let per_method = aggregate_per_method(&compilations);
+
+        assert_eq!(per_method.len(), 2);
+        assert_eq!(per_method[&1].compilations, 2);
+        assert_eq!(per_method[&1].rejits, 1);
+        assert_eq!(per_method[&1].total, Duration::from_millis(8));
+        assert_eq!(per_method[&1].max, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn timeline_buckets_compilations_by_start_time() {
+        let compilations = vec![
+            compilation(1, CompilationKind::Jit, 10, 5),
+            compilation(2, CompilationKind::Jit, 1500, 2),
+            compilation(3, CompilationKind::Jit, 1900, 2),
+            // Compilations finishing after the session are put in the last bucket
+            compilation(4, CompilationKind::Jit, 5000, 1),
+        ];
+
+        let timeline = build_timeline(&compilations, Duration::from_secs(1), Duration::from_secs(2));
+
+        assert_eq!(timeline.len(), 3);
+        assert_eq!(timeline[0].compilations, 1);
+        assert_eq!(timeline[1].compilations, 2);
+        assert_eq!(timeline[1].total, Duration::from_millis(4));
+        assert_eq!(timeline[2].compilations, 1);
+    }
+}
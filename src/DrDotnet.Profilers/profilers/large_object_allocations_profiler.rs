@@ -0,0 +1,427 @@
+use itertools::Itertools;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::api::ffi::{ClassID, FunctionID, ObjectID, COR_PRF_GC_GENERATION, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::session::Report;
+use crate::utils::{format_size, AllocationStats, CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver, TreeNode};
+
+// Threshold used by the runtime when it can't be queried
+const DEFAULT_LOH_THRESHOLD: u64 = 85_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LargeHeap {
+    Large,
+    Pinned,
+    Unknown,
+}
+
+impl LargeHeap {
+    // Objects are told apart by the heap the runtime put them in. If the generation can't be retrieved,
+    // objects above the LOH threshold are assumed to be on the LOH, and smaller ones can't be told apart.
+    pub fn classify(generation: Option<COR_PRF_GC_GENERATION>, size: u64, loh_threshold: u64) -> LargeHeap {
+        match generation {
+            Some(COR_PRF_GC_GENERATION::COR_PRF_GC_PINNED_OBJECT_HEAP) => LargeHeap::Pinned,
+            Some(COR_PRF_GC_GENERATION::COR_PRF_GC_LARGE_OBJECT_HEAP) => LargeHeap::Large,
+            _ if size >= loh_threshold => LargeHeap::Large,
+            _ => LargeHeap::Unknown,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            LargeHeap::Large => "Large Object Heap (LOH)",
+            LargeHeap::Pinned => "Pinned Object Heap (POH)",
+            LargeHeap::Unknown => "Unknown Heap",
+        }
+    }
+}
+
+// Counts allocations in power of two size buckets: bucket k holds sizes in [2^k, 2^(k+1))
+#[derive(Default)]
+pub struct SizeHistogram {
+    buckets: BTreeMap<u32, AllocationStats>,
+}
+
+impl SizeHistogram {
+    pub fn bucket(size: u64) -> u32 {
+        63 - size.max(1).leading_zeros()
+    }
+
+    pub fn record(&mut self, size: u64) {
+        let stats = self.buckets.entry(Self::bucket(size)).or_default();
+        stats.count += 1;
+        stats.bytes += size;
+    }
+
+    // Iterates over non empty buckets by increasing sizes, as (lower bound, upper bound, stats)
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64, &AllocationStats)> {
+        self.buckets
+            .iter()
+            .map(|(bucket, stats)| (1u64 << bucket, 1u64.checked_shl(bucket + 1).unwrap_or(u64::MAX), stats))
+    }
+}
+
+pub struct HeapAllocations {
+    total: AllocationStats,
+    by_class: HashMap<ClassID, AllocationStats>,
+    histogram: SizeHistogram,
+    // Allocations per second since the profiling started
+    per_second: Vec<AllocationStats>,
+    // Allocations per method directly allocating the objects (the leaf frame)
+    by_method: HashMap<FunctionID, AllocationStats>,
+    call_sites: TreeNode<FunctionID, AllocationStats>,
+}
+
+impl HeapAllocations {
+    pub fn record(&mut self, class_id: ClassID, size: u64, second: usize, method_ids: Vec<FunctionID>, caller_to_callee: bool) {
+        let stats = AllocationStats { count: 1, bytes: size };
+
+        self.total += &stats;
+        *self.by_class.entry(class_id).or_default() += &stats;
+        self.histogram.record(size);
+
+        if self.per_second.len() <= second {
+            self.per_second.resize(second + 1, AllocationStats::default());
+        }
+        self.per_second[second] += &stats;
+
+        if let Some(method_id) = method_ids.first() {
+            *self.by_method.entry(*method_id).or_default() += &stats;
+        }
+
+        let node = if caller_to_callee {
+            self.call_sites.add_sequence(method_ids.into_iter().rev())
+        } else {
+            self.call_sites.add_sequence(method_ids)
+        };
+        *node.value.get_or_insert_with(AllocationStats::default) += &stats;
+    }
+}
+
+impl Default for HeapAllocations {
+    fn default() -> Self {
+        HeapAllocations {
+            total: AllocationStats::default(),
+            by_class: HashMap::new(),
+            histogram: SizeHistogram::default(),
+            per_second: Vec::new(),
+            by_method: HashMap::new(),
+            call_sites: TreeNode::new(0),
+        }
+    }
+}
+
+type LargeAllocations = BTreeMap<LargeHeap, HeapAllocations>;
+
+#[derive(Default)]
+pub struct LargeObjectAllocationsProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    start: Option<Instant>,
+    loh_threshold: u64,
+    pinned_monitored: bool,
+    caller_to_callee: bool,
+    allocations: Arc<Mutex<LargeAllocations>>,
+}
+
+impl Profiler for LargeObjectAllocationsProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "AC998988-2500-43C3-A1A9-532E68E0062F".to_owned(),
+            name: "List large and pinned object allocations".to_owned(),
+            description: "Tracks every allocation on the Large Object Heap (objects above the LOH threshold) and on the Pinned Object Heap, along with the allocating callstack. Lists allocated types, a size histogram, the allocation rate and the top allocating call sites.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define(
+                    "Caller To Callee",
+                    "caller_to_callee",
+                    false,
+                    "If set, the output will display callers first and callees as children in the tree representation",
+                ),
+                ProfilerParameter::define("Maximum types", "max_types", 20, "The maximum number of allocated types to display per heap"),
+                ProfilerParameter::define("Maximum call sites", "max_call_sites", 20, "The maximum number of allocating methods to display per heap"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl LargeObjectAllocationsProfiler {
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, allocations: Arc<Mutex<LargeAllocations>>, loh_threshold: u64, pinned_monitored: bool) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(duration_seconds));
+
+        // Stop receiving allocations while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let max_types = session_info.get_parameter::<usize>("max_types").unwrap();
+        let max_call_sites = session_info.get_parameter::<usize>("max_call_sites").unwrap();
+
+        let mut allocations = allocations.lock().unwrap();
+        let compare = &|a: &TreeNode<FunctionID, AllocationStats>, b: &TreeNode<FunctionID, AllocationStats>| {
+            b.get_inclusive_value().bytes.cmp(&a.get_inclusive_value().bytes)
+        };
+        for heap in allocations.values_mut() {
+            heap.call_sites.sort_by_iterative(compare);
+        }
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("large_object_allocations.html".to_owned());
+        report.write_line("<h2>Large Object Allocations</h2>".to_owned());
+        report.write_line(format!(
+            "<p>Objects of {loh_threshold} bytes or more are allocated on the Large Object Heap.</p>"
+        ));
+        if !pinned_monitored {
+            report.write_line("<p>⚠️ The runtime doesn't support monitoring Pinned Object Heap allocations (requires .NET 5 or later).</p>".to_owned());
+        }
+        if allocations.is_empty() {
+            report.write_line("<p>No large or pinned object allocations happened during the profiling session.</p>".to_owned());
+        }
+
+        for (heap, heap_allocations) in allocations.iter() {
+            Self::write_heap(
+                &name_resolver,
+                &mut report,
+                *heap,
+                heap_allocations,
+                duration_seconds,
+                max_types,
+                max_call_sites,
+            );
+        }
+
+        Self::write_rate(&mut report, &allocations);
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn write_heap(
+        name_resolver: &CachedNameResolver,
+        report: &mut Report,
+        heap: LargeHeap,
+        allocations: &HeapAllocations,
+        duration_seconds: u64,
+        max_types: usize,
+        max_call_sites: usize,
+    ) {
+        let total = &allocations.total;
+        report.write_line(format!("<h3>{}</h3>", heap.title()));
+        report.write_line(format!(
+            "<h4>{} allocations for {} ({}/s)</h4>",
+            total.count,
+            format_size(total.bytes),
+            format_size(total.bytes / duration_seconds.max(1))
+        ));
+
+        report.write_line("<h4>Size Histogram</h4>".to_owned());
+        report.write_line("<table><tr><th>Size</th><th>Allocations</th><th>Bytes</th><th>Share</th></tr>".to_owned());
+        for (lower, upper, stats) in allocations.histogram.iter() {
+            report.write_line(format!(
+                "<tr><td>{} - {}</td><td>{}</td><td>{}</td><td>{:.1} %</td></tr>",
+                format_size(lower),
+                format_size(upper),
+                stats.count,
+                format_size(stats.bytes),
+                100.0 * stats.bytes as f64 / total.bytes.max(1) as f64
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h4>Allocated Types</h4>".to_owned());
+        report.write_line("<table><tr><th>Type</th><th>Allocations</th><th>Bytes</th><th>Average Size</th></tr>".to_owned());
+        for (class_id, stats) in allocations.by_class.iter().sorted_by(|a, b| b.1.bytes.cmp(&a.1.bytes)).take(max_types) {
+            let class_name = name_resolver.get_class_name(*class_id);
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape::encode_text(&class_name),
+                stats.count,
+                format_size(stats.bytes),
+                format_size(stats.bytes / stats.count.max(1))
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h4>Top Allocating Call Sites</h4>".to_owned());
+        report.write_line("<table><tr><th>Method</th><th>Allocations</th><th>Bytes</th></tr>".to_owned());
+        for (method_id, stats) in allocations.by_method.iter().sorted_by(|a, b| b.1.bytes.cmp(&a.1.bytes)).take(max_call_sites) {
+            let method_name = name_resolver.get_full_method_name(*method_id, 0);
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+                html_escape::encode_text(&method_name),
+                stats.count,
+                format_size(stats.bytes)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<details><summary>Allocating Callstacks</summary>".to_owned());
+        report.write_line("<ul>".to_owned());
+        for node in &allocations.call_sites.children {
+            node.print_html(report, &|node| Self::format_html_line(name_resolver, node));
+        }
+        report.write_line("</ul>".to_owned());
+        report.write_line("</details>".to_owned());
+    }
+
+    fn write_rate(report: &mut Report, allocations: &LargeAllocations) {
+        let seconds = allocations.values().map(|heap| heap.per_second.len()).max().unwrap_or(0);
+        if seconds == 0 {
+            return;
+        }
+
+        report.write_line("<h3>Allocation Rate</h3>".to_owned());
+        let headers: String = allocations.keys().map(|heap| format!("<th>{}</th>", heap.title())).collect();
+        report.write_line(format!("<table><tr><th>Second</th>{headers}</tr>"));
+        for second in 0..seconds {
+            let cells: String = allocations
+                .values()
+                .map(|heap| {
+                    let stats = heap.per_second.get(second).copied().unwrap_or_default();
+                    format!("<td>{} / {}</td>", stats.count, format_size(stats.bytes))
+                })
+                .collect();
+            report.write_line(format!("<tr><td>{second}</td>{cells}</tr>"));
+        }
+        report.write_line("</table>".to_owned());
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, AllocationStats>) -> String {
+        let inclusive = node.get_inclusive_value();
+
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">radio_button_checked</i></div>"
+        )
+    }
+}
+
+impl CorProfilerCallback for LargeObjectAllocationsProfiler {
+    fn object_allocated(&mut self, object_id: ObjectID, class_id: ClassID) -> Result<(), HRESULT> {
+        let size = self.clr().get_object_size_2(object_id).unwrap_or(0) as u64;
+        let generation = self.clr().get_object_generation(object_id).ok().map(|range| range.generation);
+        let heap = LargeHeap::classify(generation, size, self.loh_threshold);
+        let second = self.start.map_or(0, |start| start.elapsed().as_secs() as usize);
+
+        // We are on the allocating thread, so the current callstack is the allocation callstack
+        let method_ids = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(self.clr().clone());
+
+        let mut allocations = self.allocations.lock().unwrap();
+        allocations
+            .entry(heap)
+            .or_default()
+            .record(class_id, size, second, method_ids, self.caller_to_callee);
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for LargeObjectAllocationsProfiler {}
+
+impl CorProfilerCallback3 for LargeObjectAllocationsProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        let high_events =
+            ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_LARGEOBJECT_ALLOCATED | ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_PINNEDOBJECT_ALLOCATED;
+
+        self.pinned_monitored = true;
+        if self
+            .init(
+                ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+                Some(high_events),
+                profiler_info.clone(),
+                client_data,
+                client_data_length,
+            )
+            .is_err()
+        {
+            // Runtimes prior to .NET 5 have no Pinned Object Heap
+            warn!("Could not monitor pinned object allocations, falling back to large object allocations only");
+            self.pinned_monitored = false;
+            self.init(
+                ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+                Some(ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_LARGEOBJECT_ALLOCATED),
+                profiler_info,
+                client_data,
+                client_data_length,
+            )?;
+        }
+
+        self.loh_threshold = self
+            .clr()
+            .get_loh_object_size_threshold()
+            .map_or(DEFAULT_LOH_THRESHOLD, |threshold| threshold as u64);
+        self.caller_to_callee = self.session_info().get_parameter::<bool>("caller_to_callee").unwrap();
+        self.start = Some(Instant::now());
+
+        Ok(())
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let allocations = self.allocations.clone();
+        let loh_threshold = self.loh_threshold;
+        let pinned_monitored = self.pinned_monitored;
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || LargeObjectAllocationsProfiler::profile(session_info, clr, allocations, loh_threshold, pinned_monitored));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for LargeObjectAllocationsProfiler {}
+impl CorProfilerCallback5 for LargeObjectAllocationsProfiler {}
+impl CorProfilerCallback6 for LargeObjectAllocationsProfiler {}
+impl CorProfilerCallback7 for LargeObjectAllocationsProfiler {}
+impl CorProfilerCallback8 for LargeObjectAllocationsProfiler {}
+impl CorProfilerCallback9 for LargeObjectAllocationsProfiler {}
+impl CorProfilerCallback10 for LargeObjectAllocationsProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{LargeHeap, SizeHistogram};
+    use crate::api::ffi::COR_PRF_GC_GENERATION;
+
+    #[test]
+    fn histogram_buckets_sizes_by_powers_of_two() {
+        let mut histogram = SizeHistogram::default();
+        for size in [85_000, 100_000, 131_072, 1_000_000] {
+            histogram.record(size);
+        }
+
+        let buckets: Vec<(u64, u64, u64)> = histogram.iter().map(|(lower, upper, stats)| (lower, upper, stats.count)).collect();
+        assert_eq!(buckets, vec![(65_536, 131_072, 2), (131_072, 262_144, 1), (524_288, 1_048_576, 1)]);
+    }
+
+    #[test]
+    fn heap_is_classified_by_generation_then_size() {
+        let poh = Some(COR_PRF_GC_GENERATION::COR_PRF_GC_PINNED_OBJECT_HEAP);
+        assert_eq!(LargeHeap::classify(poh, 100_000, 85_000), LargeHeap::Pinned);
+        assert_eq!(LargeHeap::classify(None, 100_000, 85_000), LargeHeap::Large);
+        assert_eq!(LargeHeap::classify(None, 1_000, 85_000), LargeHeap::Unknown);
+    }
+}
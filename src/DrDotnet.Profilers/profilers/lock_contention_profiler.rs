@@ -0,0 +1,599 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::ops::AddAssign;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{ClassID, FunctionID, ThreadID, DWORD, EVENTPIPE_PROVIDER, HRESULT, LPCGUID, UINT_PTR};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{
+    contention_events_provider, format_duration, CachedNameResolver, ContentionStart, ContentionStop, ManagedStackSnapshotCallbackReceiver, NameResolver,
+    StackSnapshotCallbackReceiver, TreeNode, CONTENTION_START_EVENT_ID, CONTENTION_STOP_EVENT_ID,
+};
+
+// Methods a thread waits in when it is blocked, along with the synchronization primitive they belong to.
+// Waits for a condition or an event to be signaled (Monitor.Wait, ManualResetEventSlim, WaitHandle) are not
+// contention and have no primitive. WaitHandle waits are excluded as events can't be told apart from mutexes.
+// Order matters: the first matching prefix wins.
+const WAIT_METHODS: &[(&str, Option<&str>)] = &[
+    ("System.Threading.Monitor.Wait", None),
+    ("System.Threading.Monitor.ObjWait", None),
+    ("System.Threading.Monitor.", Some("Monitor")),
+    ("System.Threading.Lock.", Some("Lock")),
+    ("System.Threading.SemaphoreSlim.", Some("SemaphoreSlim")),
+    ("System.Threading.ReaderWriterLockSlim.", Some("ReaderWriterLockSlim")),
+    ("System.Threading.SpinLock.", Some("SpinLock")),
+    ("System.Threading.ManualResetEventSlim.", None),
+    ("System.Threading.WaitHandle.", None),
+];
+
+// Primitives whose contentions the runtime reports through its ContentionStart/ContentionStop events.
+// They are left out of the samples when these events are collected, so that waits aren't counted twice.
+const EVENT_PRIMITIVES: &[&str] = &["Monitor", "Lock"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitKind {
+    // Waiting to acquire a synchronization primitive held by another thread
+    Contention(&'static str),
+    // Waiting for a condition or an event to be signaled
+    Signal,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Contention {
+    pub waits: u64,
+    pub duration: Duration,
+}
+
+// Implement AddAssign for get_inclusive_value to be usable
+impl AddAssign<&Contention> for Contention {
+    fn add_assign(&mut self, other: &Self) {
+        self.waits += other.waits;
+        self.duration += other.duration;
+    }
+}
+
+impl Display for Contention {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} waits / ~{} ms", self.waits, self.duration.as_millis())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wait {
+    pub thread_id: ThreadID,
+    pub primitive: &'static str,
+    // Type of the object the lock belongs to, when reported by the runtime
+    pub lock_class_id: Option<ClassID>,
+    pub method_ids: Vec<FunctionID>,
+    pub duration: Duration,
+    // Whether the duration is estimated from samples rather than measured
+    pub estimated: bool,
+}
+
+// Follows waiting threads from one sample to the next. A wait starts when a thread is first seen blocked on a
+// synchronization primitive, and stops as soon as the thread is seen doing something else.
+#[derive(Default)]
+pub struct ContentionTracker {
+    ongoing_waits: HashMap<ThreadID, Wait>,
+    pub finished_waits: Vec<Wait>,
+}
+
+impl ContentionTracker {
+    pub fn record(&mut self, thread_id: ThreadID, waiting_on: Option<(&'static str, Vec<FunctionID>)>, interval: Duration) {
+        match waiting_on {
+            Some((primitive, method_ids)) => {
+                if let Some(wait) = self.ongoing_waits.get_mut(&thread_id) {
+                    if wait.primitive == primitive && wait.method_ids == method_ids {
+                        wait.duration += interval;
+                        return;
+                    }
+                }
+                let wait = Wait {
+                    thread_id,
+                    primitive,
+                    lock_class_id: None,
+                    method_ids,
+                    duration: interval,
+                    estimated: true,
+                };
+                if let Some(previous_wait) = self.ongoing_waits.insert(thread_id, wait) {
+                    self.finished_waits.push(previous_wait);
+                }
+            }
+            None => {
+                if let Some(wait) = self.ongoing_waits.remove(&thread_id) {
+                    self.finished_waits.push(wait);
+                }
+            }
+        }
+    }
+
+    // Ends every ongoing wait and returns all the recorded waits
+    pub fn finish(mut self) -> Vec<Wait> {
+        self.finished_waits.extend(self.ongoing_waits.drain().map(|(_, wait)| wait));
+        self.finished_waits
+    }
+}
+
+// Follows the waits reported by the runtime's ContentionStart/ContentionStop events, which are raised on the waiting thread
+#[derive(Default)]
+pub struct ContentionEvents {
+    ongoing_waits: HashMap<ThreadID, (Instant, Wait)>,
+    finished_waits: Vec<Wait>,
+}
+
+impl ContentionEvents {
+    pub fn started(&mut self, wait: Wait, now: Instant) {
+        self.ongoing_waits.insert(wait.thread_id, (now, wait));
+    }
+
+    // The duration reported by the runtime is preferred, as it doesn't include the time spent in profiler callbacks
+    pub fn stopped(&mut self, thread_id: ThreadID, duration: Option<Duration>, now: Instant) {
+        if let Some((started_at, mut wait)) = self.ongoing_waits.remove(&thread_id) {
+            wait.duration = duration.unwrap_or(now - started_at);
+            self.finished_waits.push(wait);
+        }
+    }
+
+    // Ends every ongoing wait and returns all the recorded waits
+    pub fn finish(&mut self, now: Instant) -> Vec<Wait> {
+        for (_, (started_at, mut wait)) in self.ongoing_waits.drain() {
+            wait.duration = now - started_at;
+            self.finished_waits.push(wait);
+        }
+        std::mem::take(&mut self.finished_waits)
+    }
+}
+
+#[derive(Default)]
+pub struct LockContentionProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    contention_events: Arc<Mutex<ContentionEvents>>,
+}
+
+impl Profiler for LockContentionProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "4DEFCE29-9AA4-4C61-885C-F8FF91AADF67".to_owned(),
+            name: "List lock contentions".to_owned(),
+            description: "Records Monitor (lock) contentions from the runtime's contention start/stop events (.NET 5 or later), with their duration, the waiting callstack and the type of the contended object (.NET 8 or later). Samples callstacks every X ms to detect threads blocked on other synchronization primitives (SemaphoreSlim, ReaderWriterLockSlim, ...), which the runtime doesn't report. Lists the time lost waiting per call site in a tree view, along with the worst individual waits. Waits for conditions and events (Monitor.Wait, ManualResetEventSlim, WaitHandle) are not counted as contention.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define(
+                    "Time Interval",
+                    "time_interval_ms",
+                    10,
+                    "Time interval between two samples in milliseconds, greater than 0. This is also the precision of the wait durations estimated from samples.",
+                ),
+                ProfilerParameter::define("Maximum waits to display", "max_waits", 20, "The maximum number of individual waits to display"),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl LockContentionProfiler {
+    // Returns the kind of wait a method belongs to, if any
+    pub fn get_wait_kind(method_name: &str) -> Option<WaitKind> {
+        WAIT_METHODS
+            .iter()
+            .find(|(prefix, _)| method_name.starts_with(prefix))
+            .map(|(_, primitive)| match primitive {
+                Some(primitive) => WaitKind::Contention(primitive),
+                None => WaitKind::Signal,
+            })
+    }
+
+    // Returns the primitive a thread is contending on, given the names of its frames, leaf first.
+    // Waits go through a few internal frames before blocking, all in System.Threading, and primitives are often built
+    // on top of each other (SemaphoreSlim and ManualResetEventSlim wait in Monitor.Wait), so the outermost one wins.
+    pub fn get_contended_primitive(method_names: impl IntoIterator<Item = String>) -> Option<&'static str> {
+        let wait_kind = method_names
+            .into_iter()
+            .take_while(|method_name| method_name.starts_with("System.Threading."))
+            .filter_map(|method_name| Self::get_wait_kind(&method_name))
+            .last();
+
+        match wait_kind {
+            Some(WaitKind::Contention(primitive)) => Some(primitive),
+            _ => None,
+        }
+    }
+
+    // Returns the primitive the thread is contending on, looking at its leaf frames
+    pub fn get_waiting_primitive(name_resolver: &CachedNameResolver, method_ids: &[FunctionID]) -> Option<&'static str> {
+        Self::get_contended_primitive(method_ids.iter().map(|&method_id| name_resolver.get_full_method_name(method_id, 0)))
+    }
+
+    // Waits on the primitive are reported by contention events and can be left out of the samples
+    fn is_reported_by_events(primitive: &str) -> bool {
+        EVENT_PRIMITIVES.contains(&primitive)
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, contention_events: Arc<Mutex<ContentionEvents>>) {
+        let time_interval_ms = session_info.get_parameter::<u64>("time_interval_ms").unwrap();
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        let interval = Duration::from_millis(time_interval_ms);
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+
+        if time_interval_ms == 0 {
+            let mut report = session_info.create_report("lock_contention.html".to_owned());
+            report.write_line("<h2>Lock Contention</h2>".to_owned());
+            report.write_line("<p>⚠️ The time interval must be greater than 0 ms.</p>".to_owned());
+            if let Err(e) = clr.request_profiler_detach(3000) {
+                error!("Could not detach for reason: {:?}", e);
+            }
+            return;
+        }
+
+        let event_pipe_session = clr.event_pipe_start_session(&[contention_events_provider()], false);
+        if let Err(hresult) = &event_pipe_session {
+            error!("Error starting EventPipe session, all waits will be sampled: {:?}", hresult);
+        }
+        let events_collected = event_pipe_session.is_ok();
+
+        let mut tracker = ContentionTracker::default();
+
+        let iterations = 1000 * duration_seconds / time_interval_ms;
+        let mut last_sample = Instant::now();
+        for _ in 0..iterations {
+            std::thread::sleep(interval);
+
+            // Each sample stands for the time elapsed since the previous one, which includes the time spent suspending
+            // the runtime and walking the stacks, so that wait durations don't drift below the actual wall time
+            let now = Instant::now();
+            let elapsed = now - last_sample;
+            last_sample = now;
+
+            // https://github.com/dotnet/runtime/issues/37586#issuecomment-641114483
+            if clr.suspend_runtime().is_err() {
+                error!("Can't suspend runtime!");
+                continue;
+            }
+
+            // The runtime must be resumed whatever happens, so errors are only handled once it is
+            let stacks: Result<Vec<_>, HRESULT> = clr.enum_threads().map(|threads| {
+                threads
+                    .map(|managed_thread_id| {
+                        let mut receiver = ManagedStackSnapshotCallbackReceiver::default();
+                        receiver.do_stack_snapshot(clr.clone(), managed_thread_id, false);
+                        (managed_thread_id, receiver.method_ids)
+                    })
+                    .collect()
+            });
+
+            if clr.resume_runtime().is_err() {
+                error!("Can't resume runtime!");
+            }
+
+            let stacks = match stacks {
+                Ok(stacks) => stacks,
+                Err(hresult) => {
+                    error!("Can't enumerate threads: {:?}", hresult);
+                    continue;
+                }
+            };
+
+            // Names are resolved once the runtime is resumed to keep the pauses as short as possible
+            for (thread_id, method_ids) in stacks {
+                let waiting_on = Self::get_waiting_primitive(&name_resolver, &method_ids)
+                    .filter(|primitive| !(events_collected && Self::is_reported_by_events(primitive)))
+                    .map(|primitive| (primitive, method_ids));
+                tracker.record(thread_id, waiting_on, elapsed);
+            }
+        }
+
+        if let Ok(session) = &event_pipe_session {
+            if let Err(hresult) = clr.event_pipe_stop_session(*session) {
+                error!("Error stopping EventPipe session: {:?}", hresult);
+            }
+        }
+
+        let mut waits = tracker.finish();
+        waits.extend(contention_events.lock().unwrap().finish(Instant::now()));
+        Self::write_report(&session_info, &name_resolver, waits, event_pipe_session.err());
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn write_report(session_info: &SessionInfo, name_resolver: &CachedNameResolver, waits: Vec<Wait>, events_error: Option<HRESULT>) {
+        let max_waits = session_info.get_parameter::<usize>("max_waits").unwrap();
+
+        // Merge waits per contended lock and call site
+        let mut trees_by_lock: HashMap<(&'static str, Option<ClassID>), TreeNode<FunctionID, Contention>> = HashMap::new();
+        for wait in waits.iter() {
+            let tree = trees_by_lock.entry((wait.primitive, wait.lock_class_id)).or_insert_with(|| TreeNode::new(0));
+            let node = tree.add_sequence(wait.method_ids.iter().copied());
+            *node.value.get_or_insert_with(Contention::default) += &Contention {
+                waits: 1,
+                duration: wait.duration,
+            };
+        }
+
+        let compare = &|a: &TreeNode<FunctionID, Contention>, b: &TreeNode<FunctionID, Contention>| {
+            b.get_inclusive_value().duration.cmp(&a.get_inclusive_value().duration)
+        };
+        for tree in trees_by_lock.values_mut() {
+            tree.sort_by_iterative(compare);
+        }
+
+        let mut report = session_info.create_report("lock_contention.html".to_owned());
+        report.write_line("<h2>Lock Contention</h2>".to_owned());
+        match events_error {
+            None => report.write_line(
+                "<p>Monitor (lock) waits are measured from the runtime's contention events, which also tell the type of the contended object \
+                from .NET 8. Waits on other primitives are estimated from periodic samples, and are only as precise as the sampling interval.</p>"
+                    .to_owned(),
+            ),
+            Some(hresult) => report.write_line(format!(
+                "<p>⚠️ The runtime's contention events could not be collected ({hresult:?}), which requires .NET 5 or later: \
+                all waits are estimated from periodic samples, are only as precise as the sampling interval, and the type of the contended objects is unknown.</p>"
+            )),
+        }
+
+        report.write_line("<h3>Wait Time per Call Site</h3>".to_owned());
+        for ((primitive, lock_class_id), tree) in trees_by_lock.iter().sorted_by(|a, b| compare(a.1, b.1)) {
+            let inclusive = tree.get_inclusive_value();
+            report.write_line(format!(
+                "<details><summary>{} \
+                <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">radio_button_checked</i></div></summary>",
+                Self::format_lock(name_resolver, primitive, *lock_class_id)
+            ));
+            report.write_line("<ul>".to_owned());
+            for node in &tree.children {
+                node.print_html(&mut report, &|node| Self::format_html_line(name_resolver, node));
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+
+        report.write_line("<h3>Worst Waits</h3>".to_owned());
+        for wait in waits.iter().sorted_by(|a, b| b.duration.cmp(&a.duration)).take(max_waits) {
+            let duration = if wait.estimated {
+                format!("~{} ms", wait.duration.as_millis())
+            } else {
+                format_duration(wait.duration)
+            };
+            report.write_line(format!(
+                "<details><summary>{} on thread {} \
+                <div class=\"chip\"><span>{duration}</span><i class=\"material-icons\">hourglass_empty</i></div></summary>",
+                Self::format_lock(name_resolver, wait.primitive, wait.lock_class_id),
+                wait.thread_id
+            ));
+            report.write_line("<ul>".to_owned());
+            for method_id in wait.method_ids.iter() {
+                let method_name = name_resolver.get_full_method_name(*method_id, 0);
+                report.write_line(format!("<li><code>{}</code></li>", html_escape::encode_text(&method_name)));
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+    }
+
+    fn format_lock(name_resolver: &CachedNameResolver, primitive: &str, lock_class_id: Option<ClassID>) -> String {
+        match lock_class_id {
+            Some(class_id) => format!(
+                "<code>{primitive}</code> on <code>{}</code>",
+                html_escape::encode_text(&name_resolver.get_class_name(class_id))
+            ),
+            None => format!("<code>{primitive}</code>"),
+        }
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, Contention>) -> String {
+        let inclusive = node.get_inclusive_value();
+        let exclusive = node.value.unwrap_or_default();
+
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{inclusive}</span><i class=\"material-icons\">radio_button_checked</i></div> \
+            <div class=\"chip\"><span>{exclusive}</span><i class=\"material-icons\">radio_button_unchecked</i></div>"
+        )
+    }
+}
+
+impl CorProfilerCallback for LockContentionProfiler {}
+
+impl CorProfilerCallback2 for LockContentionProfiler {}
+
+impl CorProfilerCallback3 for LockContentionProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+            Some(ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_EVENT_PIPE),
+            profiler_info,
+            client_data,
+            client_data_length,
+        )
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let contention_events = self.contention_events.clone();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || LockContentionProfiler::profile(session_info, clr, contention_events));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for LockContentionProfiler {}
+impl CorProfilerCallback5 for LockContentionProfiler {}
+impl CorProfilerCallback6 for LockContentionProfiler {}
+impl CorProfilerCallback7 for LockContentionProfiler {}
+impl CorProfilerCallback8 for LockContentionProfiler {}
+impl CorProfilerCallback9 for LockContentionProfiler {}
+
+impl CorProfilerCallback10 for LockContentionProfiler {
+    fn event_pipe_event_delivered(
+        &mut self,
+        _provider: EVENTPIPE_PROVIDER,
+        event_id: DWORD,
+        event_version: DWORD,
+        _metadata_blob: &[u8],
+        event_data: &[u8],
+        _activity_id: LPCGUID,
+        _related_activity_id: LPCGUID,
+        event_thread: ThreadID,
+        _stack_frames: &[UINT_PTR],
+    ) -> Result<(), HRESULT> {
+        match event_id {
+            CONTENTION_START_EVENT_ID => {
+                let contention_start = match ContentionStart::parse(event_version, event_data) {
+                    Some(contention_start) if contention_start.managed => contention_start,
+                    _ => return Ok(()),
+                };
+
+                let lock_class_id = contention_start
+                    .associated_object_id
+                    .and_then(|object_id| self.clr().get_class_from_object(object_id).ok());
+                let primitive = match lock_class_id {
+                    Some(class_id) if self.clr().get_class_name(class_id) == "System.Threading.Lock" => "Lock",
+                    _ => "Monitor",
+                };
+
+                // The event is raised on the waiting thread, so the current callstack is the waiting callstack
+                let method_ids = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(self.clr().clone());
+                let wait = Wait {
+                    thread_id: event_thread,
+                    primitive,
+                    lock_class_id,
+                    method_ids,
+                    duration: Duration::ZERO,
+                    estimated: false,
+                };
+                self.contention_events.lock().unwrap().started(wait, Instant::now());
+            }
+            CONTENTION_STOP_EVENT_ID => {
+                if let Some(contention_stop) = ContentionStop::parse(event_version, event_data).filter(|contention_stop| contention_stop.managed) {
+                    self.contention_events
+                        .lock()
+                        .unwrap()
+                        .stopped(event_thread, contention_stop.duration, Instant::now());
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentionEvents, ContentionTracker, LockContentionProfiler, Wait, WaitKind};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn wait_primitives_are_recognized() {
+        assert_eq!(
+            LockContentionProfiler::get_wait_kind("System.Threading.Monitor.ReliableEnter"),
+            Some(WaitKind::Contention("Monitor"))
+        );
+        assert_eq!(
+            LockContentionProfiler::get_wait_kind("System.Threading.SemaphoreSlim.WaitUntilCountOrTimeout"),
+            Some(WaitKind::Contention("SemaphoreSlim"))
+        );
+        assert_eq!(LockContentionProfiler::get_wait_kind("System.Threading.Monitor.Wait"), Some(WaitKind::Signal));
+        assert_eq!(LockContentionProfiler::get_wait_kind("System.Threading.Thread.Sleep"), None);
+    }
+
+    #[test]
+    fn outermost_primitive_decides_whether_a_wait_is_contention() {
+        let frames = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+
+        // SemaphoreSlim waits in Monitor.Wait, but is contended on
+        let semaphore_wait = frames(&[
+            "System.Threading.Monitor.Wait",
+            "System.Threading.SemaphoreSlim.WaitUntilCountOrTimeout",
+            "System.Threading.SemaphoreSlim.Wait",
+            "App.Worker.Run",
+        ]);
+        assert_eq!(LockContentionProfiler::get_contended_primitive(semaphore_wait), Some("SemaphoreSlim"));
+
+        // Task.Wait waits for an event to be signaled
+        let task_wait = frames(&[
+            "System.Threading.Monitor.Wait",
+            "System.Threading.ManualResetEventSlim.Wait",
+            "System.Threading.Tasks.Task.SpinThenBlockingWait",
+            "App.Worker.Run",
+        ]);
+        assert_eq!(LockContentionProfiler::get_contended_primitive(task_wait), None);
+
+        // Frames past the first application frame are not part of the wait
+        let nested = frames(&["System.Threading.Monitor.ReliableEnter", "App.Worker.Run", "System.Threading.Monitor.Wait"]);
+        assert_eq!(LockContentionProfiler::get_contended_primitive(nested), Some("Monitor"));
+    }
+
+    #[test]
+    fn tracker_measures_consecutive_samples_as_one_wait() {
+        let interval = Duration::from_millis(10);
+        let mut tracker = ContentionTracker::default();
+
+        tracker.record(1, Some(("Monitor", vec![1, 2])), interval);
+        tracker.record(1, Some(("Monitor", vec![1, 2])), interval);
+        tracker.record(1, Some(("Monitor", vec![1, 2])), interval);
+        tracker.record(1, None, interval);
+        tracker.record(2, Some(("SemaphoreSlim", vec![3])), interval);
+
+        let waits = tracker.finish();
+
+        assert_eq!(waits.len(), 2);
+        assert_eq!(waits[0].duration, Duration::from_millis(30));
+        assert_eq!(waits[1].primitive, "SemaphoreSlim");
+    }
+
+    #[test]
+    fn contention_events_prefer_the_duration_reported_by_the_runtime() {
+        let wait = |thread_id| Wait {
+            thread_id,
+            primitive: "Monitor",
+            lock_class_id: Some(42),
+            method_ids: vec![1, 2],
+            duration: Duration::ZERO,
+            estimated: false,
+        };
+        let start = Instant::now();
+        let mut events = ContentionEvents::default();
+
+        events.started(wait(1), start);
+        events.stopped(1, Some(Duration::from_millis(3)), start + Duration::from_millis(5));
+        events.started(wait(2), start);
+        events.stopped(2, None, start + Duration::from_millis(7));
+        events.stopped(3, Some(Duration::from_millis(1)), start);
+        events.started(wait(4), start);
+
+        let waits = events.finish(start + Duration::from_millis(10));
+
+        assert_eq!(waits.len(), 3);
+        assert_eq!(waits[0].duration, Duration::from_millis(3));
+        assert_eq!(waits[1].duration, Duration::from_millis(7));
+        // Waits still ongoing at the end of the session last until then
+        assert_eq!(waits[2].duration, Duration::from_millis(10));
+    }
+}
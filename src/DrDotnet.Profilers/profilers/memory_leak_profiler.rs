@@ -448,3 +448,4 @@ impl CorProfilerCallback6 for MemoryLeakProfiler {}
 impl CorProfilerCallback7 for MemoryLeakProfiler {}
 impl CorProfilerCallback8 for MemoryLeakProfiler {}
 impl CorProfilerCallback9 for MemoryLeakProfiler {}
+impl CorProfilerCallback10 for MemoryLeakProfiler {}
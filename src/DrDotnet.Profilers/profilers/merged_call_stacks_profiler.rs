@@ -351,3 +351,4 @@ impl CorProfilerCallback6 for MergedCallStacksProfiler {}
 impl CorProfilerCallback7 for MergedCallStacksProfiler {}
 impl CorProfilerCallback8 for MergedCallStacksProfiler {}
 impl CorProfilerCallback9 for MergedCallStacksProfiler {}
+impl CorProfilerCallback10 for MergedCallStacksProfiler {}
@@ -22,13 +22,73 @@ pub use duplicated_strings_profiler::DuplicatedStringsProfiler;
 pub mod merged_call_stacks_profiler;
 pub use merged_call_stacks_profiler::MergedCallStacksProfiler;
 
+pub mod allocation_call_sites_profiler;
+pub use allocation_call_sites_profiler::AllocationCallSitesProfiler;
+
+pub mod lock_contention_profiler;
+pub use lock_contention_profiler::LockContentionProfiler;
+
+pub mod thread_lifecycle_profiler;
+pub use thread_lifecycle_profiler::ThreadLifecycleProfiler;
+
+pub mod jit_cost_profiler;
+pub use jit_cost_profiler::JitCostProfiler;
+
+pub mod module_loads_profiler;
+pub use module_loads_profiler::ModuleLoadsProfiler;
+
+pub mod finalization_profiler;
+pub use finalization_profiler::FinalizationProfiler;
+
+pub mod gc_handles_profiler;
+pub use gc_handles_profiler::GCHandlesProfiler;
+
+pub mod call_tree_profiler;
+pub use call_tree_profiler::CallTreeProfiler;
+
+pub mod wall_clock_profiler;
+pub use wall_clock_profiler::WallClockProfiler;
+
+pub mod large_object_allocations_profiler;
+pub use large_object_allocations_profiler::LargeObjectAllocationsProfiler;
+
+pub mod heap_census_profiler;
+pub use heap_census_profiler::HeapCensusProfiler;
+
+pub mod static_fields_profiler;
+pub use static_fields_profiler::StaticFieldsProfiler;
+
+pub mod collection_sizing_profiler;
+pub use collection_sizing_profiler::CollectionSizingProfiler;
+
+pub mod boxed_values_profiler;
+pub use boxed_values_profiler::BoxedValuesProfiler;
+
+pub mod duplicated_arrays_profiler;
+pub use duplicated_arrays_profiler::DuplicatedArraysProfiler;
+
+pub mod pinned_objects_profiler;
+pub use pinned_objects_profiler::PinnedObjectsProfiler;
+
+pub mod conditional_weak_tables_profiler;
+pub use conditional_weak_tables_profiler::ConditionalWeakTablesProfiler;
+
+pub mod gc_compaction_profiler;
+pub use gc_compaction_profiler::GCCompactionProfiler;
+
+pub mod async_tasks_profiler;
+pub use async_tasks_profiler::AsyncTasksProfiler;
+
+pub mod dynamic_methods_profiler;
+pub use dynamic_methods_profiler::DynamicMethodsProfiler;
+
 use simplelog::*;
 use std::fs::File;
 
 use crate::api::*;
 use crate::rust_protobuf_protos::interop::*;
 
-pub trait Profiler: CorProfilerCallback9 {
+pub trait Profiler: CorProfilerCallback10 {
     fn profiler_info() -> ProfilerInfo;
 
     fn session_info(&self) -> &SessionInfo;
@@ -0,0 +1,408 @@
+use itertools::Itertools;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{FunctionID, ModuleID, COR_PRF_MODULE_FLAGS, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleRecord {
+    pub module_id: ModuleID,
+    pub assembly_name: String,
+    pub version: String,
+    pub app_domain_name: String,
+    pub file_name: String,
+    pub flags: COR_PRF_MODULE_FLAGS,
+    // Elapsed time since the beginning of the session. None if the module was already loaded when the profiler attached.
+    pub loaded_at: Option<Duration>,
+    pub unloaded_at: Option<Duration>,
+    // Managed callstack of the thread that loaded the module, from the leaf frame to the root frame
+    pub load_stack: Vec<FunctionID>,
+}
+
+impl ModuleRecord {
+    pub fn get_flags_description(&self) -> String {
+        let mut flags = Vec::new();
+        if self.flags.contains(COR_PRF_MODULE_FLAGS::COR_PRF_MODULE_NGEN) {
+            flags.push("ReadyToRun");
+        }
+        if self.flags.contains(COR_PRF_MODULE_FLAGS::COR_PRF_MODULE_DYNAMIC) {
+            flags.push("Dynamic");
+        }
+        if self.flags.contains(COR_PRF_MODULE_FLAGS::COR_PRF_MODULE_COLLECTIBLE) {
+            flags.push("Collectible");
+        }
+        if self.flags.contains(COR_PRF_MODULE_FLAGS::COR_PRF_MODULE_RESOURCE) {
+            flags.push("Resource");
+        }
+        flags.join(", ")
+    }
+
+    // Modules already loaded when the profiler attached are loaded since the beginning of the session,
+    // and modules that aren't unloaded stay loaded until its end
+    pub fn overlaps(&self, other: &ModuleRecord) -> bool {
+        let loaded_before_unload =
+            |module: &ModuleRecord, other: &ModuleRecord| module.loaded_at.unwrap_or_default() < other.unloaded_at.unwrap_or(Duration::MAX);
+        loaded_before_unload(self, other) && loaded_before_unload(other, self)
+    }
+}
+
+// An assembly that was loaded more than once, whether in different versions, app domains or load contexts
+#[derive(Debug, PartialEq)]
+pub struct LoadConflict<'a> {
+    pub assembly_name: &'a str,
+    pub modules: Vec<&'a ModuleRecord>,
+    pub versions: Vec<&'a str>,
+    pub app_domains: Vec<&'a str>,
+    pub several_load_contexts: bool,
+}
+
+// An assembly can only be loaded once per load context, so an assembly loaded several times at once in the same
+// app domain was loaded into several load contexts. Loads that follow each other, such as a collectible load context
+// being unloaded and created again, can share the same load context.
+pub fn find_load_conflicts(modules: &[ModuleRecord]) -> Vec<LoadConflict<'_>> {
+    modules
+        .iter()
+        .filter(|module| !module.flags.contains(COR_PRF_MODULE_FLAGS::COR_PRF_MODULE_RESOURCE))
+        .into_group_map_by(|module| module.assembly_name.as_str())
+        .into_iter()
+        .filter(|(_, modules)| modules.len() > 1)
+        .map(|(assembly_name, modules)| LoadConflict {
+            assembly_name,
+            versions: modules.iter().map(|module| module.version.as_str()).unique().sorted().collect(),
+            app_domains: modules.iter().map(|module| module.app_domain_name.as_str()).unique().sorted().collect(),
+            several_load_contexts: modules
+                .iter()
+                .tuple_combinations()
+                .any(|(a, b)| a.app_domain_name == b.app_domain_name && a.overlaps(b)),
+            modules,
+        })
+        .sorted_by(|a, b| a.assembly_name.cmp(b.assembly_name))
+        .collect()
+}
+
+#[derive(Default)]
+pub struct ModuleLoadsProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    start: Option<Instant>,
+    modules: Arc<Mutex<Vec<ModuleRecord>>>,
+}
+
+impl Profiler for ModuleLoadsProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "CA54218A-EDCB-421F-B4E2-58088197397B".to_owned(),
+            name: "List loaded modules and assemblies".to_owned(),
+            description: "Lists every loaded assembly and module with its version, app domain and flags (ReadyToRun, dynamic, collectible), records modules loaded and unloaded while attached along with the loading callstack, and highlights assemblies loaded several times, in different versions or into several load contexts.".to_owned(),
+            parameters: vec![ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds")],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl ModuleLoadsProfiler {
+    fn elapsed(&self) -> Duration {
+        self.start.map(|start| start.elapsed()).unwrap_or_default()
+    }
+
+    fn get_module_record(clr: &ClrProfilerInfo, module_id: ModuleID) -> Result<ModuleRecord, HRESULT> {
+        let module_info = clr.get_module_info_2(module_id)?;
+        let assembly_info = clr.get_assembly_info(module_info.assembly_id)?;
+        let app_domain_name = clr
+            .get_app_domain_info(assembly_info.app_domain_id)
+            .map(|app_domain_info| app_domain_info.name)
+            .unwrap_or_default();
+        // Dynamic modules have no manifest to read the version from
+        let version = clr.get_assembly_version(assembly_info.module_id).unwrap_or_default();
+
+        Ok(ModuleRecord {
+            module_id,
+            assembly_name: assembly_info.name,
+            version,
+            app_domain_name,
+            file_name: module_info.file_name,
+            flags: module_info.module_flags,
+            loaded_at: None,
+            unloaded_at: None,
+            load_stack: Vec::new(),
+        })
+    }
+
+    fn format_elapsed(elapsed: Option<Duration>) -> String {
+        elapsed.map(|elapsed| format!("{} ms", elapsed.as_millis())).unwrap_or_default()
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, modules: Arc<Mutex<Vec<ModuleRecord>>>) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        std::thread::sleep(Duration::from_secs(duration_seconds));
+
+        // Stop monitoring loads while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let modules = modules.lock().unwrap();
+        let conflicts = find_load_conflicts(&modules);
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut report = session_info.create_report("module_loads.html".to_owned());
+        report.write_line("<h2>Modules and Assemblies</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{} modules, {} loaded and {} unloaded in {duration_seconds} seconds</h4>",
+            modules.len(),
+            modules.iter().filter(|module| module.loaded_at.is_some()).count(),
+            modules.iter().filter(|module| module.unloaded_at.is_some()).count()
+        ));
+
+        report.write_line("<h3>Assemblies Loaded Several Times</h3>".to_owned());
+        if conflicts.is_empty() {
+            report.write_line("<p>No assembly was loaded more than once.</p>".to_owned());
+        }
+        for conflict in conflicts.iter() {
+            let mut flags = Vec::new();
+            if conflict.versions.len() > 1 {
+                flags.push(format!("⚠️ {} versions", conflict.versions.len()));
+            }
+            if conflict.app_domains.len() > 1 {
+                flags.push(format!("⚠️ {} app domains", conflict.app_domains.len()));
+            }
+            if conflict.several_load_contexts {
+                flags.push("⚠️ Several load contexts".to_owned());
+            }
+            report.write_line(format!(
+                "<details><summary><code>{}</code> loaded {} times {}</summary>",
+                html_escape::encode_text(conflict.assembly_name),
+                conflict.modules.len(),
+                flags.join(" ")
+            ));
+            report.write_line("<ul>".to_owned());
+            for module in conflict.modules.iter() {
+                report.write_line(format!(
+                    "<li>{} in {} from <code>{}</code> {}</li>",
+                    html_escape::encode_text(&module.version),
+                    html_escape::encode_text(&module.app_domain_name),
+                    html_escape::encode_text(&module.file_name),
+                    html_escape::encode_text(&module.get_flags_description())
+                ));
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+
+        report.write_line("<h3>Inventory</h3>".to_owned());
+        report.write_line(
+            "<table><tr><th>Assembly</th><th>Version</th><th>App Domain</th><th>Flags</th><th>Path</th><th>Loaded at</th><th>Unloaded at</th></tr>".to_owned(),
+        );
+        for module in modules.iter().sorted_by(|a, b| a.assembly_name.cmp(&b.assembly_name)) {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td></tr>",
+                html_escape::encode_text(&module.assembly_name),
+                html_escape::encode_text(&module.version),
+                html_escape::encode_text(&module.app_domain_name),
+                module.get_flags_description(),
+                html_escape::encode_text(&module.file_name),
+                Self::format_elapsed(module.loaded_at),
+                Self::format_elapsed(module.unloaded_at)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Loads and Unloads</h3>".to_owned());
+        let events = modules
+            .iter()
+            .filter_map(|module| module.loaded_at.map(|elapsed| (elapsed, "Loaded", module)))
+            .chain(
+                modules
+                    .iter()
+                    .filter_map(|module| module.unloaded_at.map(|elapsed| (elapsed, "Unloaded", module))),
+            )
+            .sorted_by(|a, b| a.0.cmp(&b.0));
+        for (elapsed, event, module) in events {
+            report.write_line(format!(
+                "<details><summary>{} ms: {event} <code>{}</code> {}</summary>",
+                elapsed.as_millis(),
+                html_escape::encode_text(&module.assembly_name),
+                html_escape::encode_text(&module.version)
+            ));
+            report.write_line("<ul>".to_owned());
+            if event == "Loaded" {
+                for method_id in module.load_stack.iter() {
+                    let method_name = name_resolver.get_full_method_name(*method_id, 0);
+                    report.write_line(format!("<li><code>{}</code></li>", html_escape::encode_text(&method_name)));
+                }
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+}
+
+impl CorProfilerCallback for ModuleLoadsProfiler {
+    fn module_load_finished(&mut self, module_id: ModuleID, hr_status: HRESULT) -> Result<(), HRESULT> {
+        if hr_status != HRESULT::S_OK {
+            return Ok(());
+        }
+
+        let clr = self.clr().clone();
+        let mut record = match Self::get_module_record(&clr, module_id) {
+            Ok(record) => record,
+            Err(hresult) => {
+                warn!("Could not get info of module {}: {:?}", module_id, hresult);
+                return Ok(());
+            }
+        };
+        record.loaded_at = Some(self.elapsed());
+        // This callback is raised on the thread loading the module
+        record.load_stack = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(clr);
+
+        self.modules.lock().unwrap().push(record);
+        Ok(())
+    }
+
+    fn module_unload_started(&mut self, module_id: ModuleID) -> Result<(), HRESULT> {
+        let elapsed = self.elapsed();
+        let mut modules = self.modules.lock().unwrap();
+        // ModuleIDs can be reused once a module is unloaded, so only look for modules that are still loaded
+        if let Some(module) = modules
+            .iter_mut()
+            .rev()
+            .find(|module| module.module_id == module_id && module.unloaded_at.is_none())
+        {
+            module.unloaded_at = Some(elapsed);
+        }
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for ModuleLoadsProfiler {}
+
+impl CorProfilerCallback3 for ModuleLoadsProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.start = Some(Instant::now());
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_MODULE_LOADS | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+            None,
+            profiler_info,
+            client_data,
+            client_data_length,
+        )
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+
+        // Modules that were already loaded before the profiler attached
+        {
+            let mut modules = self.modules.lock().unwrap();
+            match clr.enum_modules() {
+                Ok(module_ids) => {
+                    for module_id in module_ids {
+                        if modules.iter().any(|module| module.module_id == module_id) {
+                            continue;
+                        }
+                        match Self::get_module_record(&clr, module_id) {
+                            Ok(record) => modules.push(record),
+                            Err(hresult) => warn!("Could not get info of module {}: {:?}", module_id, hresult),
+                        }
+                    }
+                }
+                Err(hresult) => error!("Could not enumerate modules loaded at attach: {:?}", hresult),
+            }
+        }
+
+        let session_info = self.session_info().clone();
+        let modules = self.modules.clone();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || ModuleLoadsProfiler::profile(session_info, clr, modules));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for ModuleLoadsProfiler {}
+impl CorProfilerCallback5 for ModuleLoadsProfiler {}
+impl CorProfilerCallback6 for ModuleLoadsProfiler {}
+impl CorProfilerCallback7 for ModuleLoadsProfiler {}
+impl CorProfilerCallback8 for ModuleLoadsProfiler {}
+impl CorProfilerCallback9 for ModuleLoadsProfiler {}
+impl CorProfilerCallback10 for ModuleLoadsProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_load_conflicts, ModuleRecord};
+    use crate::api::ffi::COR_PRF_MODULE_FLAGS;
+    use std::time::Duration;
+
+    fn module(assembly_name: &str, version: &str, app_domain_name: &str) -> ModuleRecord {
+        ModuleRecord {
+            module_id: 0,
+            assembly_name: assembly_name.to_owned(),
+            version: version.to_owned(),
+            app_domain_name: app_domain_name.to_owned(),
+            file_name: String::new(),
+            flags: COR_PRF_MODULE_FLAGS::COR_PRF_MODULE_DISK,
+            loaded_at: None,
+            unloaded_at: None,
+            load_stack: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn assemblies_loaded_several_times_are_conflicts() {
+        let modules = vec![
+            module("Plugin", "1.0.0.0", "clrhost"),
+            module("Plugin", "2.0.0.0", "clrhost"),
+            module("Newtonsoft.Json", "13.0.0.0", "clrhost"),
+            module("System.Runtime", "8.0.0.0", "clrhost"),
+            module("Newtonsoft.Json", "13.0.0.0", "clrhost"),
+        ];
+
+        let conflicts = find_load_conflicts(&modules);
+
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].assembly_name, "Newtonsoft.Json");
+        assert_eq!(conflicts[0].versions, vec!["13.0.0.0"]);
+        assert_eq!(conflicts[1].assembly_name, "Plugin");
+        assert_eq!(conflicts[1].versions, vec!["1.0.0.0", "2.0.0.0"]);
+        assert!(conflicts[1].several_load_contexts);
+    }
+
+    #[test]
+    fn only_loads_alive_at_the_same_time_are_in_several_load_contexts() {
+        let loaded = |loaded_at: Option<u64>, unloaded_at: Option<u64>| ModuleRecord {
+            loaded_at: loaded_at.map(Duration::from_millis),
+            unloaded_at: unloaded_at.map(Duration::from_millis),
+            ..module("Plugin", "1.0.0.0", "clrhost")
+        };
+
+        // A collectible load context unloaded and created again
+        let reloaded = vec![loaded(None, Some(100)), loaded(Some(200), Some(300)), loaded(Some(400), None)];
+        let conflicts = find_load_conflicts(&reloaded);
+        assert_eq!(conflicts.len(), 1);
+        assert!(!conflicts[0].several_load_contexts);
+
+        let overlapping = vec![loaded(None, Some(100)), loaded(Some(50), None)];
+        assert!(find_load_conflicts(&overlapping)[0].several_load_contexts);
+    }
+}
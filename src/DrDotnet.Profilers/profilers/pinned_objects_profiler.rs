@@ -0,0 +1,341 @@
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap};
+use std::thread;
+
+use crate::api::ffi::{ClassID, ObjectID, COR_PRF_GC_GENERATION, COR_PRF_GC_ROOT_FLAGS, COR_PRF_GC_ROOT_KIND, HRESULT, UINT_PTR};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{format_size, AllocationStats, CachedNameResolver, NameResolver, GENERATION_NAMES};
+
+// Returns, for each object of a heap range sorted by address, the free space between it and the previous object (or the range start)
+pub fn gaps_in_front(range_start: usize, objects: &[(usize, u64)]) -> Vec<u64> {
+    let mut cursor = range_start;
+    objects
+        .iter()
+        .map(|&(start, size)| {
+            let gap = start.saturating_sub(cursor) as u64;
+            cursor = cursor.max(start + size as usize);
+            gap
+        })
+        .collect()
+}
+
+pub struct PinnedObject {
+    pub class_id: ClassID,
+    pub object_id: ObjectID,
+    pub size: u64,
+    pub generation: Option<COR_PRF_GC_GENERATION>,
+    pub pinned_by: BTreeSet<&'static str>,
+    // Free space right in front of the pinned object, which compaction could not reclaim
+    pub trapped_bytes: u64,
+}
+
+#[derive(Default)]
+pub struct GenerationFragmentation {
+    pub ranges: u64,
+    pub size: u64,
+    pub live: u64,
+    pub pinned: AllocationStats,
+    pub trapped_bytes: u64,
+}
+
+#[derive(Default)]
+pub struct PinnedObjectsProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    objects: Vec<(ObjectID, ClassID, u64)>,
+    pinning_roots: Vec<(ObjectID, COR_PRF_GC_ROOT_KIND)>,
+    record_object_references: bool,
+}
+
+impl Profiler for PinnedObjectsProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "F018B484-5508-41B7-BDAD-B2CF9E1FF32D".to_owned(),
+            name: "List pinned objects and heap fragmentation".to_owned(),
+            description: "Perform a full blocking garbage collection and list the objects pinned by handles or by the stack, with their type, size, generation and address range. Estimates, for each generation, the free space trapped in front of pinned objects.".to_owned(),
+            parameters: vec![ProfilerParameter::define("Maximum objects", "max_objects", 100, "The maximum number of pinned objects to display")],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl PinnedObjectsProfiler {
+    fn root_kind_name(root_kind: COR_PRF_GC_ROOT_KIND) -> &'static str {
+        match root_kind {
+            COR_PRF_GC_ROOT_KIND::COR_PRF_GC_ROOT_STACK => "Stack",
+            COR_PRF_GC_ROOT_KIND::COR_PRF_GC_ROOT_HANDLE => "Handle",
+            COR_PRF_GC_ROOT_KIND::COR_PRF_GC_ROOT_FINALIZER => "Finalizer",
+            COR_PRF_GC_ROOT_KIND::COR_PRF_GC_ROOT_OTHER => "Other",
+        }
+    }
+
+    // Resolves pinning roots to the objects containing them, as stack pins can be interior pointers
+    fn resolve_pinned_objects(&self) -> HashMap<usize, PinnedObject> {
+        let mut pinned_objects: HashMap<usize, PinnedObject> = HashMap::new();
+        for &(address, root_kind) in &self.pinning_roots {
+            let index = self.objects.partition_point(|&(object_id, _, _)| object_id <= address);
+            if index == 0 {
+                continue;
+            }
+            let (object_id, class_id, size) = self.objects[index - 1];
+            if address >= object_id + size as usize {
+                continue;
+            }
+            pinned_objects
+                .entry(index - 1)
+                .or_insert_with(|| PinnedObject {
+                    class_id,
+                    object_id,
+                    size,
+                    generation: None,
+                    pinned_by: BTreeSet::new(),
+                    trapped_bytes: 0,
+                })
+                .pinned_by
+                .insert(Self::root_kind_name(root_kind));
+        }
+        pinned_objects
+    }
+
+    fn compute_fragmentation(&self, pinned_objects: &mut HashMap<usize, PinnedObject>) -> [GenerationFragmentation; 5] {
+        let mut fragmentation: [GenerationFragmentation; 5] = Default::default();
+
+        let ranges = match self.clr().get_generation_bounds() {
+            Ok(ranges) => ranges,
+            Err(hresult) => {
+                error!("Error getting generation bounds: {:?}", hresult);
+                return fragmentation;
+            }
+        };
+
+        for range in ranges {
+            let range_start = range.rangeStart;
+            let range_end = range_start + range.rangeLength;
+            let first = self.objects.partition_point(|&(object_id, _, _)| object_id < range_start);
+            let last = self.objects.partition_point(|&(object_id, _, _)| object_id < range_end);
+
+            let stats = &mut fragmentation[range.generation as usize];
+            stats.ranges += 1;
+            stats.size += range.rangeLength as u64;
+
+            let objects = self.objects[first..last].iter().map(|&(object_id, _, size)| (object_id, size)).collect_vec();
+            for (offset, gap) in gaps_in_front(range_start, &objects).into_iter().enumerate() {
+                stats.live += objects[offset].1;
+                if let Some(pinned_object) = pinned_objects.get_mut(&(first + offset)) {
+                    pinned_object.generation = Some(range.generation);
+                    pinned_object.trapped_bytes = gap;
+                    stats.pinned += &AllocationStats {
+                        count: 1,
+                        bytes: pinned_object.size,
+                    };
+                    stats.trapped_bytes += gap;
+                }
+            }
+        }
+
+        fragmentation
+    }
+
+    fn write_report(&self, pinned_objects: &HashMap<usize, PinnedObject>, fragmentation: &[GenerationFragmentation; 5]) {
+        let max_objects = self.session_info.get_parameter::<usize>("max_objects").unwrap();
+
+        let name_resolver = CachedNameResolver::new(self.clr().clone());
+        let mut report = self.session_info.create_report("pinned_objects.html".to_owned());
+        report.write_line("<h2>Pinned Objects</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{} pinned objects ({}), trapping about {} of free space</h4>",
+            pinned_objects.len(),
+            format_size(pinned_objects.values().map(|pinned_object| pinned_object.size).sum()),
+            format_size(pinned_objects.values().map(|pinned_object| pinned_object.trapped_bytes).sum())
+        ));
+
+        report.write_line("<h3>Fragmentation By Generation</h3>".to_owned());
+        report.write_line(
+            "<p>Free space is what remains of the generation ranges once live objects are removed. \
+            Free space right in front of a pinned object is considered trapped by the pin, as compaction cannot slide objects over it.</p>"
+                .to_owned(),
+        );
+        report.write_line(
+            "<table><tr><th>Generation</th><th>Ranges</th><th>Size</th><th>Live</th><th>Free</th><th>Pinned Objects</th><th>Trapped By Pins</th></tr>"
+                .to_owned(),
+        );
+        for (name, stats) in GENERATION_NAMES.iter().zip(fragmentation.iter()) {
+            report.write_line(format!(
+                "<tr><td>{name}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                stats.ranges,
+                format_size(stats.size),
+                format_size(stats.live),
+                format_size(stats.size.saturating_sub(stats.live)),
+                stats.pinned,
+                format_size(stats.trapped_bytes)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Pinned Objects</h3>".to_owned());
+        report.write_line(
+            "<table><tr><th>Type</th><th>Pinned By</th><th>Generation</th><th>Address Range</th><th>Size</th><th>Trapped In Front</th></tr>".to_owned(),
+        );
+        let sorted = pinned_objects
+            .values()
+            .sorted_by_key(|pinned_object| (std::cmp::Reverse(pinned_object.trapped_bytes), std::cmp::Reverse(pinned_object.size)));
+        for pinned_object in sorted.take(max_objects) {
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td><code>0x{:x} - 0x{:x}</code></td><td>{}</td><td>{}</td></tr>",
+                html_escape::encode_text(&name_resolver.get_class_name(pinned_object.class_id)),
+                pinned_object.pinned_by.iter().join(", "),
+                pinned_object.generation.map_or("?", |generation| GENERATION_NAMES[generation as usize]),
+                pinned_object.object_id,
+                pinned_object.object_id + pinned_object.size as usize,
+                format_size(pinned_object.size),
+                format_size(pinned_object.trapped_bytes)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+    }
+}
+
+impl CorProfilerCallback for PinnedObjectsProfiler {
+    fn object_references(&mut self, object_id: ObjectID, class_id: ClassID, _object_ref_ids: &[ObjectID]) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        let size = self.clr().get_object_size_2(object_id).unwrap_or(0) as u64;
+        self.objects.push((object_id, class_id, size));
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for PinnedObjectsProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        // Start recording objects
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        info!(
+            "GC finished, {} objects and {} pinning roots recorded",
+            self.objects.len(),
+            self.pinning_roots.len()
+        );
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        self.objects.sort_unstable_by_key(|&(object_id, _, _)| object_id);
+
+        let mut pinned_objects = self.resolve_pinned_objects();
+        let fragmentation = self.compute_fragmentation(&mut pinned_objects);
+        self.write_report(&pinned_objects, &fragmentation);
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+
+    fn root_references_2(
+        &mut self,
+        root_ref_ids: &[ObjectID],
+        root_kinds: &[COR_PRF_GC_ROOT_KIND],
+        root_flags: &[COR_PRF_GC_ROOT_FLAGS],
+        _root_ids: &[UINT_PTR],
+    ) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+
+        // Roots are reported along with objects once the GC is done, so their addresses match the recorded objects
+        for i in 0..root_ref_ids.len() {
+            if root_ref_ids[i] != 0 && root_flags[i].contains(COR_PRF_GC_ROOT_FLAGS::COR_PRF_GC_ROOT_PINNING) {
+                self.pinning_roots.push((root_ref_ids[i], root_kinds[i]));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for PinnedObjectsProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        let clr = self.clr().clone();
+        let _ = thread::spawn(move || {
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        })
+        .join();
+
+        // Security timeout
+        detach_after_duration::<PinnedObjectsProfiler>(self, 120);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for PinnedObjectsProfiler {}
+impl CorProfilerCallback5 for PinnedObjectsProfiler {}
+impl CorProfilerCallback6 for PinnedObjectsProfiler {}
+impl CorProfilerCallback7 for PinnedObjectsProfiler {}
+impl CorProfilerCallback8 for PinnedObjectsProfiler {}
+impl CorProfilerCallback9 for PinnedObjectsProfiler {}
+impl CorProfilerCallback10 for PinnedObjectsProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::gaps_in_front;
+
+    #[test]
+    fn gaps_are_measured_from_the_end_of_the_previous_object() {
+        // [0x1000 free 0x10][0x1010 24 B][0x1028 free 0x18][0x1040 16 B][0x1050 32 B]
+        let objects = [(0x1010, 24), (0x1040, 16), (0x1050, 32)];
+        assert_eq!(gaps_in_front(0x1000, &objects), vec![0x10, 0x18, 0]);
+        assert!(gaps_in_front(0x1000, &[]).is_empty());
+    }
+}
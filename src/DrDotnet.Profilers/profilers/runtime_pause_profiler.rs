@@ -187,3 +187,4 @@ impl CorProfilerCallback6 for RuntimePauseProfiler {}
 impl CorProfilerCallback7 for RuntimePauseProfiler {}
 impl CorProfilerCallback8 for RuntimePauseProfiler {}
 impl CorProfilerCallback9 for RuntimePauseProfiler {}
+impl CorProfilerCallback10 for RuntimePauseProfiler {}
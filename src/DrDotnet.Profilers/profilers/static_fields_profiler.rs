@@ -0,0 +1,457 @@
+use itertools::Itertools;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::thread;
+
+use crate::api::ffi::{mdTypeDef, ClassID, CorOpenFlags, ModuleID, ObjectID, ThreadID, COR_PRF_STATIC_TYPE, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{AllocationStats, CachedNameResolver, NameResolver};
+
+// CorFieldAttr flags
+const FD_STATIC: u32 = 0x10;
+const FD_LITERAL: u32 = 0x40;
+
+// Returns whether a field signature (FIELD calling convention, custom modifiers, then the field type) is the one of a reference type.
+// Static fields of value types are left aside, as they can't retain much memory unless they embed references themselves.
+pub fn is_reference_field_signature(signature: &[u8]) -> bool {
+    const FIELD: u8 = 0x06;
+    const CMOD_REQD: u8 = 0x1f;
+    const CMOD_OPT: u8 = 0x20;
+
+    if signature.first() != Some(&FIELD) {
+        return false;
+    }
+
+    let mut position = 1;
+    while let Some(&element_type) = signature.get(position) {
+        match element_type {
+            // Custom modifiers are followed by a compressed token of 1, 2 or 4 bytes
+            CMOD_REQD | CMOD_OPT => {
+                position += match signature.get(position + 1) {
+                    Some(byte) if byte & 0x80 == 0 => 2,
+                    Some(byte) if byte & 0xc0 == 0x80 => 3,
+                    Some(_) => 5,
+                    None => return false,
+                };
+            }
+            // STRING, CLASS, ARRAY, OBJECT and SZARRAY
+            0x0e | 0x12 | 0x14 | 0x1c | 0x1d => return true,
+            // GENERICINST is followed by either CLASS or VALUETYPE
+            0x15 => return signature.get(position + 1) == Some(&0x12),
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+pub struct StaticField {
+    name: String,
+    thread_static: bool,
+    // Addresses of the static field storage, one per thread for thread statics
+    slots: Vec<usize>,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct FieldMemory {
+    // Objects reachable from the field
+    pub reachable: AllocationStats,
+    // Objects reachable from this field only, and not from any other static field
+    pub exclusive: AllocationStats,
+    pub truncated: bool,
+}
+
+// Accounts objects reachable from static fields, to tell apart the memory held by a single field from the memory shared with others
+pub struct FieldsRetention {
+    fields: Vec<FieldMemory>,
+    // Index of the only field reaching an object (None when several fields do) along with its size
+    owners: HashMap<ObjectID, (Option<usize>, u64)>,
+}
+
+impl FieldsRetention {
+    pub fn new(nb_fields: usize) -> Self {
+        FieldsRetention {
+            fields: vec![FieldMemory::default(); nb_fields],
+            owners: HashMap::new(),
+        }
+    }
+
+    // Records that the given object is reachable from the given field. Each object must be recorded once per field.
+    pub fn record(&mut self, field: usize, object_id: ObjectID, size: u64) {
+        self.fields[field].reachable += &AllocationStats { count: 1, bytes: size };
+        match self.owners.entry(object_id) {
+            Entry::Vacant(entry) => {
+                entry.insert((Some(field), size));
+            }
+            Entry::Occupied(mut entry) => entry.get_mut().0 = None,
+        }
+    }
+
+    pub fn truncate(&mut self, field: usize) {
+        self.fields[field].truncated = true;
+    }
+
+    pub fn finish(mut self) -> Vec<FieldMemory> {
+        for (owner, size) in self.owners.values() {
+            if let Some(field) = owner {
+                self.fields[*field].exclusive += &AllocationStats { count: 1, bytes: *size };
+            }
+        }
+        self.fields
+    }
+}
+
+#[derive(Default)]
+pub struct StaticFieldsProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    fields: Vec<StaticField>,
+    record_object_references: bool,
+}
+
+impl Profiler for StaticFieldsProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "ADE65B3E-A29E-4F89-BB37-223DB0A6B419".to_owned(),
+            name: "List memory held by static fields".to_owned(),
+            description: "Enumerates the static (and thread static) fields of the types with jitted methods, then performs a full blocking garbage collection and computes the size of the object graph each field holds. Lists the static fields holding the most memory, such as caches and singletons.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Maximum fields", "max_fields", 50, "The maximum number of static fields to display"),
+                ProfilerParameter::define(
+                    "Maximum objects per field",
+                    "max_objects_per_field",
+                    1000000,
+                    "The maximum number of objects to walk through from a single static field. This bounds the time spent on fields holding huge object graphs.",
+                ),
+                ProfilerParameter::define(
+                    "Load all types",
+                    "load_all_types",
+                    false,
+                    "If set, the static fields of every type of every module are listed, and not only those of the types with jitted methods. \
+                    ⚠️ Types that aren't loaded yet get loaded, which allocates their statics and inflates the memory being measured.",
+                ),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl StaticFieldsProfiler {
+    // Classes of the methods jitted so far. Types holding static references almost always ran some code, at least their static
+    // constructor, so this finds them without loading any type. Methods precompiled with ReadyToRun are not jitted though.
+    fn find_jitted_classes(clr: &ClrProfilerInfo) -> HashSet<ClassID> {
+        let Ok(functions) = clr.enum_jited_functions() else {
+            error!("Could not enumerate jitted functions");
+            return HashSet::new();
+        };
+
+        functions
+            .filter_map(|function| clr.get_function_info(function.functionId).ok())
+            .map(|function_info| function_info.class_id)
+            .filter(|&class_id| class_id != 0)
+            .collect()
+    }
+
+    // Classes of every type defined in the loaded modules. Retrieving a class loads its type if it isn't loaded yet.
+    fn load_all_classes(clr: &ClrProfilerInfo) -> HashSet<ClassID> {
+        let mut classes = HashSet::new();
+
+        let Ok(modules) = clr.enum_modules() else {
+            error!("Could not enumerate modules");
+            return classes;
+        };
+
+        for module_id in modules {
+            let Ok(metadata) = clr.get_module_metadata(module_id, CorOpenFlags::ofRead) else {
+                continue;
+            };
+            for type_def in metadata.enum_type_defs().unwrap_or_default() {
+                // Classes of generic types can't be retrieved without their type arguments
+                if let Ok(class_id) = clr.get_class_from_token_and_type_args(module_id, type_def, None) {
+                    classes.insert(class_id);
+                }
+            }
+        }
+
+        classes
+    }
+
+    // Static fields are looked up before forcing the GC, as resolving their addresses may require allocating their storage.
+    // The storage of static references is pinned, so these addresses are still valid once the GC happened.
+    fn find_static_fields(clr: &ClrProfilerInfo, classes: HashSet<ClassID>) -> Vec<StaticField> {
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let threads: Vec<ThreadID> = clr.enum_threads().map(|threads| threads.collect()).unwrap_or_default();
+        let mut fields = Vec::new();
+
+        // Statics of generic types are per instantiation and are left aside
+        let classes_by_module: HashMap<ModuleID, Vec<(ClassID, mdTypeDef)>> = classes
+            .into_iter()
+            .filter_map(|class_id| clr.get_class_id_info_2(class_id).ok().map(|class_info| (class_id, class_info)))
+            .filter(|(_, class_info)| class_info.type_args.is_empty())
+            .map(|(class_id, class_info)| (class_info.module_id, (class_id, class_info.token)))
+            .into_group_map();
+
+        for (module_id, classes) in classes_by_module {
+            let Ok(metadata) = clr.get_module_metadata(module_id, CorOpenFlags::ofRead) else {
+                continue;
+            };
+            let app_domain_id = clr
+                .get_module_info(module_id)
+                .and_then(|module_info| clr.get_assembly_info(module_info.assembly_id))
+                .map(|assembly_info| assembly_info.app_domain_id);
+
+            for (class_id, type_def) in classes {
+                for field_def in metadata.enum_fields(type_def).unwrap_or_default() {
+                    let Ok(field_props) = metadata.get_field_props(field_def) else {
+                        continue;
+                    };
+                    if field_props.attr_flags & FD_STATIC == 0 || field_props.attr_flags & FD_LITERAL != 0 {
+                        continue;
+                    }
+                    let signature = unsafe { std::slice::from_raw_parts(field_props.sig, field_props.sig_length as usize) };
+                    if !is_reference_field_signature(signature) {
+                        continue;
+                    }
+
+                    let (thread_static, slots) = match clr.get_static_field_info(class_id, field_def) {
+                        Ok(COR_PRF_STATIC_TYPE::COR_PRF_FIELD_APP_DOMAIN_STATIC) => {
+                            let address = app_domain_id
+                                .as_ref()
+                                .ok()
+                                .and_then(|&app_domain_id| clr.get_app_domain_static_address(class_id, field_def, app_domain_id).ok());
+                            (false, address.into_iter().collect::<Vec<_>>())
+                        }
+                        Ok(COR_PRF_STATIC_TYPE::COR_PRF_FIELD_THREAD_STATIC) => {
+                            let addresses = threads
+                                .iter()
+                                .filter_map(|&thread_id| clr.get_thread_static_address(class_id, field_def, thread_id).ok());
+                            (true, addresses.collect())
+                        }
+                        _ => continue,
+                    };
+
+                    let slots: Vec<usize> = slots.into_iter().filter(|address| !address.is_null()).map(|address| address as usize).collect();
+                    if slots.is_empty() {
+                        continue;
+                    }
+
+                    fields.push(StaticField {
+                        name: format!("{}.{}", name_resolver.get_class_name(class_id), field_props.name),
+                        thread_static,
+                        slots,
+                    });
+                }
+            }
+        }
+
+        fields
+    }
+
+    fn compute_retention(&self, max_objects_per_field: usize) -> Vec<FieldMemory> {
+        let clr = self.clr();
+        let mut retention = FieldsRetention::new(self.fields.len());
+
+        for (index, field) in self.fields.iter().enumerate() {
+            let mut visited: HashSet<ObjectID> = HashSet::new();
+            let mut queue: VecDeque<ObjectID> = field
+                .slots
+                .iter()
+                .map(|&slot| unsafe { *(slot as *const ObjectID) })
+                .filter(|&object_id| object_id != 0)
+                .collect();
+
+            while let Some(object_id) = queue.pop_front() {
+                if !visited.insert(object_id) {
+                    continue;
+                }
+                if visited.len() > max_objects_per_field {
+                    retention.truncate(index);
+                    break;
+                }
+
+                let size = clr.get_object_size_2(object_id).unwrap_or(0) as u64;
+                retention.record(index, object_id, size);
+
+                let reference_object_ids = Vec::<ObjectID>::new();
+                // We must pass this data as a pointer for callback to mutate it with actual object references ids
+                let references_ptr_c = &reference_object_ids as *const Vec<ObjectID> as *mut std::ffi::c_void;
+                let _ = clr.enumerate_object_references(object_id, crate::utils::enum_references_callback, references_ptr_c);
+                queue.extend(reference_object_ids.into_iter().filter(|&reference_id| reference_id != 0));
+            }
+        }
+
+        retention.finish()
+    }
+
+    fn write_report(&self, memory: &[FieldMemory]) {
+        let max_fields = self.session_info.get_parameter::<usize>("max_fields").unwrap();
+        let scope = if self.session_info.get_parameter::<bool>("load_all_types").unwrap() {
+            "the types of all loaded modules, which were loaded if they weren't already"
+        } else {
+            "the types with jitted methods. Types whose methods are all precompiled are not listed"
+        };
+
+        let mut report = self.session_info.create_report("static_fields.html".to_owned());
+        report.write_line("<h2>Static Fields</h2>".to_owned());
+        report.write_line(format!(
+            "<p>{} static fields holding references were found. The reachable memory is the size of the object graph held by a field, \
+            and the exclusive memory is the part of it that no other static field holds. Static fields of generic types are not listed.</p>",
+            self.fields.len()
+        ));
+        report.write_line(format!("<p>Static fields were looked up in {scope}.</p>"));
+
+        report.write_line("<table><tr><th>Field</th><th>Kind</th><th>Reachable</th><th>Exclusive</th></tr>".to_owned());
+        for (field, memory) in self
+            .fields
+            .iter()
+            .zip(memory)
+            .sorted_by(|a, b| b.1.reachable.bytes.cmp(&a.1.reachable.bytes))
+            .take(max_fields)
+        {
+            let kind = if field.thread_static { "Thread static" } else { "Static" };
+            let truncated = if memory.truncated { "⚠️ truncated " } else { "" };
+            report.write_line(format!(
+                "<tr><td><code>{}</code></td><td>{kind}</td><td>{truncated}{}</td><td>{}</td></tr>",
+                html_escape::encode_text(&field.name),
+                memory.reachable,
+                memory.exclusive
+            ));
+        }
+        report.write_line("</table>".to_owned());
+    }
+}
+
+impl CorProfilerCallback for StaticFieldsProfiler {}
+
+impl CorProfilerCallback2 for StaticFieldsProfiler {
+    fn garbage_collection_started(&mut self, generation_collected: &[ffi::BOOL], reason: ffi::COR_PRF_GC_REASON) -> Result<(), HRESULT> {
+        info!(
+            "GC started on gen {} for reason {:?}",
+            ClrProfilerInfo::get_gc_gen(generation_collected),
+            reason
+        );
+
+        if reason == ffi::COR_PRF_GC_REASON::COR_PRF_GC_INDUCED {
+            self.record_object_references = true;
+        }
+
+        Ok(())
+    }
+
+    fn garbage_collection_finished(&mut self) -> Result<(), HRESULT> {
+        if !self.record_object_references {
+            // Early return if we received an event before the forced GC started
+            return Ok(());
+        }
+        self.record_object_references = false;
+
+        // Disable profiling to free some resources
+        if let Err(hresult) = self.clr().set_event_mask(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let now = std::time::Instant::now();
+        let max_objects_per_field = self.session_info.get_parameter::<usize>("max_objects_per_field").unwrap();
+        let memory = self.compute_retention(max_objects_per_field);
+        info!(
+            "Memory held by {} static fields computed in {} ms",
+            self.fields.len(),
+            now.elapsed().as_millis()
+        );
+
+        self.write_report(&memory);
+
+        // We're done, we can detach :)
+        if let Err(e) = self.clr().request_profiler_detach(3000) {
+            error!("Failed to detach in garbage_collection_finished: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for StaticFieldsProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_GC, None, profiler_info, client_data, client_data_length)
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let classes = if self.session_info.get_parameter::<bool>("load_all_types").unwrap() {
+            Self::load_all_classes(self.clr())
+        } else {
+            Self::find_jitted_classes(self.clr())
+        };
+        self.fields = Self::find_static_fields(self.clr(), classes);
+        info!("Found {} static fields holding references", self.fields.len());
+
+        // The ForceGC method must be called only from a thread that does not have any profiler callbacks on its stack.
+        // https://learn.microsoft.com/en-us/dotnet/framework/unmanaged-api/profiling/icorprofilerinfo-forcegc-method
+        let clr = self.clr().clone();
+        let _ = thread::spawn(move || {
+            debug!("Force GC");
+            match clr.force_gc() {
+                Ok(_) => debug!("GC Forced!"),
+                Err(hresult) => error!("Error forcing GC: {:?}", hresult),
+            };
+        })
+        .join();
+
+        // Security timeout
+        detach_after_duration::<StaticFieldsProfiler>(self, 320);
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for StaticFieldsProfiler {}
+impl CorProfilerCallback5 for StaticFieldsProfiler {}
+impl CorProfilerCallback6 for StaticFieldsProfiler {}
+impl CorProfilerCallback7 for StaticFieldsProfiler {}
+impl CorProfilerCallback8 for StaticFieldsProfiler {}
+impl CorProfilerCallback9 for StaticFieldsProfiler {}
+impl CorProfilerCallback10 for StaticFieldsProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_reference_field_signature, FieldsRetention};
+
+    #[test]
+    fn reference_fields_are_told_apart_from_value_fields() {
+        // string, object[], Dictionary<K, V>
+        assert!(is_reference_field_signature(&[0x06, 0x0e]));
+        assert!(is_reference_field_signature(&[0x06, 0x1d, 0x1c]));
+        assert!(is_reference_field_signature(&[0x06, 0x15, 0x12, 0x49, 0x02, 0x0e, 0x08]));
+        // volatile object (modreq IsVolatile)
+        assert!(is_reference_field_signature(&[0x06, 0x1f, 0x81, 0x02, 0x1c]));
+        // int, KeyValuePair<K, V>
+        assert!(!is_reference_field_signature(&[0x06, 0x08]));
+        assert!(!is_reference_field_signature(&[0x06, 0x15, 0x11, 0x49, 0x02, 0x0e, 0x08]));
+    }
+
+    #[test]
+    fn objects_held_by_several_fields_are_not_exclusive() {
+        let mut retention = FieldsRetention::new(2);
+        retention.record(0, 1, 100);
+        retention.record(0, 2, 10);
+        retention.record(1, 2, 10);
+        retention.record(1, 3, 1);
+
+        let memory = retention.finish();
+        assert_eq!((memory[0].reachable.bytes, memory[0].exclusive.bytes), (110, 100));
+        assert_eq!((memory[1].reachable.bytes, memory[1].exclusive.bytes), (11, 1));
+    }
+}
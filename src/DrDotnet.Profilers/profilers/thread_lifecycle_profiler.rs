@@ -0,0 +1,414 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{FunctionID, ThreadID, DWORD, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver};
+
+// Thread pools keep their threads alive on purpose, so threads they create are never considered leaked
+const THREAD_POOL_PREFIXES: &[&str] = &[
+    "System.Threading.PortableThreadPool",
+    "System.Threading.ThreadPool",
+    "System.Threading.WindowsThreadPool",
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThreadRecord {
+    // Elapsed time since the beginning of the session. None if the thread already existed when the profiler attached.
+    pub created_at: Option<Duration>,
+    pub destroyed_at: Option<Duration>,
+    pub name: Option<String>,
+    pub os_thread_id: Option<DWORD>,
+    // Managed callstack of the thread that created this thread, from the leaf frame to the root frame
+    pub creation_stack: Vec<FunctionID>,
+}
+
+#[derive(Default)]
+pub struct ThreadRecords {
+    pub threads: Vec<ThreadRecord>,
+    // Index of each alive thread in the records. ThreadIDs can be reused once a thread is destroyed.
+    alive: HashMap<ThreadID, usize>,
+}
+
+impl ThreadRecords {
+    pub fn created(&mut self, thread_id: ThreadID, record: ThreadRecord) {
+        self.threads.push(record);
+        self.alive.insert(thread_id, self.threads.len() - 1);
+    }
+
+    pub fn destroyed(&mut self, thread_id: ThreadID, destroyed_at: Duration) {
+        if let Some(index) = self.alive.remove(&thread_id) {
+            self.threads[index].destroyed_at = Some(destroyed_at);
+        }
+    }
+
+    pub fn get_alive_mut(&mut self, thread_id: ThreadID) -> Option<&mut ThreadRecord> {
+        let index = *self.alive.get(&thread_id)?;
+        self.threads.get_mut(index)
+    }
+}
+
+// Threads created from the same callstack
+pub struct CallSite<'a> {
+    pub creation_stack: &'a [FunctionID],
+    pub threads: Vec<&'a ThreadRecord>,
+    pub created_per_second: f64,
+    // Threads still alive when the session ended
+    pub still_alive: usize,
+    pub high_rate: bool,
+    // Threads keep piling up without being destroyed: more than the threshold are still alive, or none of them was destroyed.
+    // Thread pools and threads created by the runtime are expected to stay alive and are never flagged.
+    pub never_destroyed: bool,
+}
+
+// Groups the threads created during the session per creation callstack, the most created first.
+// Threads that already existed when the profiler attached are left out since we don't know where they come from.
+pub fn group_by_call_site(
+    threads: &[ThreadRecord],
+    session_duration: Duration,
+    rate_threshold: f64,
+    alive_threshold: usize,
+    is_thread_pool: impl Fn(&[FunctionID]) -> bool,
+) -> Vec<CallSite<'_>> {
+    let seconds = session_duration.as_secs_f64().max(1.0);
+    threads
+        .iter()
+        .filter(|thread| thread.created_at.is_some())
+        .into_group_map_by(|thread| thread.creation_stack.as_slice())
+        .into_iter()
+        .map(|(creation_stack, threads)| {
+            let created_per_second = threads.len() as f64 / seconds;
+            let still_alive = threads.iter().filter(|thread| thread.destroyed_at.is_none()).count();
+            let expected_alive = creation_stack.is_empty() || is_thread_pool(creation_stack);
+            let only_growing = threads.len() > 1 && still_alive == threads.len();
+            CallSite {
+                creation_stack,
+                still_alive,
+                high_rate: created_per_second >= rate_threshold,
+                never_destroyed: !expected_alive && (still_alive > alive_threshold || only_growing),
+                created_per_second,
+                threads,
+            }
+        })
+        .sorted_by(|a, b| b.threads.len().cmp(&a.threads.len()))
+        .collect()
+}
+
+#[derive(Default)]
+pub struct ThreadLifecycleProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+    start: Option<Instant>,
+    records: Arc<Mutex<ThreadRecords>>,
+}
+
+impl Profiler for ThreadLifecycleProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "6EEEA50F-3516-4FF7-A70D-F6329A330C47".to_owned(),
+            name: "List thread creations and leaks".to_owned(),
+            description: "Tracks every managed thread over the session (creation time, creating callstack, name, OS thread id and lifetime), and flags call sites creating threads at a high rate or never destroying them, which are the signs of a thread leak. A call site is flagged as never destroying its threads when more than the alive threshold are still alive at the end of the session, or when none of its threads was destroyed. Threads created by thread pools or by the runtime itself are expected to stay alive and are not flagged.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define(
+                    "Creation rate threshold",
+                    "rate_threshold",
+                    1.0,
+                    "Call sites creating more threads per second than this threshold are flagged",
+                ),
+                ProfilerParameter::define(
+                    "Alive threshold",
+                    "alive_threshold",
+                    10,
+                    "Call sites with more threads than this threshold still alive at the end of the session are flagged",
+                ),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl ThreadLifecycleProfiler {
+    fn elapsed(&self) -> Duration {
+        self.start.map(|start| start.elapsed()).unwrap_or_default()
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo, records: Arc<Mutex<ThreadRecords>>) {
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        let rate_threshold = session_info.get_parameter::<f64>("rate_threshold").unwrap();
+        let alive_threshold = session_info.get_parameter::<usize>("alive_threshold").unwrap();
+        let session_duration = Duration::from_secs(duration_seconds);
+        std::thread::sleep(session_duration);
+
+        // Stop tracking threads while the report is being written
+        if let Err(hresult) = clr.set_event_mask_2(ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_NONE, ffi::COR_PRF_HIGH_MONITOR::COR_PRF_HIGH_MONITOR_NONE) {
+            error!("Error setting event mask: {:?}", hresult);
+        }
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let records = records.lock().unwrap();
+        let call_sites = group_by_call_site(&records.threads, session_duration, rate_threshold, alive_threshold, |creation_stack| {
+            Self::is_thread_pool(&name_resolver, creation_stack)
+        });
+        let created = records.threads.iter().filter(|thread| thread.created_at.is_some()).count();
+        let destroyed = records.threads.iter().filter(|thread| thread.destroyed_at.is_some()).count();
+
+        let mut report = session_info.create_report("thread_lifecycle.html".to_owned());
+        report.write_line("<h2>Thread Lifecycle</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{} threads alive at attach, {created} created and {destroyed} destroyed in {duration_seconds} seconds</h4>",
+            records.threads.len() - created
+        ));
+
+        for call_site in call_sites {
+            let mut flags = String::new();
+            if call_site.high_rate {
+                flags.push_str(" ⚠️ High creation rate");
+            }
+            if call_site.never_destroyed {
+                flags.push_str(&format!(" ⚠️ Never destroyed: {} still alive at end of session", call_site.still_alive));
+            } else if call_site.still_alive > 0 {
+                flags.push_str(&format!(" {} still alive at end of session", call_site.still_alive));
+            }
+
+            let caller = match call_site
+                .creation_stack
+                .iter()
+                .find(|&&method_id| !Self::is_thread_infrastructure(&name_resolver, method_id))
+            {
+                Some(&method_id) => name_resolver.get_full_method_name(method_id, 0),
+                None => "Unknown (created by the runtime)".to_owned(),
+            };
+
+            report.write_line(format!(
+                "<details><summary><code>{}</code> \
+                <div class=\"chip\"><span>{} threads</span><i class=\"material-icons\">add_circle_outline</i></div> \
+                <div class=\"chip\"><span>{:.2} / s</span><i class=\"material-icons\">speed</i></div>{flags}</summary>",
+                html_escape::encode_text(&caller),
+                call_site.threads.len(),
+                call_site.created_per_second
+            ));
+
+            report.write_line("<h4>Creation callstack</h4>".to_owned());
+            report.write_line("<ul>".to_owned());
+            for method_id in call_site.creation_stack {
+                let method_name = name_resolver.get_full_method_name(*method_id, 0);
+                report.write_line(format!("<li><code>{}</code></li>", html_escape::encode_text(&method_name)));
+            }
+            report.write_line("</ul>".to_owned());
+
+            report.write_line("<h4>Threads</h4>".to_owned());
+            report.write_line("<table><tr><th>Name</th><th>OS Thread Id</th><th>Created at</th><th>Lifetime</th></tr>".to_owned());
+            for thread in call_site.threads {
+                let name = thread.name.as_deref().unwrap_or("");
+                let os_thread_id = thread.os_thread_id.map(|id| id.to_string()).unwrap_or_default();
+                let created_at = thread.created_at.unwrap_or_default();
+                let lifetime = match thread.destroyed_at {
+                    Some(destroyed_at) => format!("{} ms", destroyed_at.saturating_sub(created_at).as_millis()),
+                    None => "Still alive at end of session".to_owned(),
+                };
+                report.write_line(format!(
+                    "<tr><td>{}</td><td>{os_thread_id}</td><td>{} ms</td><td>{lifetime}</td></tr>",
+                    html_escape::encode_text(name),
+                    created_at.as_millis()
+                ));
+            }
+            report.write_line("</table>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn is_thread_pool(name_resolver: &CachedNameResolver, creation_stack: &[FunctionID]) -> bool {
+        creation_stack.iter().any(|&method_id| {
+            let method_name = name_resolver.get_full_method_name(method_id, 0);
+            THREAD_POOL_PREFIXES.iter().any(|prefix| method_name.starts_with(prefix))
+        })
+    }
+
+    // Frames of the Thread class itself (constructor, Start, ...) don't tell where the thread comes from
+    fn is_thread_infrastructure(name_resolver: &CachedNameResolver, method_id: FunctionID) -> bool {
+        name_resolver.get_full_method_name(method_id, 0).starts_with("System.Threading.Thread.")
+    }
+}
+
+impl CorProfilerCallback for ThreadLifecycleProfiler {
+    fn thread_created(&mut self, thread_id: ThreadID) -> Result<(), HRESULT> {
+        // For threads created from managed code, this callback is raised on the creating thread
+        let creation_stack = ManagedStackSnapshotCallbackReceiver::snapshot_current_thread(self.clr().clone());
+        let record = ThreadRecord {
+            created_at: Some(self.elapsed()),
+            creation_stack,
+            ..ThreadRecord::default()
+        };
+        self.records.lock().unwrap().created(thread_id, record);
+        Ok(())
+    }
+
+    fn thread_destroyed(&mut self, thread_id: ThreadID) -> Result<(), HRESULT> {
+        let elapsed = self.elapsed();
+        self.records.lock().unwrap().destroyed(thread_id, elapsed);
+        Ok(())
+    }
+
+    fn thread_assigned_to_os_thread(&mut self, managed_thread_id: ThreadID, os_thread_id: DWORD) -> Result<(), HRESULT> {
+        if let Some(record) = self.records.lock().unwrap().get_alive_mut(managed_thread_id) {
+            record.os_thread_id = Some(os_thread_id);
+        }
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback2 for ThreadLifecycleProfiler {
+    fn thread_name_changed(&mut self, thread_id: ThreadID, name: &str) -> Result<(), HRESULT> {
+        if let Some(record) = self.records.lock().unwrap().get_alive_mut(thread_id) {
+            record.name = Some(name.to_owned());
+        }
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback3 for ThreadLifecycleProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.start = Some(Instant::now());
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_MONITOR_THREADS | ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+            None,
+            profiler_info,
+            client_data,
+            client_data_length,
+        )
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        // Threads that were already running before the profiler attached
+        {
+            let clr = self.clr().clone();
+            let mut records = self.records.lock().unwrap();
+            match clr.enum_threads() {
+                Ok(thread_ids) => {
+                    for thread_id in thread_ids {
+                        if records.get_alive_mut(thread_id).is_none() {
+                            let record = ThreadRecord {
+                                os_thread_id: clr.get_thread_info(thread_id).ok(),
+                                ..ThreadRecord::default()
+                            };
+                            records.created(thread_id, record);
+                        }
+                    }
+                }
+                Err(hresult) => error!("Could not enumerate threads alive at attach: {:?}", hresult),
+            }
+        }
+
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+        let records = self.records.clone();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || ThreadLifecycleProfiler::profile(session_info, clr, records));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for ThreadLifecycleProfiler {}
+impl CorProfilerCallback5 for ThreadLifecycleProfiler {}
+impl CorProfilerCallback6 for ThreadLifecycleProfiler {}
+impl CorProfilerCallback7 for ThreadLifecycleProfiler {}
+impl CorProfilerCallback8 for ThreadLifecycleProfiler {}
+impl CorProfilerCallback9 for ThreadLifecycleProfiler {}
+impl CorProfilerCallback10 for ThreadLifecycleProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_by_call_site, ThreadRecord, ThreadRecords};
+    use std::time::Duration;
+
+    fn created_at(ms: u64, creation_stack: Vec<usize>) -> ThreadRecord {
+        ThreadRecord {
+            created_at: Some(Duration::from_millis(ms)),
+            creation_stack,
+            ..ThreadRecord::default()
+        }
+    }
+
+    #[test]
+    fn reused_thread_ids_are_tracked_separately() {
+        let mut records = ThreadRecords::default();
+        records.created(1, created_at(0, vec![10]));
+        records.destroyed(1, Duration::from_millis(5));
+        records.created(1, created_at(10, vec![10]));
+
+        assert_eq!(records.threads.len(), 2);
+        assert_eq!(records.threads[0].destroyed_at, Some(Duration::from_millis(5)));
+        assert_eq!(records.threads[1].destroyed_at, None);
+    }
+
+    #[test]
+    fn call_sites_are_flagged() {
+        let mut threads: Vec<ThreadRecord> = (0..30).map(|i| created_at(i * 100, vec![1, 2])).collect();
+        threads.push(created_at(0, vec![3]));
+        threads.push(ThreadRecord::default());
+        for thread in threads.iter_mut().take(20) {
+            thread.destroyed_at = Some(Duration::from_secs(9));
+        }
+
+        let call_sites = group_by_call_site(&threads, Duration::from_secs(10), 1.0, 5, |_| false);
+
+        assert_eq!(call_sites.len(), 2);
+        assert_eq!(call_sites[0].creation_stack, &[1, 2]);
+        assert!(call_sites[0].high_rate);
+        assert_eq!(call_sites[0].still_alive, 10);
+        assert!(call_sites[0].never_destroyed);
+        assert!(!call_sites[1].high_rate);
+        assert_eq!(call_sites[1].still_alive, 1);
+        assert!(!call_sites[1].never_destroyed);
+    }
+
+    #[test]
+    fn call_sites_never_destroying_threads_are_flagged_unless_thread_pools() {
+        let threads: Vec<ThreadRecord> = vec![
+            created_at(0, vec![1]),
+            created_at(100, vec![1]),
+            created_at(0, vec![2]),
+            created_at(100, vec![2]),
+            created_at(0, vec![]),
+            created_at(100, vec![]),
+        ];
+
+        let call_sites = group_by_call_site(&threads, Duration::from_secs(10), 1.0, 5, |creation_stack| creation_stack == [2]);
+        let never_destroyed = |creation_stack: &[usize]| {
+            call_sites
+                .iter()
+                .find(|call_site| call_site.creation_stack == creation_stack)
+                .unwrap()
+                .never_destroyed
+        };
+
+        // None of the threads was destroyed, so the alive count only grew
+        assert!(never_destroyed(&[1]));
+        // Thread pools and the runtime keep their threads alive on purpose
+        assert!(!never_destroyed(&[2]));
+        assert!(!never_destroyed(&[]));
+    }
+}
@@ -0,0 +1,359 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::api::ffi::{FunctionID, ThreadID, HRESULT};
+use crate::api::*;
+use crate::macros::*;
+use crate::profilers::*;
+use crate::utils::{CachedNameResolver, ManagedStackSnapshotCallbackReceiver, NameResolver, StackSnapshotCallbackReceiver, TreeNode};
+
+// Methods a thread sits in when it is idle or waiting for I/O or a timer, used when the OS thread state is unavailable
+const WAITING_METHODS: &[&str] = &[
+    "System.Threading.Thread.Sleep",
+    "System.Threading.LowLevelLifoSemaphore.",
+    "System.Threading.LowLevelMonitor.",
+    "System.Threading.WaitSubsystem.",
+    "System.Net.Sockets.",
+    "System.IO.",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ThreadState {
+    Running,
+    Waiting,
+    Blocked,
+}
+
+impl ThreadState {
+    const ALL: [ThreadState; 3] = [ThreadState::Running, ThreadState::Waiting, ThreadState::Blocked];
+
+    // Threads blocked on a synchronization primitive are told apart from the others by their leaf frames.
+    // Otherwise, the OS state tells whether the thread is running or waiting (for I/O, a timer, work, ...).
+    pub fn classify(os_state: Option<char>, blocked: bool, in_waiting_method: bool) -> ThreadState {
+        match (blocked, os_state) {
+            (true, _) => ThreadState::Blocked,
+            (false, Some('R')) => ThreadState::Running,
+            (false, Some(_)) => ThreadState::Waiting,
+            (false, None) if in_waiting_method => ThreadState::Waiting,
+            (false, None) => ThreadState::Running,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// Returns the state letter (R, S, D, ...) of a /proc/<pid>/task/<tid>/stat line. The thread name
+// between parentheses may contain spaces or parentheses, so the state is looked up after the last one.
+pub fn parse_thread_state(stat: &str) -> Option<char> {
+    stat.rfind(')').and_then(|end| stat[end + 1..].trim_start().chars().next())
+}
+
+#[cfg(target_os = "linux")]
+fn read_os_state(os_thread_id: u32) -> Option<char> {
+    std::fs::read_to_string(format!("/proc/self/task/{os_thread_id}/stat"))
+        .ok()
+        .and_then(|stat| parse_thread_state(&stat))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_os_state(_os_thread_id: u32) -> Option<char> {
+    None
+}
+
+#[derive(Default)]
+pub struct ThreadSamples {
+    os_thread_id: u32,
+    per_state: [u64; 3],
+}
+
+#[derive(Default)]
+pub struct WallClockProfiler {
+    clr_profiler_info: ClrProfilerInfo,
+    session_info: SessionInfo,
+}
+
+impl Profiler for WallClockProfiler {
+    profiler_getset!();
+
+    fn profiler_info() -> ProfilerInfo {
+        ProfilerInfo {
+            uuid: "423CDC37-159A-44DA-83A8-18D11BE28796".to_owned(),
+            name: "List wall-clock hotpaths".to_owned(),
+            description: "Samples callstacks of every thread every X ms, including threads that are not consuming CPU, and classifies each sample as running, waiting (I/O, sleep, idle) or blocked (on a lock). Shows where time is spent from the point of view of latency, in a tree view per state.".to_owned(),
+            parameters: vec![
+                ProfilerParameter::define("Duration", "duration_seconds", 10, "The profiling duration in seconds"),
+                ProfilerParameter::define(
+                    "Time Interval",
+                    "time_interval_ms",
+                    10,
+                    "Time interval between two samples in milliseconds, greater than 0",
+                ),
+                ProfilerParameter::define(
+                    "Caller To Callee",
+                    "caller_to_callee",
+                    false,
+                    "If set, the output will display callers first and callees as children in the tree representation",
+                ),
+            ],
+            ..std::default::Default::default()
+        }
+    }
+}
+
+impl WallClockProfiler {
+    fn in_waiting_method(name_resolver: &CachedNameResolver, method_ids: &[FunctionID]) -> bool {
+        method_ids.first().is_some_and(|&method_id| {
+            let method_name = name_resolver.get_full_method_name(method_id, 0);
+            WAITING_METHODS.iter().any(|prefix| method_name.starts_with(prefix))
+        })
+    }
+
+    fn profile(session_info: SessionInfo, clr: ClrProfilerInfo) {
+        let time_interval_ms = session_info.get_parameter::<u64>("time_interval_ms").unwrap();
+        let duration_seconds = session_info.get_parameter::<u64>("duration_seconds").unwrap();
+        let caller_to_callee = session_info.get_parameter::<bool>("caller_to_callee").unwrap();
+
+        let name_resolver = CachedNameResolver::new(clr.clone());
+        let mut trees: HashMap<ThreadState, TreeNode<FunctionID, u64>> = HashMap::new();
+        let mut threads: HashMap<ThreadID, ThreadSamples> = HashMap::new();
+        let mut os_states_available = false;
+
+        if time_interval_ms == 0 {
+            let mut report = session_info.create_report("wall_clock.html".to_owned());
+            report.write_line("<h2>Wall Clock</h2>".to_owned());
+            report.write_line("<p>⚠️ The time interval must be greater than 0 ms.</p>".to_owned());
+            if let Err(e) = clr.request_profiler_detach(3000) {
+                error!("Could not detach for reason: {:?}", e);
+            }
+            return;
+        }
+
+        let iterations = 1000 * duration_seconds / time_interval_ms;
+        let sampling_start = Instant::now();
+        for _ in 0..iterations {
+            std::thread::sleep(Duration::from_millis(time_interval_ms));
+
+            // OS states are read before suspending the runtime, which would otherwise show every thread as waiting
+            let os_states: Vec<(ThreadID, u32, Option<char>)> = match clr.enum_threads() {
+                Ok(thread_ids) => thread_ids
+                    .map(|thread_id| {
+                        let os_thread_id = clr.get_thread_info(thread_id).unwrap_or(0);
+                        (thread_id, os_thread_id, read_os_state(os_thread_id))
+                    })
+                    .collect(),
+                Err(hresult) => {
+                    error!("Can't enumerate threads: {:?}", hresult);
+                    continue;
+                }
+            };
+
+            // https://github.com/dotnet/runtime/issues/37586#issuecomment-641114483
+            if clr.suspend_runtime().is_err() {
+                error!("Can't suspend runtime!");
+                continue;
+            }
+
+            let mut stacks = Vec::new();
+            for (thread_id, os_thread_id, os_state) in os_states {
+                let mut receiver = ManagedStackSnapshotCallbackReceiver::default();
+                receiver.do_stack_snapshot(clr.clone(), thread_id, false);
+                stacks.push((thread_id, os_thread_id, os_state, receiver.method_ids));
+            }
+
+            if clr.resume_runtime().is_err() {
+                error!("Can't resume runtime!");
+            }
+
+            // Names are resolved once the runtime is resumed to keep the pauses as short as possible
+            for (thread_id, os_thread_id, os_state, method_ids) in stacks {
+                os_states_available |= os_state.is_some();
+                let blocked = LockContentionProfiler::get_waiting_primitive(&name_resolver, &method_ids).is_some();
+                let state = ThreadState::classify(os_state, blocked, Self::in_waiting_method(&name_resolver, &method_ids));
+
+                let thread = threads.entry(thread_id).or_default();
+                thread.os_thread_id = os_thread_id;
+                thread.per_state[state.index()] += 1;
+
+                let tree = trees.entry(state).or_insert_with(|| TreeNode::new(0));
+                let node = if caller_to_callee {
+                    tree.add_sequence(method_ids.into_iter().rev())
+                } else {
+                    tree.add_sequence(method_ids)
+                };
+                *node.value.get_or_insert(0) += 1;
+            }
+        }
+
+        // Samples are further apart than the interval, as suspending the runtime and walking the stacks takes time.
+        // Each sample stands for the actual average time between two samples.
+        let sample_period = sampling_start.elapsed() / iterations.max(1) as u32;
+
+        Self::write_report(
+            &session_info,
+            &name_resolver,
+            trees,
+            &threads,
+            os_states_available,
+            time_interval_ms,
+            sample_period,
+        );
+
+        if let Err(e) = clr.request_profiler_detach(3000) {
+            error!("Could not detach for reason: {:?}", e);
+        }
+    }
+
+    fn write_report(
+        session_info: &SessionInfo,
+        name_resolver: &CachedNameResolver,
+        mut trees: HashMap<ThreadState, TreeNode<FunctionID, u64>>,
+        threads: &HashMap<ThreadID, ThreadSamples>,
+        os_states_available: bool,
+        time_interval_ms: u64,
+        sample_period: Duration,
+    ) {
+        let total_samples: u64 = threads.values().map(|thread| thread.per_state.iter().sum::<u64>()).sum();
+        let wall_time = |samples: u64| format!("~{} ms", (sample_period * samples as u32).as_millis());
+
+        let mut report = session_info.create_report("wall_clock.html".to_owned());
+        report.write_line("<h2>Wall Clock</h2>".to_owned());
+        report.write_line(format!(
+            "<h4>{total_samples} samples of {} threads every {time_interval_ms} ms</h4>",
+            threads.len()
+        ));
+        report.write_line(format!(
+            "<p>Wall times are estimated from the number of samples, one sample standing for {:.1} ms on average.</p>",
+            sample_period.as_secs_f64() * 1000.0
+        ));
+        if !os_states_available {
+            report.write_line(
+                "<p>⚠️ Thread states could not be read from the OS: running and waiting threads are told apart from their leaf frames only.</p>".to_owned(),
+            );
+        }
+
+        report.write_line("<table><tr><th>State</th><th>Samples</th><th>Share</th><th>Estimated wall time</th></tr>".to_owned());
+        for state in ThreadState::ALL {
+            let samples: u64 = threads.values().map(|thread| thread.per_state[state.index()]).sum();
+            report.write_line(format!(
+                "<tr><td>{state:?}</td><td>{samples}</td><td>{:.2} %</td><td>{}</td></tr>",
+                100.0 * samples as f64 / total_samples.max(1) as f64,
+                wall_time(samples)
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        report.write_line("<h3>Threads</h3>".to_owned());
+        report.write_line("<table><tr><th>OS thread</th><th>Running</th><th>Waiting</th><th>Blocked</th></tr>".to_owned());
+        for thread in threads
+            .values()
+            .sorted_by_key(|thread| std::cmp::Reverse(thread.per_state[ThreadState::Running.index()]))
+        {
+            report.write_line(format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                thread.os_thread_id,
+                wall_time(thread.per_state[ThreadState::Running.index()]),
+                wall_time(thread.per_state[ThreadState::Waiting.index()]),
+                wall_time(thread.per_state[ThreadState::Blocked.index()])
+            ));
+        }
+        report.write_line("</table>".to_owned());
+
+        for state in ThreadState::ALL {
+            let tree = match trees.get_mut(&state) {
+                Some(tree) => tree,
+                None => continue,
+            };
+            tree.sort_by_iterative(&|a: &TreeNode<FunctionID, u64>, b: &TreeNode<FunctionID, u64>| b.get_inclusive_value().cmp(&a.get_inclusive_value()));
+            let state_samples = tree.get_inclusive_value();
+
+            report.write_line(format!("<h3>{state:?} ({state_samples} samples)</h3>"));
+            report.write_line("<ul>".to_owned());
+            for node in &tree.children {
+                node.print_html(&mut report, &|node| Self::format_html_line(name_resolver, node, state_samples));
+            }
+            report.write_line("</ul>".to_owned());
+        }
+    }
+
+    fn format_html_line(name_resolver: &CachedNameResolver, node: &TreeNode<FunctionID, u64>, total_samples: u64) -> String {
+        let percentage_exclusive = 100f64 * node.value.unwrap_or_default() as f64 / total_samples as f64;
+        let percentage_inclusive = 100f64 * node.get_inclusive_value() as f64 / total_samples as f64;
+
+        let method_name = name_resolver.get_full_method_name(node.key, 0);
+        let escaped_method_name = html_escape::encode_text(&method_name);
+
+        format!(
+            "<code>{escaped_method_name}</code> \
+            <div class=\"chip\"><span>{percentage_inclusive:.2} %</span><i class=\"material-icons\">radio_button_checked</i></div> \
+            <div class=\"chip\"><span>{percentage_exclusive:.2} %</span><i class=\"material-icons\">radio_button_unchecked</i></div>"
+        )
+    }
+}
+
+impl CorProfilerCallback for WallClockProfiler {}
+
+impl CorProfilerCallback2 for WallClockProfiler {}
+
+impl CorProfilerCallback3 for WallClockProfiler {
+    fn initialize_for_attach(
+        &mut self,
+        profiler_info: ClrProfilerInfo,
+        client_data: *const std::os::raw::c_void,
+        client_data_length: u32,
+    ) -> Result<(), HRESULT> {
+        self.init(
+            ffi::COR_PRF_MONITOR::COR_PRF_ENABLE_STACK_SNAPSHOT,
+            None,
+            profiler_info,
+            client_data,
+            client_data_length,
+        )
+    }
+
+    fn profiler_attach_complete(&mut self) -> Result<(), HRESULT> {
+        let clr = self.clr().clone();
+        let session_info = self.session_info().clone();
+
+        // Run profiling in separate thread
+        std::thread::spawn(move || WallClockProfiler::profile(session_info, clr));
+
+        Ok(())
+    }
+
+    fn profiler_detach_succeeded(&mut self) -> Result<(), HRESULT> {
+        self.session_info.finish();
+        Ok(())
+    }
+}
+
+impl CorProfilerCallback4 for WallClockProfiler {}
+impl CorProfilerCallback5 for WallClockProfiler {}
+impl CorProfilerCallback6 for WallClockProfiler {}
+impl CorProfilerCallback7 for WallClockProfiler {}
+impl CorProfilerCallback8 for WallClockProfiler {}
+impl CorProfilerCallback9 for WallClockProfiler {}
+impl CorProfilerCallback10 for WallClockProfiler {}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_thread_state, ThreadState};
+
+    #[test]
+    fn thread_state_is_parsed_after_thread_name() {
+        assert_eq!(parse_thread_state("1234 (.NET TP Worker) S 1 1234 1234 0 -1"), Some('S'));
+        assert_eq!(parse_thread_state("1235 (weird) name)) R 1 1234"), Some('R'));
+        assert_eq!(parse_thread_state(""), None);
+    }
+
+    #[test]
+    fn samples_are_classified() {
+        assert_eq!(ThreadState::classify(Some('R'), true, false), ThreadState::Blocked);
+        assert_eq!(ThreadState::classify(Some('R'), false, true), ThreadState::Running);
+        assert_eq!(ThreadState::classify(Some('D'), false, false), ThreadState::Waiting);
+        assert_eq!(ThreadState::classify(None, false, true), ThreadState::Waiting);
+        assert_eq!(ThreadState::classify(None, false, false), ThreadState::Running);
+    }
+}
@@ -0,0 +1,29 @@
+use std::fmt::{Display, Formatter};
+use std::ops::AddAssign;
+use thousands::{digits, Separable, SeparatorPolicy};
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct AllocationStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+// Implement AddAssign for get_inclusive_value to be usable
+impl AddAssign<&AllocationStats> for AllocationStats {
+    fn add_assign(&mut self, other: &Self) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+    }
+}
+
+impl Display for AllocationStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let policy = SeparatorPolicy {
+            separator: ",",
+            groups: &[3],
+            digits: digits::ASCII_DECIMAL,
+        };
+
+        write!(f, "{} / {} B", self.count.separate_by_policy(policy), self.bytes.separate_by_policy(policy))
+    }
+}
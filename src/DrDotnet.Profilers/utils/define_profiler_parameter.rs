@@ -49,7 +49,7 @@ impl DefineParameter for &str {
             name: name.to_owned(),
             key: key.to_owned(),
             description: description.to_owned(),
-            type_: ParameterType::BOOLEAN.into(),
+            type_: ParameterType::STRING.into(),
             value: self.to_string().to_owned(),
             ..std::default::Default::default()
         }
@@ -61,3 +61,14 @@ impl ProfilerParameter {
         default_value.define(name, key, description)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{ParameterType, ProfilerParameter};
+
+    #[test]
+    fn string_parameters_are_typed_as_strings() {
+        let parameter = ProfilerParameter::define("Namespaces", "namespaces", "", "Comma separated namespaces");
+        assert_eq!(parameter.type_, ParameterType::STRING.into());
+    }
+}
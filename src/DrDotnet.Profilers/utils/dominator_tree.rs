@@ -0,0 +1,165 @@
+// Dominator tree of a directed graph whose nodes are indexed from 0, node 0 being the entry of the graph.
+// A node dominates another if every path from the entry to the latter goes through it. Applied to an object graph
+// with a virtual entry referencing every GC root, the memory retained by an object (what would be freed if it went away)
+// is the sum of the sizes of the objects it dominates.
+// Dominators are computed with the iterative algorithm from "A Simple, Fast Dominance Algorithm" (Cooper, Harvey, Kennedy).
+pub struct DominatorTree {
+    // Immediate dominator of each node, or None if the node is unreachable from the entry
+    idoms: Vec<Option<usize>>,
+    // Reachable nodes in postorder, so that a node always comes before its immediate dominator
+    postorder: Vec<usize>,
+}
+
+impl DominatorTree {
+    pub const ENTRY: usize = 0;
+
+    pub fn new(successors: &[Vec<usize>]) -> Self {
+        let nb_nodes = successors.len();
+        if nb_nodes == 0 {
+            return DominatorTree {
+                idoms: Vec::new(),
+                postorder: Vec::new(),
+            };
+        }
+
+        let postorder = Self::postorder(successors);
+
+        let mut postorder_index = vec![usize::MAX; nb_nodes];
+        for (index, &node) in postorder.iter().enumerate() {
+            postorder_index[node] = index;
+        }
+
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); nb_nodes];
+        for &node in &postorder {
+            for &successor in &successors[node] {
+                predecessors[successor].push(node);
+            }
+        }
+
+        let mut idoms: Vec<Option<usize>> = vec![None; nb_nodes];
+        idoms[Self::ENTRY] = Some(Self::ENTRY);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            // Reverse postorder, skipping the entry which is last in postorder
+            for &node in postorder.iter().rev().skip(1) {
+                let mut new_idom: Option<usize> = None;
+                for &predecessor in &predecessors[node] {
+                    if idoms[predecessor].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(current) => Self::intersect(&idoms, &postorder_index, predecessor, current),
+                    });
+                }
+                if new_idom.is_some() && idoms[node] != new_idom {
+                    idoms[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        DominatorTree { idoms, postorder }
+    }
+
+    fn postorder(successors: &[Vec<usize>]) -> Vec<usize> {
+        let mut visited = vec![false; successors.len()];
+        let mut postorder = Vec::with_capacity(successors.len());
+        // Iterative depth first search, as object graphs can be way deeper than the stack allows
+        let mut stack = vec![(Self::ENTRY, 0)];
+        visited[Self::ENTRY] = true;
+
+        while let Some((node, next_child)) = stack.last_mut() {
+            let node = *node;
+            match successors[node].get(*next_child) {
+                Some(&successor) => {
+                    *next_child += 1;
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => {
+                    postorder.push(node);
+                    stack.pop();
+                }
+            }
+        }
+
+        postorder
+    }
+
+    fn intersect(idoms: &[Option<usize>], postorder_index: &[usize], mut a: usize, mut b: usize) -> usize {
+        while a != b {
+            while postorder_index[a] < postorder_index[b] {
+                a = idoms[a].unwrap();
+            }
+            while postorder_index[b] < postorder_index[a] {
+                b = idoms[b].unwrap();
+            }
+        }
+        a
+    }
+
+    // Returns the immediate dominator of the given node, or None for the entry and unreachable nodes
+    pub fn idom(&self, node: usize) -> Option<usize> {
+        self.idoms.get(node).copied().flatten().filter(|_| node != Self::ENTRY)
+    }
+
+    // Returns, for each node, the nodes it immediately dominates
+    pub fn children(&self) -> Vec<Vec<usize>> {
+        let mut children = vec![Vec::new(); self.idoms.len()];
+        for &node in &self.postorder {
+            if let Some(idom) = self.idom(node) {
+                children[idom].push(node);
+            }
+        }
+        children
+    }
+
+    // Returns, for each node, the sum of its own size and of the sizes of all the nodes it dominates
+    pub fn retained_sizes(&self, sizes: &[u64]) -> Vec<u64> {
+        let mut retained = sizes.to_vec();
+        for &node in &self.postorder {
+            if let Some(idom) = self.idom(node) {
+                retained[idom] += retained[node];
+            }
+        }
+        retained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DominatorTree;
+
+    #[test]
+    fn shared_objects_are_retained_by_their_common_dominator() {
+        // 0 -> 1 -> 2 -> 4
+        //   \-> 3 -/
+        // 4 is reachable through 1 and 3, so only the entry retains it. 2 is only reachable through 1.
+        let successors = vec![vec![1, 3], vec![2], vec![4], vec![4], vec![]];
+        let dominators = DominatorTree::new(&successors);
+
+        assert_eq!(dominators.idom(1), Some(0));
+        assert_eq!(dominators.idom(2), Some(1));
+        assert_eq!(dominators.idom(4), Some(0));
+
+        let retained = dominators.retained_sizes(&[0, 10, 20, 30, 40]);
+        assert_eq!(retained, vec![100, 30, 20, 30, 40]);
+    }
+
+    #[test]
+    fn cycles_and_unreachable_nodes() {
+        // 0 -> 1 <-> 2, and 3 -> 1 is unreachable
+        let successors = vec![vec![1], vec![2], vec![1], vec![1]];
+        let dominators = DominatorTree::new(&successors);
+
+        assert_eq!(dominators.idom(2), Some(1));
+        assert_eq!(dominators.idom(3), None);
+        assert_eq!(dominators.retained_sizes(&[0, 1, 2, 4]), vec![3, 3, 2, 4]);
+        assert_eq!(dominators.children()[1], vec![2]);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::api::ffi::{ClassID, ObjectID};
+use crate::api::EventPipeProviderConfig;
+use std::time::Duration;
+
+// https://learn.microsoft.com/en-us/dotnet/fundamentals/diagnostics/runtime-events
+pub const DOTNET_RUNTIME_PROVIDER: &str = "Microsoft-Windows-DotNETRuntime";
+pub const GC_KEYWORD: u64 = 0x1;
+pub const CONTENTION_KEYWORD: u64 = 0x4000;
+pub const INFORMATIONAL_LEVEL: u32 = 4;
+pub const VERBOSE_LEVEL: u32 = 5;
+pub const ALLOCATION_TICK_EVENT_ID: u32 = 10;
+pub const CONTENTION_START_EVENT_ID: u32 = 81;
+pub const CONTENTION_STOP_EVENT_ID: u32 = 91;
+
+// Runtime GC events, including AllocationTick which is emitted roughly every 100 KB allocated
+pub fn gc_events_provider() -> EventPipeProviderConfig {
+    EventPipeProviderConfig {
+        provider_name: DOTNET_RUNTIME_PROVIDER.to_owned(),
+        keywords: GC_KEYWORD,
+        logging_level: VERBOSE_LEVEL,
+        filter_data: None,
+    }
+}
+
+// Runtime ContentionStart and ContentionStop events, emitted when a thread has to wait for a Monitor
+pub fn contention_events_provider() -> EventPipeProviderConfig {
+    EventPipeProviderConfig {
+        provider_name: DOTNET_RUNTIME_PROVIDER.to_owned(),
+        keywords: CONTENTION_KEYWORD,
+        logging_level: INFORMATIONAL_LEVEL,
+        filter_data: None,
+    }
+}
+
+// Reads the fields of an event payload, in order
+pub struct PayloadReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        PayloadReader { data, position: 0 }
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(self.position..self.position + N)?.try_into().ok()?;
+        self.position += N;
+        Some(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.read_bytes().map(u8::from_le_bytes)
+    }
+
+    pub fn read_u16(&mut self) -> Option<u16> {
+        self.read_bytes().map(u16::from_le_bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        self.read_bytes().map(u32::from_le_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> Option<u64> {
+        self.read_bytes().map(u64::from_le_bytes)
+    }
+
+    pub fn read_f64(&mut self) -> Option<f64> {
+        self.read_bytes().map(f64::from_le_bytes)
+    }
+
+    // Pointers are written with the size of the process they come from, which is ours
+    pub fn read_pointer(&mut self) -> Option<usize> {
+        self.read_bytes().map(usize::from_le_bytes)
+    }
+
+    // Reads a null terminated UTF-16 string
+    pub fn read_string(&mut self) -> Option<String> {
+        let mut chars = Vec::new();
+        loop {
+            match self.read_u16()? {
+                0 => return Some(String::from_utf16_lossy(&chars)),
+                c => chars.push(c),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationKind {
+    Small,
+    Large,
+    Pinned,
+}
+
+// GC/AllocationTick payload, from version 2 which is the first to include the allocated type
+#[derive(Clone, Debug, PartialEq)]
+pub struct AllocationTick {
+    pub kind: AllocationKind,
+    // Bytes allocated since the previous AllocationTick, which this one stands for
+    pub amount: u64,
+    pub class_id: ClassID,
+    pub type_name: String,
+    // Size of the allocated object, only available from version 4
+    pub object_size: Option<u64>,
+}
+
+impl AllocationTick {
+    pub fn parse(event_version: u32, event_data: &[u8]) -> Option<AllocationTick> {
+        if event_version < 2 {
+            return None;
+        }
+
+        let mut reader = PayloadReader::new(event_data);
+        let _amount_32 = reader.read_u32()?;
+        let kind = match reader.read_u32()? {
+            0 => AllocationKind::Small,
+            1 => AllocationKind::Large,
+            _ => AllocationKind::Pinned,
+        };
+        let _clr_instance_id = reader.read_u16()?;
+        let amount = reader.read_u64()?;
+        let class_id = reader.read_pointer()?;
+        let type_name = reader.read_string()?;
+        let _heap_index = reader.read_u32()?;
+
+        let object_size = if event_version >= 4 {
+            let _address = reader.read_pointer()?;
+            Some(reader.read_u64()?)
+        } else {
+            None
+        };
+
+        Some(AllocationTick {
+            kind,
+            amount,
+            class_id,
+            type_name,
+            object_size,
+        })
+    }
+
+    // Estimated number of objects allocated since the previous AllocationTick, assuming they are all of this size
+    pub fn estimated_count(&self) -> u64 {
+        match self.object_size {
+            Some(object_size) if object_size > 0 => (self.amount / object_size).max(1),
+            _ => 1,
+        }
+    }
+}
+
+// Contention/Start payload
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentionStart {
+    // Contention on a managed lock, as opposed to a native one
+    pub managed: bool,
+    // Object the lock belongs to, only available from version 2 (.NET 8)
+    pub associated_object_id: Option<ObjectID>,
+}
+
+impl ContentionStart {
+    pub fn parse(event_version: u32, event_data: &[u8]) -> Option<ContentionStart> {
+        let mut reader = PayloadReader::new(event_data);
+        let flags = reader.read_u8()?;
+        let _clr_instance_id = reader.read_u16()?;
+
+        let associated_object_id = if event_version >= 2 {
+            let _lock_id = reader.read_pointer()?;
+            Some(reader.read_pointer()?).filter(|&object_id| object_id != 0)
+        } else {
+            None
+        };
+
+        Some(ContentionStart {
+            managed: flags == 0,
+            associated_object_id,
+        })
+    }
+}
+
+// Contention/Stop payload
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentionStop {
+    pub managed: bool,
+    // Time spent waiting, only available from version 1 (.NET 8)
+    pub duration: Option<Duration>,
+}
+
+impl ContentionStop {
+    pub fn parse(event_version: u32, event_data: &[u8]) -> Option<ContentionStop> {
+        let mut reader = PayloadReader::new(event_data);
+        let flags = reader.read_u8()?;
+        let _clr_instance_id = reader.read_u16()?;
+
+        let duration = if event_version >= 1 {
+            Some(Duration::from_nanos(reader.read_f64()? as u64))
+        } else {
+            None
+        };
+
+        Some(ContentionStop { managed: flags == 0, duration })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AllocationKind, AllocationTick, ContentionStart, ContentionStop};
+    use std::time::Duration;
+
+    fn allocation_tick_payload(version: u32) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend(100_000u32.to_le_bytes());
+        payload.extend(1u32.to_le_bytes());
+        payload.extend(7u16.to_le_bytes());
+        payload.extend(100_000u64.to_le_bytes());
+        payload.extend(0x1234usize.to_le_bytes());
+        payload.extend("System.Byte[]\0".encode_utf16().flat_map(u16::to_le_bytes));
+        payload.extend(2u32.to_le_bytes());
+        if version >= 3 {
+            payload.extend(0x5678usize.to_le_bytes());
+        }
+        if version >= 4 {
+            payload.extend(25_000u64.to_le_bytes());
+        }
+        payload
+    }
+
+    #[test]
+    fn allocation_tick_is_parsed() {
+        let tick = AllocationTick::parse(4, &allocation_tick_payload(4)).unwrap();
+
+        assert_eq!(
+            tick,
+            AllocationTick {
+                kind: AllocationKind::Large,
+                amount: 100_000,
+                class_id: 0x1234,
+                type_name: "System.Byte[]".to_owned(),
+                object_size: Some(25_000),
+            }
+        );
+        assert_eq!(tick.estimated_count(), 4);
+    }
+
+    #[test]
+    fn allocation_tick_without_object_size_counts_one_object() {
+        let tick = AllocationTick::parse(3, &allocation_tick_payload(3)).unwrap();
+
+        assert_eq!(tick.object_size, None);
+        assert_eq!(tick.estimated_count(), 1);
+    }
+
+    #[test]
+    fn truncated_or_old_allocation_ticks_are_ignored() {
+        let payload = allocation_tick_payload(4);
+
+        assert_eq!(AllocationTick::parse(4, &payload[..20]), None);
+        assert_eq!(AllocationTick::parse(1, &payload), None);
+    }
+
+    #[test]
+    fn contention_events_are_parsed() {
+        let mut start = vec![0u8];
+        start.extend(7u16.to_le_bytes());
+        start.extend(0x10usize.to_le_bytes());
+        start.extend(0x20usize.to_le_bytes());
+        start.extend(3u64.to_le_bytes());
+
+        assert_eq!(
+            ContentionStart::parse(2, &start),
+            Some(ContentionStart {
+                managed: true,
+                associated_object_id: Some(0x20),
+            })
+        );
+        assert_eq!(ContentionStart::parse(1, &start[..3]).unwrap().associated_object_id, None);
+
+        let mut stop = vec![0u8];
+        stop.extend(7u16.to_le_bytes());
+        stop.extend(1_500_000f64.to_le_bytes());
+
+        assert_eq!(ContentionStop::parse(1, &stop).unwrap().duration, Some(Duration::from_micros(1500)));
+        assert_eq!(ContentionStop::parse(0, &stop[..3]).unwrap().duration, None);
+    }
+}
@@ -0,0 +1,38 @@
+// Generation names in COR_PRF_GC_GENERATION order
+pub const GENERATION_NAMES: [&str; 5] = ["Gen 0", "Gen 1", "Gen 2", "LOH", "POH"];
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub fn format_duration(duration: std::time::Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, format_size};
+    use std::time::Duration;
+
+    #[test]
+    fn sizes_are_formatted_with_binary_units() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(131_072), "128.0 KB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn durations_are_formatted_in_milliseconds() {
+        assert_eq!(format_duration(Duration::from_micros(1_500)), "1.500 ms");
+    }
+}
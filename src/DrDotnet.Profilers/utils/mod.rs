@@ -15,3 +15,15 @@ pub use simple_hasher::*;
 
 pub mod define_profiler_parameter;
 pub use define_profiler_parameter::*;
+
+pub mod dominator_tree;
+pub use dominator_tree::*;
+
+pub mod format;
+pub use format::*;
+
+pub mod allocation_stats;
+pub use allocation_stats::*;
+
+pub mod event_pipe;
+pub use event_pipe::*;
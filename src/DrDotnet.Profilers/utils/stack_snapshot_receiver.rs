@@ -42,3 +42,30 @@ pub trait StackSnapshotCallbackReceiver {
         return HRESULT::S_OK;
     }
 }
+
+// Collects the managed frames of a callstack, from the leaf frame to the root frame.
+// Unmanaged frames are skipped because they can't be resolved to a method name.
+#[derive(Default)]
+pub struct ManagedStackSnapshotCallbackReceiver {
+    pub method_ids: Vec<FunctionID>,
+}
+
+impl ManagedStackSnapshotCallbackReceiver {
+    // Snapshots the callstack of the thread calling this method (usually the thread a callback is raised on)
+    pub fn snapshot_current_thread(pinfo: ClrProfilerInfo) -> Vec<FunctionID> {
+        let mut receiver = Self::default();
+        // Passing a null thread ID makes the runtime snapshot the current thread
+        receiver.do_stack_snapshot(pinfo, 0, false);
+        receiver.method_ids
+    }
+}
+
+impl StackSnapshotCallbackReceiver for ManagedStackSnapshotCallbackReceiver {
+    type AssociatedType = Self;
+
+    fn callback(&mut self, method_id: FunctionID, _ip: usize, _frame_info: COR_PRF_FRAME_INFO, _context: &[u8]) {
+        if method_id != 0 {
+            self.method_ids.push(method_id);
+        }
+    }
+}
@@ -3,6 +3,8 @@ use std::cmp::Ordering;
 use std::collections::{HashMap, VecDeque};
 use std::ops::AddAssign;
 
+use crate::session::Report;
+
 #[derive(Debug, Clone)]
 pub struct TreeNode<K, V> {
     pub key: K,
@@ -35,6 +37,26 @@ where
         }
     }
 
+    // Writes the tree as nested collapsible HTML lists, formatting each node with the given closure
+    pub fn print_html<F>(&self, report: &mut Report, format_line: &F)
+    where
+        F: Fn(&Self) -> String,
+    {
+        let line = format_line(self);
+
+        if self.children.is_empty() {
+            report.write_line(format!("<li>{line}</li>"));
+        } else {
+            report.write_line(format!("<details><summary>{line}</summary>"));
+            report.write_line("<ul>".to_owned());
+            for child in &self.children {
+                child.print_html(report, format_line);
+            }
+            report.write_line("</ul>".to_owned());
+            report.write_line("</details>".to_owned());
+        }
+    }
+
     // Sort children recursively based on the given closure
     pub fn sort_by<F>(&mut self, compare: &F)
     where
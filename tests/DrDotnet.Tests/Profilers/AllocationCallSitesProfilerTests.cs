@@ -0,0 +1,63 @@
+using NUnit.Framework;
+using System;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Tests.Simulations;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class AllocationCallSitesProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{323977FC-434E-4333-B2A7-BDE6950C7847}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(60_000)]
+    [NonParallelizable]
+    public async Task Profiler_Lists_Allocation_Call_Sites()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+        profiler.SetParameter("duration_seconds", 5);
+        profiler.SetParameter("sampling_interval", 1);
+
+        // Allocations of any size are sampled from AllocationTick events
+        using var simulation = new AllocationSimulation(1_000_000, 1_000);
+        using var largeObjectSimulation = new LargeObjectAllocationSimulation(20);
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "allocation_call_sites.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        var content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        content.Should().Contain("System.String", "The simulation allocates small strings");
+        content.Should().Contain("System.Byte[]", "The simulation allocates large byte arrays");
+        content.Should().Contain("LargeObjectAllocationSimulation.AllocateLargeArray", "Large arrays are allocated from the simulation");
+    }
+}
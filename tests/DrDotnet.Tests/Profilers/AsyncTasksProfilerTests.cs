@@ -0,0 +1,75 @@
+using NUnit.Framework;
+using System;
+using System.Collections.Generic;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public static class HungRequests
+{
+    public static async Task HandleRequestAsync(Task pending)
+    {
+        await QueryDatabaseAsync(pending);
+    }
+
+    private static async Task QueryDatabaseAsync(Task pending)
+    {
+        await pending;
+    }
+}
+
+public class AsyncTasksProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{2956ECC2-7D9C-4D29-ACBB-865DCB06DA9C}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(160_000)]
+    [NonParallelizable]
+    public async Task Profiler_Rebuilds_Await_Chains()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+
+        var pending = new TaskCompletionSource();
+        Task[] requests = Enumerable.Range(0, 10).Select(_ => HungRequests.HandleRequestAsync(pending.Task)).ToArray();
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "async_tasks.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        string content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        pending.SetResult();
+        await Task.WhenAll(requests);
+
+        content.Should().Contain("async DrDotnet.Tests.Profilers.HungRequests.HandleRequestAsync", "Requests are awaiting");
+        content.Should().Contain("async DrDotnet.Tests.Profilers.HungRequests.QueryDatabaseAsync", "Await chains are rebuilt from continuations");
+    }
+}
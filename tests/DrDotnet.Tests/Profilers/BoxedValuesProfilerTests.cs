@@ -0,0 +1,63 @@
+using NUnit.Framework;
+using System;
+using System.Collections.Generic;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class BoxedValuesProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{453034DC-01A9-4600-B70A-32A75103C944}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(160_000)]
+    [NonParallelizable]
+    public async Task Profiler_Lists_Boxed_Primitives_Enums_And_Structs()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+
+        object[] boxes = Enumerable.Range(0, 1000)
+            .SelectMany(i => new object[] { i, (DayOfWeek)(i % 7), new KeyValuePair<int, int>(i, i) })
+            .ToArray();
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "boxed_values.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        string content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        GC.KeepAlive(boxes);
+
+        content.Should().Contain("System.Int32", "Integers have been boxed");
+        content.Should().Contain("System.DayOfWeek", "Enums have been boxed");
+        content.Should().Contain("System.Collections.Generic.KeyValuePair&lt;System.Int32, System.Int32&gt;", "Structs have been boxed");
+    }
+}
@@ -0,0 +1,68 @@
+using NUnit.Framework;
+using System;
+using System.Collections.Generic;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class CollectionHolder
+{
+    public readonly List<int> OversizedList = new(10_000) { 1, 2, 3 };
+    public readonly Dictionary<int, string> EmptyDictionary = new(1_000);
+}
+
+public class CollectionSizingProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{8DE5233B-704E-4A4F-9F3F-75C7223EFFFA}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(160_000)]
+    [NonParallelizable]
+    public async Task Profiler_Flags_Oversized_And_Empty_Collections()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+        profiler.SetParameter("oversized_ratio", 4.0);
+
+        var holders = Enumerable.Range(0, 100).Select(_ => new CollectionHolder()).ToArray();
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "collection_sizing.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        string content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        GC.KeepAlive(holders);
+
+        content.Should().Contain("System.Collections.Generic.List&lt;System.Int32&gt;", "The holders have oversized lists");
+        content.Should().Contain("System.Collections.Generic.Dictionary&lt;System.Int32, System.String&gt;", "The holders have empty dictionaries");
+        content.Should().Contain("DrDotnet.Tests.Profilers.CollectionHolder", "Collections are attributed to the type holding them");
+    }
+}
@@ -0,0 +1,76 @@
+using NUnit.Framework;
+using System;
+using System.Collections.Generic;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Runtime.CompilerServices;
+using System.Threading.Tasks;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class WeakTableKey
+{
+}
+
+public class WeakTableValue
+{
+    public readonly byte[] Payload = new byte[1024];
+}
+
+public class ConditionalWeakTablesProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{0F637811-0EEF-4550-A54D-60F7BF5B5AA6}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(160_000)]
+    [NonParallelizable]
+    public async Task Profiler_Lists_Values_Kept_Alive_By_Keys()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+
+        var table = new ConditionalWeakTable<WeakTableKey, WeakTableValue>();
+        WeakTableKey[] keys = Enumerable.Range(0, 100).Select(_ => new WeakTableKey()).ToArray();
+        foreach (WeakTableKey key in keys)
+        {
+            table.Add(key, new WeakTableValue());
+        }
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "conditional_weak_tables.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        string content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        GC.KeepAlive(keys);
+        GC.KeepAlive(table);
+
+        content.Should().Contain("DrDotnet.Tests.Profilers.WeakTableKey", "Values are grouped by key type");
+        content.Should().Contain("DrDotnet.Tests.Profilers.WeakTableValue", "Values are kept alive by their keys");
+    }
+}
@@ -0,0 +1,61 @@
+using NUnit.Framework;
+using System;
+using System.Collections.Generic;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class DuplicatedArraysProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{491AC509-56EE-4368-9EB0-B6880376A07D}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(160_000)]
+    [NonParallelizable]
+    public async Task Profiler_Lists_Duplicated_Array_Contents()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+
+        byte[] payload = Enumerable.Range(0, 1024).Select(i => (byte)i).ToArray();
+        byte[][] copies = Enumerable.Range(0, 100).Select(_ => payload.ToArray()).ToArray();
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "duplicated_arrays.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        string content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        GC.KeepAlive(copies);
+
+        content.Should().Contain("System.Byte[]", "The payload has been copied");
+        content.Should().Contain("00 01 02 03", "The content of the copies is previewed");
+    }
+}
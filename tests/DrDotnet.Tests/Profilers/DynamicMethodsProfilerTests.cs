@@ -0,0 +1,73 @@
+using NUnit.Framework;
+using System;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Linq.Expressions;
+using System.Threading;
+using System.Threading.Tasks;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class DynamicMethodsProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{56140F98-27EA-47DD-82B6-04B3ABEDC947}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(60_000)]
+    [NonParallelizable]
+    public async Task Profiler_Lists_Compiled_Expressions()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+        profiler.SetParameter("duration_seconds", 5);
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        // Compiling the same expression over and over creates a new dynamic method each time
+        using var cts = new CancellationTokenSource();
+        var compiling = Task.Run(() =>
+        {
+            while (!cts.IsCancellationRequested)
+            {
+                ParameterExpression x = Expression.Parameter(typeof(int), "x");
+                var square = Expression.Lambda<Func<int, int>>(Expression.Multiply(x, x), x).Compile();
+                square(42);
+                Thread.Sleep(50);
+            }
+        });
+
+        await session.AwaitUntilCompletion();
+
+        cts.Cancel();
+        await compiling;
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "dynamic_methods.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        var content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        content.Should().Contain("Compiled Expression", "The test compiles expressions during the session");
+    }
+}
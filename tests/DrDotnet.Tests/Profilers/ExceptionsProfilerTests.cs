@@ -64,6 +64,16 @@ public class ExceptionsProfilerTests : ProfilerTests
         
         content.Should().Contain("DrDotnet.Tests.Profilers.TestException:");
         content.Should().NotContain("DrDotnet.Tests.Profilers.TestException: 0");
+
+        var throwSites = session.EnumerateReports().Where(x => x.Name == "exceptions.html").FirstOrDefault();
+
+        Assert.NotNull(throwSites, "No throw sites report have been created!");
+
+        var throwSitesContent = await File.ReadAllTextAsync(throwSites.FullName);
+        throwSitesContent.Should().Contain("DrDotnet.Tests.Profilers.TestException");
+        throwSitesContent.Should().Contain("Catch Sites Swallowing The Most Exceptions");
+        throwSitesContent.Should().Contain("Exception Timeline");
+        throwSitesContent.Should().Contain("Exception of type 'DrDotnet.Tests.Profilers.TestException' was thrown.");
     }
 }
 
@@ -0,0 +1,59 @@
+using NUnit.Framework;
+using System;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Tests.Simulations;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class FinalizationProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{8BFD2BD7-3377-4353-B202-8DF3D065BB13}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(60_000)]
+    [NonParallelizable]
+    public async Task Profiler_Flags_Undisposed_Finalizable_Types()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+        profiler.SetParameter("duration_seconds", 5);
+
+        using var simulation = new FinalizationSimulation(1_000);
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "finalization.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        var content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        content.Should().Contain("UndisposedResource", "The simulation never disposes its resources");
+        content.Should().Contain("Dispose() was probably not called", "UndisposedResource implements IDisposable");
+    }
+}
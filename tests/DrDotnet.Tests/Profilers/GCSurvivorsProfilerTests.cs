@@ -71,4 +71,44 @@ public class GCSurvivorsProfilerTests : ProfilerTests
         content.Should().Contain("1,000,001", "There should be a path with 1,000,001 objects held (array itself + each elements)");
         content.Should().Contain("1,000,000", "There should also be a path with 1,000,000 objects held (each element)");
     }
+
+    [Test, Explicit]
+    [Order(2)]
+    [Timeout(160_000)]
+    [NonParallelizable]
+    public async Task Profiler_Computes_Retained_Sizes_From_Dominator_Tree()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+        profiler.SetParameter("retained_references_threshold", 10);
+        profiler.SetParameter("retained_bytes_threshold", 1000);
+        profiler.SetParameter("max_depth", 3);
+        profiler.SetParameter("retained_sizes", true);
+
+        // The same array is referenced twice, but its memory must only be retained once by its holder
+        var survivorObjects = Enumerable.Range(0, 100_000).Select(_ => new SurvivorObject(1, 2, 3)).ToArray();
+        var holder = new[] { survivorObjects, survivorObjects };
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "summary.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        string content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        GC.KeepAlive(holder);
+
+        content.Should().Contain("Retained Size By Type", "Retained sizes were requested");
+        content.Should().Contain("Dominator Tree", "Retained sizes were requested");
+        content.Should().Contain("SurvivorObject[][]", "The holder array dominates the array of SurvivorObject objects");
+    }
 }
@@ -0,0 +1,67 @@
+using NUnit.Framework;
+using System;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class HeapCensusProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{7B84CEDE-F64A-4CFC-A1EC-D90C9073F532}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(160_000)]
+    [NonParallelizable]
+    public async Task Profiler_Counts_Live_Objects_Per_Type_And_Generation()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+        profiler.SetParameter("sort_by_size", false);
+
+        // Create 1000 SurvivorObject objects that will be placed in the GEN 2 heap
+        var survivorObjects = Enumerable.Range(0, 1_000_000).Select(_ => new SurvivorObject(1, 2, 3)).ToArray();
+
+        // Force two garbage collections to promote objects from GEN 0 to GEN 2
+        GC.Collect();
+        GC.Collect();
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "heap_census.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        string content = await File.ReadAllTextAsync(summary.FullName);
+        
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+        
+        // Check that the objects are in the GEN 2 heap
+        Assert.AreEqual(2, GC.GetGeneration(survivorObjects));
+        
+        content.Should().Contain("SurvivorObject[]", "The array of SurvivorObject objects is alive");
+        content.Should().Contain("Gen 2", "Live objects are broken down per generation");
+        content.Should().Contain("<td>1,000,000 / ", "There should be 1,000,000 live SurvivorObject objects");
+    }
+}
@@ -0,0 +1,61 @@
+using NUnit.Framework;
+using System;
+using System.Diagnostics;
+using System.IO;
+using System.Linq;
+using System.Threading.Tasks;
+using DrDotnet.Tests.Simulations;
+using DrDotnet.Utils;
+using FluentAssertions;
+using Microsoft.Extensions.Logging;
+using Microsoft.Extensions.Logging.Abstractions;
+
+namespace DrDotnet.Tests.Profilers;
+
+public class LargeObjectAllocationsProfilerTests : ProfilerTests
+{
+    protected override Guid ProfilerGuid => new Guid("{AC998988-2500-43C3-A1A9-532E68E0062F}");
+
+    [Test]
+    [Order(0)]
+    [Timeout(5_000)]
+    [NonParallelizable]
+    public void Profiler_Exists()
+    {
+        Assert.NotNull(GetProfiler());
+    }
+
+    [Test, Explicit]
+    [Order(1)]
+    [Timeout(60_000)]
+    [NonParallelizable]
+    public async Task Profiler_Lists_Large_And_Pinned_Allocations()
+    {
+        ILogger<ProcessDiscovery> logger = NullLogger<ProcessDiscovery>.Instance;
+        ProcessDiscovery processDiscovery = new ProcessDiscovery(logger);
+        ProfilerInfo profiler = GetProfiler();
+        profiler.SetParameter("duration_seconds", 5);
+
+        using var simulation = new LargeObjectAllocationSimulation(20);
+
+        Assert.True(processDiscovery.TryGetProcessInfoFromPid(Process.GetCurrentProcess().Id, out ProcessInfo? processInfo), "Could not find current process info");
+        SessionInfo session = ProfilingExtensions.StartProfilingSession(profiler, processInfo, logger);
+
+        await session.AwaitUntilCompletion();
+
+        var summary = session.EnumerateReports().FirstOrDefault(x => x.Name == "large_object_allocations.html");
+
+        Assert.NotNull(summary, "No summary have been created!");
+
+        var content = await File.ReadAllTextAsync(summary.FullName);
+
+#if DEBUG
+        Console.WriteLine(content);
+#endif
+
+        content.Should().Contain("Large Object Heap (LOH)", "The simulation allocates arrays above the LOH threshold");
+        content.Should().Contain("Pinned Object Heap (POH)", "The simulation allocates pinned arrays");
+        content.Should().Contain("System.Byte[]", "The simulation allocates byte arrays");
+        content.Should().Contain("LargeObjectAllocationSimulation.AllocateLargeArray", "Large arrays are allocated from the simulation");
+    }
+}
@@ -0,0 +1,45 @@
+﻿using System;
+using System.Threading;
+using System.Threading.Tasks;
+
+namespace DrDotnet.Tests.Simulations;
+
+public class FinalizationSimulation : IDisposable
+{
+    private volatile bool _disposed = false;
+
+    public FinalizationSimulation(int undisposedObjectsPerSecond)
+    {
+        _ = Task.Run(() =>
+        {
+            while (!_disposed)
+            {
+                for (int i = 0; i < undisposedObjectsPerSecond / 10; i++)
+                {
+                    // Dispose() is never called, so the object ends up in the finalizer queue
+                    _ = new UndisposedResource();
+                }
+
+                Thread.Sleep(100);
+                GC.Collect();
+            }
+        });
+    }
+
+    public void Dispose()
+    {
+        _disposed = true;
+    }
+
+    public class UndisposedResource : IDisposable
+    {
+        public void Dispose()
+        {
+            GC.SuppressFinalize(this);
+        }
+
+        ~UndisposedResource()
+        {
+        }
+    }
+}
@@ -0,0 +1,50 @@
+﻿using System;
+using System.Collections.Generic;
+using System.Runtime.CompilerServices;
+using System.Runtime.InteropServices;
+using System.Threading;
+using System.Threading.Tasks;
+
+namespace DrDotnet.Tests.Simulations;
+
+public class GCHandleLeakSimulation : IDisposable
+{
+    private readonly List<GCHandle> _handles = new();
+
+    private volatile bool _disposed = false;
+
+    public GCHandleLeakSimulation(int handlesPerSecond)
+    {
+        _ = Task.Run(() =>
+        {
+            while (!_disposed)
+            {
+                lock (_handles)
+                {
+                    _handles.Add(LeakHandle());
+                }
+                Thread.Sleep(1000 / handlesPerSecond);
+            }
+        });
+    }
+
+    [MethodImpl(MethodImplOptions.NoInlining)]
+    private GCHandle LeakHandle()
+    {
+        // Handles are only freed when the simulation is disposed, like leaked handles would never be
+        return GCHandle.Alloc(new byte[1024], GCHandleType.Pinned);
+    }
+
+    public void Dispose()
+    {
+        _disposed = true;
+        lock (_handles)
+        {
+            foreach (var handle in _handles)
+            {
+                handle.Free();
+            }
+            _handles.Clear();
+        }
+    }
+}
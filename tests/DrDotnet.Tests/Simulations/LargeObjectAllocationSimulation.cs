@@ -0,0 +1,44 @@
+﻿using System;
+using System.Runtime.CompilerServices;
+using System.Threading;
+using System.Threading.Tasks;
+
+namespace DrDotnet.Tests.Simulations;
+
+public class LargeObjectAllocationSimulation : IDisposable
+{
+    private volatile bool _disposed = false;
+
+    private byte[]? _lastLargeArray;
+    private byte[]? _lastPinnedArray;
+
+    public LargeObjectAllocationSimulation(int allocationsPerSecond)
+    {
+        _ = Task.Factory.StartNew(() =>
+        {
+            while (!_disposed)
+            {
+                AllocateLargeArray();
+                AllocatePinnedArray();
+                Thread.Sleep(1000 / allocationsPerSecond);
+            }
+        }, TaskCreationOptions.LongRunning);
+    }
+
+    [MethodImpl(MethodImplOptions.NoInlining)]
+    private void AllocateLargeArray()
+    {
+        _lastLargeArray = new byte[100_000 + Random.Shared.Next(1_000_000)];
+    }
+
+    [MethodImpl(MethodImplOptions.NoInlining)]
+    private void AllocatePinnedArray()
+    {
+        _lastPinnedArray = GC.AllocateArray<byte>(1_000, pinned: true);
+    }
+
+    public void Dispose()
+    {
+        _disposed = true;
+    }
+}
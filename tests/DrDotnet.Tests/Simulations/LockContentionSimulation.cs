@@ -0,0 +1,41 @@
+﻿using System;
+using System.Runtime.CompilerServices;
+using System.Threading;
+using System.Threading.Tasks;
+
+namespace DrDotnet.Tests.Simulations;
+
+public class LockContentionSimulation : IDisposable
+{
+    private readonly object _lock = new();
+
+    private volatile bool _disposed = false;
+
+    public LockContentionSimulation(int threads, int holdMilliseconds)
+    {
+        for (int i = 0; i < threads; i++)
+        {
+            _ = Task.Factory.StartNew(() =>
+            {
+                while (!_disposed)
+                {
+                    HoldLock(holdMilliseconds);
+                }
+            }, TaskCreationOptions.LongRunning);
+        }
+    }
+
+    [MethodImpl(MethodImplOptions.NoInlining)]
+    private void HoldLock(int holdMilliseconds)
+    {
+        lock (_lock)
+        {
+            Thread.Sleep(holdMilliseconds);
+        }
+    }
+
+    public void Dispose()
+    {
+        _disposed = true;
+    }
+}
@@ -0,0 +1,42 @@
+﻿using System;
+using System.Runtime.CompilerServices;
+using System.Threading;
+using System.Threading.Tasks;
+
+namespace DrDotnet.Tests.Simulations;
+
+public class ThreadCreationSimulation : IDisposable
+{
+    private readonly ManualResetEventSlim _stop = new();
+
+    private volatile bool _disposed = false;
+
+    public ThreadCreationSimulation(int threadsPerSecond)
+    {
+        _ = Task.Run(() =>
+        {
+            int i = 0;
+            while (!_disposed)
+            {
+                StartThread(i++);
+                Thread.Sleep(1000 / threadsPerSecond);
+            }
+        });
+    }
+
+    [MethodImpl(MethodImplOptions.NoInlining)]
+    private void StartThread(int index)
+    {
+        // Threads are kept alive until the simulation is disposed, like leaked threads would be
+        var thread = new Thread(() => _stop.Wait());
+        thread.Name = $"Simulation thread {index}";
+        thread.IsBackground = true;
+        thread.Start();
+    }
+
+    public void Dispose()
+    {
+        _disposed = true;
+        _stop.Set();
+    }
+}